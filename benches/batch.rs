@@ -0,0 +1,77 @@
+//! Batch verification of settlement files of realistic sizes.
+//!
+//! Run with `cargo bench --bench batch`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+
+use rustpay::bank::{BatchVerifier, VerificationPolicy};
+use rustpay::crypto::{KeyCache, PublicKey};
+use rustpay::merchant::Merchant;
+use rustpay::traits::TransactionSign;
+use rustpay::transaction::{Currency, SignedTransaction, Transaction};
+use rustpay::user::User;
+
+// a day's file usually holds many payments from comparatively few customers
+const USERS: usize = 200;
+
+fn settlement_file(size: usize) -> Vec<SignedTransaction> {
+    let mut rng = ChaCha20Rng::seed_from_u64(42);
+    let account = "DE89370400440532013000".parse().expect("account number is valid");
+    let users: Vec<_> = (0..USERS).map(|_| User::from_rng(account, &mut rng)).collect();
+    let merchant = Merchant::from_rng("GB82WEST12345698765432".to_string(), &mut rng);
+
+    (0..size)
+        .map(|i| {
+            let user = &users[i % USERS];
+            let transaction = Transaction::new(1_000 + i as u64, Currency::EUR, merchant.clone(), user.clone(), i as u64);
+            user.sign(transaction).expect("user holds its key")
+        })
+        .collect()
+}
+
+fn verify(c: &mut Criterion) {
+    let mut group = c.benchmark_group("verify");
+    group.sample_size(10);
+
+    for size in [1_000, 10_000, 100_000] {
+        let file = settlement_file(size);
+        let verifier = BatchVerifier::new(VerificationPolicy::default());
+        group.throughput(Throughput::Elements(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("sequential", size), &file, |b, file| {
+            b.iter(|| file.iter().map(|signed| verifier.policy().verify(signed)).collect::<Vec<_>>())
+        });
+        group.bench_with_input(BenchmarkId::new("parallel", size), &file, |b, file| b.iter(|| verifier.verify(file)));
+    }
+
+    group.finish();
+}
+
+// decoding the keys of a file as it is read, through the same cache the
+// batch verifier keeps its signing keys in
+fn decode_keys(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_keys");
+    let encoded: Vec<_> = settlement_file(10_000)
+        .iter()
+        .map(|signed| signed.transaction().user().verifying_key().to_bytes())
+        .collect();
+    group.throughput(Throughput::Elements(encoded.len() as u64));
+
+    group.bench_function("uncached", |b| {
+        b.iter(|| encoded.iter().map(|bytes| PublicKey::from_bytes(bytes)).collect::<Vec<_>>())
+    });
+    group.bench_function("cached", |b| {
+        b.iter_batched(
+            KeyCache::new,
+            |cache| encoded.iter().map(|bytes| cache.decode(bytes)).collect::<Vec<_>>(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, verify, decode_keys);
+criterion_main!(benches);
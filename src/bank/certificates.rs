@@ -0,0 +1,383 @@
+use sha2::{Digest, Sha256};
+
+use super::{StatusCache, VerificationPolicy};
+use crate::crypto::{KeyId, PublicKey, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_str, put_u64};
+use crate::transaction::SignedTransaction;
+use crate::user::User;
+use crate::{Error, Timestamp, IBAN};
+
+/// Identifies an account certificate by the SHA-256 hash of its signed contents.
+pub type CertificateId = [u8; 32];
+
+/// The longest holder name a certificate may carry, as in SEPA messages.
+pub const MAX_HOLDER_NAME_LEN: usize = 70;
+
+/// Binds a user's key to the account it may debit and the account holder's
+/// name, issued by a bank key for a limited time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountCertificate {
+    key_id: KeyId,
+    account_number: IBAN,
+    holder: String,
+    valid_from: Timestamp,
+    valid_until: Timestamp,
+    issuer: KeyId,
+    signature: Signature,
+}
+
+/// Allows a bank key to issue certificates, signed by the root key or by
+/// another key certified this way.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssuerCertificate {
+    key: PublicKey,
+    valid_from: Timestamp,
+    valid_until: Timestamp,
+    issuer: KeyId,
+    signature: Signature,
+}
+
+/// Checks certificates against the root key of a bank.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertificateVerifier {
+    root: PublicKey,
+    policy: VerificationPolicy,
+}
+
+impl AccountCertificate {
+    pub(crate) fn new(
+        key_id: KeyId,
+        account_number: IBAN,
+        holder: String,
+        valid_from: Timestamp,
+        valid_until: Timestamp,
+        issuer: KeyId,
+        signature: Signature,
+    ) -> Self {
+        Self { key_id, account_number, holder, valid_from, valid_until, issuer, signature }
+    }
+
+    pub(crate) fn signed_bytes(
+        key_id: &KeyId,
+        account_number: &IBAN,
+        holder: &str,
+        valid_from: Timestamp,
+        valid_until: Timestamp,
+        issuer: &KeyId,
+    ) -> Vec<u8> {
+        let mut buf = b"account-certificate".to_vec();
+        put_bytes(&mut buf, key_id);
+        put_iban(&mut buf, account_number);
+        put_str(&mut buf, holder);
+        put_u64(&mut buf, valid_from);
+        put_u64(&mut buf, valid_until);
+        put_bytes(&mut buf, issuer);
+        buf
+    }
+
+    /// Checks that a holder name is not blank and fits into a certificate.
+    pub(crate) fn check_holder(holder: &str) -> Result<(), Error> {
+        if holder.trim().is_empty() || holder.chars().count() > MAX_HOLDER_NAME_LEN || holder.chars().any(char::is_control) {
+            return Err(Error::InvalidHolderName);
+        }
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> CertificateId {
+        Sha256::digest(self.to_signed_bytes()).into()
+    }
+
+    pub fn key_id(&self) -> &KeyId {
+        &self.key_id
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    pub fn valid_from(&self) -> Timestamp {
+        self.valid_from
+    }
+
+    /// Returns the first point in time the certificate is no longer valid.
+    pub fn valid_until(&self) -> Timestamp {
+        self.valid_until
+    }
+
+    /// Returns the id of the key that issued the certificate.
+    pub fn issuer(&self) -> &KeyId {
+        &self.issuer
+    }
+
+    /// Checks the signature of the certificate against the key that issued it.
+    pub fn verify(&self, issuer: &PublicKey) -> Result<(), Error> {
+        if issuer.id() != self.issuer {
+            return Err(Error::CertificateChainBroken);
+        }
+
+        issuer.verify(&self.to_signed_bytes(), &self.signature)
+    }
+
+    /// Checks that the certificate is valid at `at`.
+    pub fn check_period(&self, at: Timestamp) -> Result<(), Error> {
+        check_period(self.valid_from, self.valid_until, at)
+    }
+
+    fn to_signed_bytes(&self) -> Vec<u8> {
+        Self::signed_bytes(&self.key_id, &self.account_number, &self.holder, self.valid_from, self.valid_until, &self.issuer)
+    }
+
+    /// Checks that the certificate is about `user`.
+    pub fn check_user(&self, user: &User) -> Result<(), Error> {
+        if self.key_id != user.verifying_key().id() || self.account_number != *user.account_number() {
+            return Err(Error::CertificateMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+impl IssuerCertificate {
+    pub(crate) fn new(key: PublicKey, valid_from: Timestamp, valid_until: Timestamp, issuer: KeyId, signature: Signature) -> Self {
+        Self { key, valid_from, valid_until, issuer, signature }
+    }
+
+    pub(crate) fn signed_bytes(key: &PublicKey, valid_from: Timestamp, valid_until: Timestamp, issuer: &KeyId) -> Vec<u8> {
+        let mut buf = b"issuer-certificate".to_vec();
+        put_key(&mut buf, key);
+        put_u64(&mut buf, valid_from);
+        put_u64(&mut buf, valid_until);
+        put_bytes(&mut buf, issuer);
+        buf
+    }
+
+    pub fn id(&self) -> CertificateId {
+        Sha256::digest(Self::signed_bytes(&self.key, self.valid_from, self.valid_until, &self.issuer)).into()
+    }
+
+    /// Returns the key allowed to issue certificates.
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    pub fn valid_from(&self) -> Timestamp {
+        self.valid_from
+    }
+
+    /// Returns the first point in time the certificate is no longer valid.
+    pub fn valid_until(&self) -> Timestamp {
+        self.valid_until
+    }
+
+    /// Returns the id of the key that issued the certificate.
+    pub fn issuer(&self) -> &KeyId {
+        &self.issuer
+    }
+
+    /// Checks the signature of the certificate against the key that issued it.
+    pub fn verify(&self, issuer: &PublicKey) -> Result<(), Error> {
+        if issuer.id() != self.issuer {
+            return Err(Error::CertificateChainBroken);
+        }
+
+        let message = Self::signed_bytes(&self.key, self.valid_from, self.valid_until, &self.issuer);
+        issuer.verify(&message, &self.signature)
+    }
+
+    /// Checks that the certificate is valid at `at`.
+    pub fn check_period(&self, at: Timestamp) -> Result<(), Error> {
+        check_period(self.valid_from, self.valid_until, at)
+    }
+}
+
+impl CertificateVerifier {
+    pub fn new(root: PublicKey) -> Self {
+        Self { root, policy: VerificationPolicy::default() }
+    }
+
+    /// Only verifies transactions signed with algorithms `policy` accepts.
+    pub fn with_policy(mut self, policy: VerificationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn root(&self) -> &PublicKey {
+        &self.root
+    }
+
+    /// Checks that `certificate` binds the key of `user` to its account at
+    /// `at`. `chain` leads from the root key down to the key that issued
+    /// `certificate` and is empty if the root key issued it directly.
+    ///
+    /// Every certificate along the way has to be valid at `at`.
+    pub fn verify(&self, user: &User, chain: &[IssuerCertificate], certificate: &AccountCertificate, at: Timestamp) -> Result<(), Error> {
+        let mut issuer = &self.root;
+
+        for link in chain {
+            link.verify(issuer)?;
+            link.check_period(at)?;
+            issuer = link.key();
+        }
+
+        certificate.verify(issuer)?;
+        certificate.check_period(at)?;
+        certificate.check_user(user)
+    }
+
+    /// Verifies a signed transaction and that its user's key was certified
+    /// for the account it debits at `at`, when the verifier received it.
+    ///
+    /// The signer picks the transaction's own timestamp, so it says nothing
+    /// about whether the certificate was still valid.
+    pub fn verify_transaction(
+        &self,
+        signed: &SignedTransaction,
+        chain: &[IssuerCertificate],
+        certificate: &AccountCertificate,
+        at: Timestamp,
+    ) -> Result<(), Error> {
+        self.policy.verify(signed)?;
+        self.verify(signed.transaction().user(), chain, certificate, at)
+    }
+
+    /// Like [`CertificateVerifier::verify`], also checking with the status
+    /// information in `status` as of `now` that neither `certificate` nor
+    /// any certificate in `chain` was revoked at `at`.
+    pub fn verify_status(
+        &self,
+        user: &User,
+        chain: &[IssuerCertificate],
+        certificate: &AccountCertificate,
+        status: &StatusCache,
+        at: Timestamp,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        self.verify(user, chain, certificate, at)?;
+        for link in chain {
+            status.check_issuer(link, at, now)?;
+        }
+
+        status.check(certificate, at, now)
+    }
+
+    /// Like [`CertificateVerifier::verify_transaction`], also checking with
+    /// the status information in `status` as of `now` that no certificate
+    /// involved was revoked at `at`, when the verifier received the
+    /// transaction.
+    pub fn verify_transaction_status(
+        &self,
+        signed: &SignedTransaction,
+        chain: &[IssuerCertificate],
+        certificate: &AccountCertificate,
+        status: &StatusCache,
+        at: Timestamp,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        self.policy.verify(signed)?;
+        self.verify_status(signed.transaction().user(), chain, certificate, status, at, now)
+    }
+}
+
+fn check_period(valid_from: Timestamp, valid_until: Timestamp, at: Timestamp) -> Result<(), Error> {
+    if at < valid_from {
+        return Err(Error::CertificateNotValid);
+    }
+    if at >= valid_until {
+        return Err(Error::CertificateExpired);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::BankKey;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+
+    const YEAR: Timestamp = 365 * 24 * 60 * 60;
+
+    #[test]
+    fn issued_by_root() -> Result<(), Error> {
+        let root = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let certificate = root.certify_account(&user, "Erika Mustermann", 0, YEAR)?;
+        let verifier = CertificateVerifier::new(*root.verifying_key());
+
+        verifier.verify(&user, &[], &certificate, 0)?;
+        assert_eq!(certificate.holder(), "Erika Mustermann");
+        assert_eq!(verifier.verify(&user, &[], &certificate, YEAR), Err(Error::CertificateExpired));
+
+        let other = User::new("DE89370400440532013000".parse()?);
+        assert_eq!(verifier.verify(&other, &[], &certificate, 0), Err(Error::CertificateMismatch));
+        let moved = User::from_verifying_key("GB82WEST12345698765432".parse()?, *user.verifying_key());
+        assert_eq!(verifier.verify(&moved, &[], &certificate, 0), Err(Error::CertificateMismatch));
+
+        Ok(())
+    }
+
+    #[test]
+    fn chain() -> Result<(), Error> {
+        let root = BankKey::new();
+        let branch = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let chain = [root.certify_issuer(branch.verifying_key(), 0, 2 * YEAR)?];
+        let certificate = branch.certify_account(&user, "Erika Mustermann", YEAR, 3 * YEAR)?;
+        let verifier = CertificateVerifier::new(*root.verifying_key());
+
+        verifier.verify(&user, &chain, &certificate, YEAR)?;
+        // the branch key expires before the account certificate does
+        assert_eq!(verifier.verify(&user, &chain, &certificate, 2 * YEAR), Err(Error::CertificateExpired));
+        assert_eq!(verifier.verify(&user, &chain, &certificate, 0), Err(Error::CertificateNotValid));
+        // the branch key is not the root
+        assert_eq!(verifier.verify(&user, &[], &certificate, YEAR), Err(Error::CertificateChainBroken));
+
+        // a key certifying itself gets nowhere
+        let rogue = BankKey::new();
+        let forged = [rogue.certify_issuer(rogue.verifying_key(), 0, 2 * YEAR)?];
+        let certificate = rogue.certify_account(&user, "Erika Mustermann", 0, YEAR)?;
+        assert_eq!(verifier.verify(&user, &forged, &certificate, 0), Err(Error::CertificateChainBroken));
+
+        Ok(())
+    }
+
+    #[test]
+    fn transaction() -> Result<(), Error> {
+        let root = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let certificate = root.certify_account(&user, "Erika Mustermann", 100, 200)?;
+        let verifier = CertificateVerifier::new(*root.verifying_key());
+
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 150))?;
+        verifier.verify_transaction(&signed, &[], &certificate, 150)?;
+        // backdating doesn't get a transaction past an expired certificate
+        assert_eq!(verifier.verify_transaction(&signed, &[], &certificate, 200), Err(Error::CertificateExpired));
+
+        let late = user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 200))?;
+        assert_eq!(verifier.verify_transaction(&late, &[], &certificate, 200), Err(Error::CertificateExpired));
+
+        Ok(())
+    }
+
+    #[test]
+    fn holder_names() -> Result<(), Error> {
+        let root = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+
+        for holder in ["", "   ", "Erika\nMustermann", &"x".repeat(MAX_HOLDER_NAME_LEN + 1)] {
+            assert_eq!(root.certify_account(&user, holder, 0, YEAR), Err(Error::InvalidHolderName));
+        }
+        root.certify_account(&user, &"ö".repeat(MAX_HOLDER_NAME_LEN), 0, YEAR)?;
+        assert_eq!(root.to_public().certify_account(&user, "Erika Mustermann", 0, YEAR), Err(Error::NoPrivateKey));
+
+        Ok(())
+    }
+}
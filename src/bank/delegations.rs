@@ -0,0 +1,127 @@
+use std::collections::{HashMap, HashSet};
+
+use super::Ledger;
+use crate::transaction::{SignedTransaction, TransactionId};
+use crate::user::DelegationId;
+use crate::{Error, Timestamp};
+
+/// Applies transactions signed through delegation chains, keeping track of
+/// how much of each allowance has been spent.
+#[derive(Debug, Default)]
+pub struct DelegationBook {
+    spent: HashMap<DelegationId, u64>,
+    applied: HashSet<TransactionId>,
+}
+
+impl DelegationBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how much has been spent under the delegation with id `id`.
+    pub fn spent(&self, id: &DelegationId) -> u64 {
+        self.spent.get(id).copied().unwrap_or(0)
+    }
+
+    /// Verifies a delegated transaction and applies it to the ledger.
+    ///
+    /// The chain has to start at the key of the paying account at `now`,
+    /// the time the bank received the transaction, no delegation in it may
+    /// have expired by then, and the transaction has to fit into what is
+    /// left of every allowance. Each transaction is applied once.
+    pub fn apply(&mut self, ledger: &mut Ledger, signed: &SignedTransaction, now: Timestamp) -> Result<(), Error> {
+        ledger.policy().verify(signed)?;
+        if signed.delegation().is_empty() {
+            return Err(Error::DelegationChainBroken);
+        }
+        let owner = signed.transaction().user();
+        ledger.authorize(owner.account_number(), owner.verifying_key(), now)?;
+
+        let id = signed.id();
+        if self.applied.contains(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let amount = signed.transaction().amount();
+        let totals = signed.delegation().iter()
+            .map(|certificate| {
+                let allowance = certificate.allowance();
+                if now >= allowance.expires_at() {
+                    return Err(Error::DelegationExpired);
+                }
+
+                let total = self.spent(&certificate.id()).checked_add(amount)
+                    .filter(|total| *total <= allowance.max_amount())
+                    .ok_or(Error::DelegationExhausted)?;
+                Ok((certificate.id(), total))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        ledger.book(signed.transaction())?;
+
+        self.spent.extend(totals);
+        self.applied.insert(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iban::parse_valid;
+    use crate::merchant::Merchant;
+    use crate::transaction::{Currency, Transaction};
+    use crate::user::{Allowance, User};
+
+    #[test]
+    fn cumulative_allowance() -> Result<(), Error> {
+        let owner = User::new("DE89370400440532013000".parse()?);
+        let device = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let certificate = owner.delegate(device.verifying_key(), Allowance::new(2_000, Currency::EUR, 1_000))?;
+        let pay = |amount, at| {
+            device.sign_delegated(Transaction::new(amount, Currency::EUR, merchant.clone(), owner.clone(), at), vec![certificate.clone()])
+        };
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&owner, 0, None)?;
+        ledger.deposit(owner.account_number(), Currency::EUR, 10_000)?;
+        let mut book = DelegationBook::new();
+
+        let first = pay(1_500, 0)?;
+        book.apply(&mut ledger, &first, 10)?;
+        assert_eq!(book.apply(&mut ledger, &first, 10), Err(Error::DuplicateTransaction));
+        // each transaction is within the allowance, but not both together
+        assert_eq!(book.apply(&mut ledger, &pay(1_000, 1)?, 10), Err(Error::DelegationExhausted));
+        book.apply(&mut ledger, &pay(500, 1)?, 10)?;
+        assert_eq!(book.spent(&certificate.id()), 2_000);
+        assert_eq!(ledger.balance(&parse_valid(merchant.account_number())?, Currency::EUR), 2_000);
+
+        // a delegated transaction can't bypass the book
+        assert_eq!(ledger.apply(&first, 10), Err(Error::UntrackedDelegation));
+
+        // the delegation ends with the key that granted it
+        let (_, rotation) = owner.rotate_key(20)?;
+        ledger.keys_mut().rotate(&rotation)?;
+        assert_eq!(book.apply(&mut ledger, &pay(100, 21)?, 21), Err(Error::KeyNotValid));
+        Ok(())
+    }
+
+    #[test]
+    fn expiry_at_bank_time() -> Result<(), Error> {
+        let owner = User::new("DE89370400440532013000".parse()?);
+        let device = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let certificate = owner.delegate(device.verifying_key(), Allowance::new(2_000, Currency::EUR, 1_000))?;
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&owner, 0, None)?;
+        ledger.deposit(owner.account_number(), Currency::EUR, 10_000)?;
+
+        // backdated by the delegate after the delegation expired
+        let backdated = device.sign_delegated(Transaction::new(500, Currency::EUR, merchant, owner.clone(), 0), vec![certificate])?;
+        assert_eq!(DelegationBook::new().apply(&mut ledger, &backdated, 1_000), Err(Error::DelegationExpired));
+        assert_eq!(ledger.balance(owner.account_number(), Currency::EUR), 10_000);
+        Ok(())
+    }
+}
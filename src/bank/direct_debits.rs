@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+
+use super::{Ledger, MandateRegistry};
+use crate::sepa::{
+    DebitId, DirectDebit, PreNotification, RefundClaim, SequenceType, SignedDirectDebit, DEFAULT_NOTICE_PERIOD, REFUND_WINDOW,
+};
+use crate::transaction::{Currency, MandateId};
+use crate::{Error, Timestamp};
+
+/// A direct debit that has been collected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedDebit {
+    debit: SignedDirectDebit,
+    collected_at: Timestamp,
+    refunded: bool,
+}
+
+impl CollectedDebit {
+    pub fn debit(&self) -> &SignedDirectDebit {
+        &self.debit
+    }
+
+    pub fn collected_at(&self) -> Timestamp {
+        self.collected_at
+    }
+
+    pub fn is_refunded(&self) -> bool {
+        self.refunded
+    }
+
+    /// Returns the last point in time at which the debtor may claim a refund.
+    pub fn refundable_until(&self) -> Timestamp {
+        self.collected_at.saturating_add(REFUND_WINDOW)
+    }
+}
+
+/// Collects direct debits into a ledger and handles refund claims.
+#[derive(Debug)]
+pub struct DirectDebitBook {
+    notice_period: Timestamp,
+    // when the bank passed on the pre-notification of each debit
+    notified: HashMap<DebitId, Timestamp>,
+    collected: HashMap<DebitId, CollectedDebit>,
+    // last sequence type per mandate the debtor signed
+    sequences: HashMap<MandateId, SequenceType>,
+}
+
+impl Default for DirectDebitBook {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DirectDebitBook {
+    pub fn new() -> Self {
+        Self::with_notice_period(DEFAULT_NOTICE_PERIOD)
+    }
+
+    /// Creates a book that requires debtors to be notified `notice_period`
+    /// before a debit is due.
+    pub fn with_notice_period(notice_period: Timestamp) -> Self {
+        Self { notice_period, notified: HashMap::new(), collected: HashMap::new(), sequences: HashMap::new() }
+    }
+
+    pub fn get(&self, id: &DebitId) -> Option<&CollectedDebit> {
+        self.collected.get(id)
+    }
+
+    /// Passes the pre-notification of a debit on to its debtor at `now`,
+    /// which has to be at least the notice period before the debit is due.
+    ///
+    /// The creditor chooses the time the notification claims to be sent at,
+    /// so only the time the bank passed it on counts.
+    pub fn notify(&mut self, debit: &DirectDebit, notification: &PreNotification, now: Timestamp) -> Result<(), Error> {
+        notification.verify(debit, self.notice_period)?;
+        if now.saturating_add(self.notice_period) > debit.due_date() {
+            return Err(Error::PreNotificationTooLate);
+        }
+
+        self.notified.entry(debit.id()).or_insert(now);
+        Ok(())
+    }
+
+    /// Collects a debit due at or before `now`, moving its amount from the
+    /// debtor's to the creditor's account.
+    ///
+    /// The debit has to be covered by a mandate registered with `mandates`,
+    /// which its allowance is charged against, and its debtor has to have
+    /// been notified through [`DirectDebitBook::notify`].
+    pub fn collect(
+        &mut self,
+        ledger: &mut Ledger,
+        mandates: &mut MandateRegistry,
+        signed: &SignedDirectDebit,
+        now: Timestamp,
+    ) -> Result<DebitId, Error> {
+        signed.verify()?;
+
+        let debit = signed.debit();
+        let id = debit.id();
+        if self.collected.contains_key(&id) {
+            return Err(Error::DuplicateDebit);
+        }
+
+        if !self.notified.contains_key(&id) {
+            return Err(Error::DebitNotNotified);
+        }
+
+        if now < debit.due_date() {
+            return Err(Error::DebitNotDue);
+        }
+
+        if !debit.sequence().may_follow(self.sequences.get(debit.mandate_id()).copied()) {
+            return Err(Error::InvalidSequence);
+        }
+
+        let charge = mandates.check_debit(debit, now)?;
+        ledger.check_single_signer(debit.debtor_iban())?;
+        ledger.transfer(debit.debtor_iban(), debit.creditor_iban(), Currency::EUR, debit.amount())?;
+
+        mandates.book(charge);
+        self.sequences.insert(*debit.mandate_id(), debit.sequence());
+        self.collected.insert(id, CollectedDebit { debit: signed.clone(), collected_at: now, refunded: false });
+        Ok(id)
+    }
+
+    /// Refunds a collected debit to its debtor. No reason is needed as long
+    /// as the claim is made within the refund window.
+    pub fn refund(&mut self, ledger: &mut Ledger, claim: &RefundClaim, now: Timestamp) -> Result<(), Error> {
+        let collected = self.collected.get_mut(claim.debit_id()).ok_or(Error::UnknownDebit)?;
+        let debit = collected.debit.debit();
+        claim.verify(debit)?;
+
+        if collected.refunded {
+            return Err(Error::AlreadyRefunded);
+        }
+        if now > collected.refundable_until() {
+            return Err(Error::RefundWindowClosed);
+        }
+
+        ledger.transfer(debit.creditor_iban(), debit.debtor_iban(), Currency::EUR, debit.amount())?;
+        collected.refunded = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::sepa::CreditorId;
+    use crate::time::DAY;
+    use crate::transaction::{Mandate, Period, SignedMandate};
+    use crate::user::User;
+
+    // 2024-03-01T00:00:00Z
+    const DUE: Timestamp = 1_709_251_200;
+
+    struct Setup {
+        creditor: Merchant,
+        debtor: User,
+        mandate: SignedMandate,
+        ledger: Ledger,
+        mandates: MandateRegistry,
+        book: DirectDebitBook,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let creditor = Merchant::new("GB82WEST12345698765432".to_string());
+        let debtor = User::new("DE89370400440532013000".parse()?);
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&debtor, 0, None)?;
+        ledger.deposit(debtor.account_number(), Currency::EUR, 100_000)?;
+
+        let terms = Mandate::new(debtor.clone(), creditor.clone(), Currency::EUR, 75_000, Period::Monthly, DUE - 30 * DAY, DUE + 365 * DAY)?
+            .for_direct_debit(CreditorId::try_from("DE98ZZZ09999999999")?, "MANDATE-0001".to_string())?;
+        let mandate = debtor.sign_mandate(terms)?;
+        let mut mandates = MandateRegistry::new();
+        mandates.register(&ledger, mandate.clone(), DUE - 30 * DAY)?;
+
+        Ok(Setup { creditor, debtor, mandate, ledger, mandates, book: DirectDebitBook::new() })
+    }
+
+    fn debit(setup: &mut Setup, sequence: SequenceType, due_date: Timestamp) -> Result<SignedDirectDebit, Error> {
+        debit_with(setup, sequence, 25_000, due_date)
+    }
+
+    // creates a debit and passes on its notification in time
+    fn debit_with(setup: &mut Setup, sequence: SequenceType, amount: u64, due_date: Timestamp) -> Result<SignedDirectDebit, Error> {
+        let debit = DirectDebit::new(setup.creditor.clone(), &setup.mandate, amount, sequence, due_date)?;
+        let notification = setup.creditor.pre_notify(&debit, due_date - 14 * DAY)?;
+        setup.book.notify(&debit, &notification, due_date - 14 * DAY)?;
+
+        setup.creditor.sign_direct_debit(debit)
+    }
+
+    fn collect(setup: &mut Setup, signed: &SignedDirectDebit, now: Timestamp) -> Result<DebitId, Error> {
+        setup.book.collect(&mut setup.ledger, &mut setup.mandates, signed, now)
+    }
+
+    #[test]
+    fn collect_and_refund() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let signed = debit(&mut setup, SequenceType::OneOff, DUE)?;
+        let debtor_iban = *signed.debit().debtor_iban();
+        let creditor_iban = *signed.debit().creditor_iban();
+
+        collect(&mut setup, &signed, DUE)?;
+        assert_eq!(setup.ledger.balance(&debtor_iban, Currency::EUR), 75_000);
+        assert_eq!(setup.ledger.balance(&creditor_iban, Currency::EUR), 25_000);
+        assert_eq!(setup.mandates.remaining(&setup.mandate.id(), DUE)?, 50_000);
+
+        let claim = setup.debtor.claim_refund(&signed)?;
+        setup.book.refund(&mut setup.ledger, &claim, DUE + REFUND_WINDOW)?;
+        assert_eq!(setup.ledger.balance(&debtor_iban, Currency::EUR), 100_000);
+        assert_eq!(setup.ledger.balance(&creditor_iban, Currency::EUR), 0);
+
+        assert_eq!(setup.book.refund(&mut setup.ledger, &claim, DUE + REFUND_WINDOW), Err(Error::AlreadyRefunded));
+        Ok(())
+    }
+
+    #[test]
+    fn refund_window_closed() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let signed = debit(&mut setup, SequenceType::OneOff, DUE)?;
+        collect(&mut setup, &signed, DUE)?;
+
+        let claim = setup.debtor.claim_refund(&signed)?;
+        assert_eq!(setup.book.refund(&mut setup.ledger, &claim, DUE + REFUND_WINDOW + 1), Err(Error::RefundWindowClosed));
+        Ok(())
+    }
+
+    #[test]
+    fn not_due() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let signed = debit(&mut setup, SequenceType::OneOff, DUE)?;
+
+        assert_eq!(collect(&mut setup, &signed, DUE - 1), Err(Error::DebitNotDue));
+        Ok(())
+    }
+
+    #[test]
+    fn late_pre_notification() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let signed = debit(&mut setup, SequenceType::OneOff, DUE)?;
+
+        let debit = DirectDebit::new(setup.creditor.clone(), &setup.mandate, 10_000, SequenceType::OneOff, DUE)?;
+        let late = setup.creditor.pre_notify(&debit, DUE - 13 * DAY)?;
+        assert_eq!(setup.book.notify(&debit, &late, DUE - 14 * DAY), Err(Error::PreNotificationTooLate));
+
+        // claiming to have sent the notification in time does not help if the bank passed it on late
+        let backdated = setup.creditor.pre_notify(&debit, DUE - 14 * DAY)?;
+        assert_eq!(setup.book.notify(&debit, &backdated, DUE - 13 * DAY), Err(Error::PreNotificationTooLate));
+
+        let unnotified = setup.creditor.sign_direct_debit(debit)?;
+        assert_eq!(collect(&mut setup, &unnotified, DUE), Err(Error::DebitNotNotified));
+        collect(&mut setup, &signed, DUE)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sequence() -> Result<(), Error> {
+        let mut setup = setup()?;
+
+        let recurring = debit(&mut setup, SequenceType::Recurring, DUE)?;
+        assert_eq!(collect(&mut setup, &recurring, DUE), Err(Error::InvalidSequence));
+
+        let first = debit(&mut setup, SequenceType::First, DUE)?;
+        collect(&mut setup, &first, DUE)?;
+        collect(&mut setup, &recurring, DUE)?;
+
+        let last = debit(&mut setup, SequenceType::Final, DUE + DAY)?;
+        collect(&mut setup, &last, DUE + DAY)?;
+
+        let after = debit(&mut setup, SequenceType::Recurring, DUE + 2 * DAY)?;
+        assert_eq!(collect(&mut setup, &after, DUE + 2 * DAY), Err(Error::InvalidSequence));
+
+        // the series belongs to the mandate the debtor signed, which also
+        // fixes its reference, so the creditor can't start it over
+        let restart = debit(&mut setup, SequenceType::First, DUE + 2 * DAY)?;
+        assert_eq!(collect(&mut setup, &restart, DUE + 2 * DAY), Err(Error::InvalidSequence));
+
+        Ok(())
+    }
+
+    #[test]
+    fn requires_mandate() -> Result<(), Error> {
+        let mut setup = setup()?;
+
+        // a creditor can not debit under a mandate given to someone else
+        let other = Merchant::new("FR1420041010050500013M02606".to_string());
+        let debit = DirectDebit::new(other, &setup.mandate, 25_000, SequenceType::OneOff, DUE);
+        assert_eq!(debit, Err(Error::MandateMismatch));
+
+        // nor under one that does not allow direct debits
+        let terms = Mandate::new(setup.debtor.clone(), setup.creditor.clone(), Currency::EUR, 75_000, Period::Monthly, DUE - DAY, DUE + DAY)?;
+        let plain = setup.debtor.sign_mandate(terms.clone())?;
+        assert_eq!(DirectDebit::new(setup.creditor.clone(), &plain, 25_000, SequenceType::OneOff, DUE), Err(Error::MandateMismatch));
+
+        // nor under one the bank does not know about
+        let unregistered = setup.debtor.sign_mandate(terms.for_direct_debit(CreditorId::try_from("DE98ZZZ09999999999")?, "MANDATE-0002".to_string())?)?;
+        let known = std::mem::replace(&mut setup.mandate, unregistered);
+        let signed = debit_with(&mut setup, SequenceType::OneOff, 25_000, DUE)?;
+        assert_eq!(collect(&mut setup, &signed, DUE), Err(Error::UnknownMandate));
+
+        // and the mandate's allowance and revocation apply
+        setup.mandate = known;
+        let signed = debit_with(&mut setup, SequenceType::OneOff, 80_000, DUE)?;
+        assert_eq!(collect(&mut setup, &signed, DUE), Err(Error::MandateLimitExceeded));
+        setup.mandates.revoke(&setup.debtor.revoke_mandate(&setup.mandate, DUE)?)?;
+        let signed = debit_with(&mut setup, SequenceType::OneOff, 25_000, DUE)?;
+        assert_eq!(collect(&mut setup, &signed, DUE), Err(Error::MandateRevoked));
+        assert_eq!(setup.ledger.balance(setup.debtor.account_number(), Currency::EUR), 100_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn refund_by_other_user() -> Result<(), Error> {
+        let mut setup = setup()?;
+        let signed = debit(&mut setup, SequenceType::OneOff, DUE)?;
+        let impostor = User::new("DE89370400440532013000".parse()?);
+
+        assert_eq!(impostor.claim_refund(&signed), Err(Error::WrongSigner));
+        Ok(())
+    }
+}
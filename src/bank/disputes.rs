@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use super::{BankKey, Ledger, PaymentBook};
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::put_u64;
+use crate::iban::parse_valid;
+use crate::time::DAY;
+use crate::transaction::{Currency, TransactionId};
+use crate::{Error, Timestamp, IBAN};
+
+/// Identifies a dispute by the transaction and leg it is about.
+pub type DisputeId = [u8; 32];
+
+/// Returns the id of the dispute about the leg at `leg` of a transaction.
+pub fn dispute_id(transaction_id: &TransactionId, leg: usize) -> DisputeId {
+    let mut buf = b"dispute".to_vec();
+    buf.extend_from_slice(transaction_id);
+    put_u64(&mut buf, leg as u64);
+    Sha256::digest(buf).into()
+}
+
+/// Why a user disputes a payment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReasonCode {
+    Fraud,
+    NotReceived,
+    NotAsDescribed,
+    Duplicate,
+    Cancelled,
+    IncorrectAmount,
+}
+
+impl ReasonCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Fraud => 10,
+            Self::NotReceived => 20,
+            Self::NotAsDescribed => 21,
+            Self::Duplicate => 30,
+            Self::Cancelled => 31,
+            Self::IncorrectAmount => 32,
+        }
+    }
+}
+
+/// A single step of a dispute, taken by one of the parties.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisputeStep {
+    /// The user disputes `amount` of a leg of a settled transaction.
+    Open { transaction_id: TransactionId, leg: usize, amount: u64, reason: ReasonCode },
+    /// The merchant submits the hash of a document backing its case.
+    Evidence { digest: [u8; 32] },
+    /// The merchant contests the dispute, based on the evidence submitted.
+    Represent,
+    /// The merchant accepts the dispute.
+    AcceptLiability,
+    /// The arbiter decides a contested dispute.
+    Arbitrate { in_favor_of_user: bool },
+}
+
+impl DisputeStep {
+    fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Open { transaction_id, leg, amount, reason } => {
+                let mut buf = vec![0];
+                buf.extend_from_slice(transaction_id);
+                put_u64(&mut buf, *leg as u64);
+                put_u64(&mut buf, *amount);
+                buf.extend_from_slice(&reason.code().to_be_bytes());
+                buf
+            }
+            Self::Evidence { digest } => [&[1][..], digest].concat(),
+            Self::Represent => vec![2],
+            Self::AcceptLiability => vec![3],
+            Self::Arbitrate { in_favor_of_user } => vec![4, *in_favor_of_user as u8],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedDisputeStep {
+    dispute_id: DisputeId,
+    step: DisputeStep,
+    at: Timestamp,
+    signer: PublicKey,
+    signature: Signature,
+}
+
+impl SignedDisputeStep {
+    pub(crate) fn new(dispute_id: DisputeId, step: DisputeStep, at: Timestamp, signer: PublicKey, signature: Signature) -> Self {
+        Self { dispute_id, step, at, signer, signature }
+    }
+
+    pub(crate) fn signed_bytes(dispute_id: &DisputeId, step: &DisputeStep, at: Timestamp) -> Vec<u8> {
+        let mut buf = b"dispute-step".to_vec();
+        buf.extend_from_slice(dispute_id);
+        buf.extend_from_slice(&step.as_bytes());
+        put_u64(&mut buf, at);
+        buf
+    }
+
+    pub fn dispute_id(&self) -> &DisputeId {
+        &self.dispute_id
+    }
+
+    pub fn step(&self) -> &DisputeStep {
+        &self.step
+    }
+
+    /// Returns when the signer claims to have taken the step.
+    pub fn at(&self) -> Timestamp {
+        self.at
+    }
+
+    pub fn signer(&self) -> &PublicKey {
+        &self.signer
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        self.signer
+            .verify(&Self::signed_bytes(&self.dispute_id, &self.step, self.at), &self.signature)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeStage {
+    /// The user has been credited provisionally, the merchant has to respond.
+    Opened,
+    /// The merchant contested the dispute, the arbiter has to decide.
+    Represented,
+    Resolved { in_favor_of_user: bool },
+}
+
+/// One entry of a dispute's audit trail.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditEntry {
+    /// A signed step, along with the time the bank accepted it.
+    Step { step: Box<SignedDisputeStep>, accepted_at: Timestamp },
+    /// The deadline of a stage passed without the required step.
+    DeadlinePassed { stage: DisputeStage, at: Timestamp },
+}
+
+/// How long each stage of a dispute may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeDeadlines {
+    /// How long after a transaction was created it may be disputed.
+    pub opening: Timestamp,
+    /// How long the merchant has to respond to an opened dispute.
+    pub response: Timestamp,
+    /// How long the arbiter has to decide a contested dispute.
+    pub arbitration: Timestamp,
+}
+
+impl Default for DisputeDeadlines {
+    fn default() -> Self {
+        Self { opening: 120 * DAY, response: 30 * DAY, arbitration: 30 * DAY }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dispute {
+    transaction_id: TransactionId,
+    leg: usize,
+    amount: u64,
+    reason: ReasonCode,
+    merchant: PublicKey,
+    user_account: IBAN,
+    merchant_account: IBAN,
+    currency: Currency,
+    stage: DisputeStage,
+    deadline: Timestamp,
+    evidence: Vec<[u8; 32]>,
+    audit: Vec<AuditEntry>,
+}
+
+impl Dispute {
+    pub fn transaction_id(&self) -> &TransactionId {
+        &self.transaction_id
+    }
+
+    pub fn leg(&self) -> usize {
+        self.leg
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn reason(&self) -> ReasonCode {
+        self.reason
+    }
+
+    pub fn stage(&self) -> DisputeStage {
+        self.stage
+    }
+
+    /// Returns until when the current stage has to be completed.
+    pub fn deadline(&self) -> Timestamp {
+        self.deadline
+    }
+
+    pub fn evidence(&self) -> &[[u8; 32]] {
+        &self.evidence
+    }
+
+    pub fn audit_trail(&self) -> &[AuditEntry] {
+        &self.audit
+    }
+}
+
+/// Tracks disputes over settled payments, from opening to resolution.
+///
+/// Opening a dispute credits the disputed amount to the user right away,
+/// from the bank's suspense account. This provisional credit becomes final
+/// unless the merchant contests the dispute in time and wins the
+/// arbitration, or the arbiter misses its deadline.
+///
+/// Once a dispute is resolved, the side that lost it pays the credit back to
+/// the suspense account: the merchant if the user won, the user otherwise.
+/// Whatever the losing side can't pay is recorded as its debt.
+#[derive(Debug)]
+pub struct DisputeBook {
+    arbiter: PublicKey,
+    suspense: IBAN,
+    deadlines: DisputeDeadlines,
+    disputes: HashMap<DisputeId, Dispute>,
+    debts: HashMap<(IBAN, Currency), u64>,
+}
+
+impl DisputeBook {
+    /// Creates a book that funds provisional credits from `suspense`.
+    pub fn new(arbiter: &BankKey, suspense: IBAN) -> Self {
+        Self::with_deadlines(arbiter, suspense, DisputeDeadlines::default())
+    }
+
+    pub fn with_deadlines(arbiter: &BankKey, suspense: IBAN, deadlines: DisputeDeadlines) -> Self {
+        Self { arbiter: *arbiter.verifying_key(), suspense, deadlines, disputes: HashMap::new(), debts: HashMap::new() }
+    }
+
+    pub fn get(&self, id: &DisputeId) -> Option<&Dispute> {
+        self.disputes.get(id)
+    }
+
+    /// Returns what `account` still owes the bank in `currency` for the
+    /// disputes it lost.
+    pub fn debt(&self, account: &IBAN, currency: Currency) -> u64 {
+        self.debts.get(&(*account, currency)).copied().unwrap_or(0)
+    }
+
+    /// Opens a dispute signed by the user of a settled transaction and
+    /// provisionally credits the disputed amount.
+    pub fn open(
+        &mut self,
+        ledger: &mut Ledger,
+        payments: &mut PaymentBook,
+        signed: &SignedDisputeStep,
+        now: Timestamp,
+    ) -> Result<DisputeId, Error> {
+        let DisputeStep::Open { transaction_id, leg, amount, reason } = signed.step().clone() else {
+            return Err(Error::InvalidDisputeStep);
+        };
+
+        let id = dispute_id(&transaction_id, leg);
+        if *signed.dispute_id() != id {
+            return Err(Error::InvalidDisputeStep);
+        }
+        if self.disputes.contains_key(&id) {
+            return Err(Error::DuplicateDispute);
+        }
+
+        let payment = payments.get(&transaction_id).ok_or(Error::UnknownTransaction)?.transaction().transaction();
+        if payment.user().verifying_key() != signed.signer() {
+            return Err(Error::WrongSigner);
+        }
+        signed.verify()?;
+
+        if now > payment.created_at().saturating_add(self.deadlines.opening) {
+            return Err(Error::DeadlinePassed);
+        }
+
+        let merchant = payment.legs().get(leg).ok_or(Error::UnknownLeg)?.merchant();
+        let (merchant, merchant_account) = (*merchant.verifying_key(), parse_valid(merchant.account_number())?);
+        let (user_account, currency) = (*payment.user().account_number(), payment.currency());
+
+        payments.reserve_refund(&transaction_id, leg, amount)?;
+        if let Err(err) = ledger.transfer(&self.suspense, &user_account, currency, amount) {
+            payments.release_refund(&transaction_id, leg, amount)?;
+            return Err(err);
+        }
+
+        self.disputes.insert(id, Dispute {
+            transaction_id,
+            leg,
+            amount,
+            reason,
+            merchant,
+            user_account,
+            merchant_account,
+            currency,
+            stage: DisputeStage::Opened,
+            deadline: now.saturating_add(self.deadlines.response),
+            evidence: Vec::new(),
+            audit: vec![AuditEntry::Step { step: Box::new(signed.clone()), accepted_at: now }],
+        });
+        Ok(id)
+    }
+
+    /// Applies a step by the merchant or the arbiter to an open dispute.
+    pub fn submit(
+        &mut self,
+        ledger: &mut Ledger,
+        payments: &mut PaymentBook,
+        signed: &SignedDisputeStep,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        let id = *signed.dispute_id();
+        let dispute = self.disputes.get_mut(&id).ok_or(Error::UnknownDispute)?;
+
+        let signer = match signed.step() {
+            DisputeStep::Arbitrate { .. } => self.arbiter,
+            _ => dispute.merchant,
+        };
+        if *signed.signer() != signer {
+            return Err(Error::WrongSigner);
+        }
+        signed.verify()?;
+
+        if matches!(dispute.stage, DisputeStage::Resolved { .. }) {
+            return Err(Error::InvalidDisputeStep);
+        }
+        if now > dispute.deadline {
+            return Err(Error::DeadlinePassed);
+        }
+
+        match (signed.step(), dispute.stage) {
+            (DisputeStep::Evidence { digest }, DisputeStage::Opened) => {
+                dispute.evidence.push(*digest);
+            }
+            (DisputeStep::Represent, DisputeStage::Opened) => {
+                if dispute.evidence.is_empty() {
+                    return Err(Error::MissingEvidence);
+                }
+                dispute.stage = DisputeStage::Represented;
+                dispute.deadline = now.saturating_add(self.deadlines.arbitration);
+            }
+            (DisputeStep::AcceptLiability, DisputeStage::Opened) => {
+                self.resolve(ledger, payments, &id, true)?;
+            }
+            (DisputeStep::Arbitrate { in_favor_of_user }, DisputeStage::Represented) => {
+                self.resolve(ledger, payments, &id, *in_favor_of_user)?;
+            }
+            _ => return Err(Error::InvalidDisputeStep),
+        }
+
+        let dispute = self.disputes.get_mut(&id).ok_or(Error::UnknownDispute)?;
+        dispute.audit.push(AuditEntry::Step { step: Box::new(signed.clone()), accepted_at: now });
+        Ok(())
+    }
+
+    /// Resolves all disputes whose current stage ran past its deadline and
+    /// returns their ids.
+    ///
+    /// A merchant that did not respond loses the dispute. A contested
+    /// dispute the arbiter did not decide in time goes to the merchant.
+    pub fn expire(&mut self, ledger: &mut Ledger, payments: &mut PaymentBook, now: Timestamp) -> Result<Vec<DisputeId>, Error> {
+        let expired = self.disputes.iter()
+            .filter(|(_, dispute)| now > dispute.deadline && !matches!(dispute.stage, DisputeStage::Resolved { .. }))
+            .map(|(id, dispute)| (*id, dispute.stage))
+            .collect::<Vec<_>>();
+
+        let mut resolved = Vec::new();
+        for (id, stage) in expired {
+            self.resolve(ledger, payments, &id, stage == DisputeStage::Opened)?;
+
+            let dispute = self.disputes.get_mut(&id).ok_or(Error::UnknownDispute)?;
+            dispute.audit.push(AuditEntry::DeadlinePassed { stage, at: now });
+            resolved.push(id);
+        }
+
+        Ok(resolved)
+    }
+
+    /// Has the side that lost a dispute pay the provisional credit back to
+    /// the suspense account, as far as its balance allows, and records the
+    /// rest as its debt.
+    fn resolve(&mut self, ledger: &mut Ledger, payments: &mut PaymentBook, id: &DisputeId, in_favor_of_user: bool) -> Result<(), Error> {
+        let dispute = self.disputes.get(id).ok_or(Error::UnknownDispute)?;
+        let (amount, currency) = (dispute.amount, dispute.currency);
+        let loser = if in_favor_of_user { dispute.merchant_account } else { dispute.user_account };
+
+        let paid = ledger.balance(&loser, currency).min(amount);
+        let debt = self.debt(&loser, currency).checked_add(amount - paid).ok_or(Error::AmountOverflow)?;
+        ledger.transfer(&loser, &self.suspense, currency, paid)?;
+        if !in_favor_of_user {
+            // the merchant keeps the payment, which may be refunded again
+            payments.release_refund(&dispute.transaction_id, dispute.leg, amount)?;
+        }
+
+        if debt > 0 {
+            self.debts.insert((loser, currency), debt);
+        }
+        let dispute = self.disputes.get_mut(id).ok_or(Error::UnknownDispute)?;
+        dispute.stage = DisputeStage::Resolved { in_favor_of_user };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::Transaction;
+    use crate::user::User;
+
+    struct Setup {
+        user: User,
+        merchant: Merchant,
+        arbiter: BankKey,
+        ledger: Ledger,
+        payments: PaymentBook,
+        disputes: DisputeBook,
+        id: DisputeId,
+        suspense: IBAN,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let arbiter = BankKey::new();
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 20_000)?;
+        let mut payments = PaymentBook::new();
+        let signed = user.sign(Transaction::new(20_000, Currency::EUR, merchant.clone(), user.clone(), 0))?;
+        payments.settle(&mut ledger, &signed, 0)?;
+
+        let suspense = IBAN::try_from("AT611904300234573201")?;
+        ledger.deposit(&suspense, Currency::EUR, 100_000)?;
+        let mut disputes = DisputeBook::new(&arbiter, suspense);
+        let open = user.open_dispute(&signed, 0, 20_000, ReasonCode::NotReceived, DAY)?;
+        let id = disputes.open(&mut ledger, &mut payments, &open, DAY)?;
+
+        Ok(Setup { user, merchant, arbiter, ledger, payments, disputes, id, suspense })
+    }
+
+    // the balances of the user, the merchant and the suspense account
+    fn balances(s: &Setup) -> Result<(u64, u64, u64), Error> {
+        Ok((
+            s.ledger.balance(s.user.account_number(), Currency::EUR),
+            s.ledger.balance(&parse_valid(s.merchant.account_number())?, Currency::EUR),
+            s.ledger.balance(&s.suspense, Currency::EUR),
+        ))
+    }
+
+    #[test]
+    fn provisional_credit() -> Result<(), Error> {
+        let s = setup()?;
+
+        // the bank fronts the credit, the merchant keeps the payment for now
+        assert_eq!(balances(&s)?, (20_000, 20_000, 80_000));
+        assert_eq!(s.disputes.get(&s.id).map(|d| d.stage()), Some(DisputeStage::Opened));
+        Ok(())
+    }
+
+    #[test]
+    fn merchant_wins_arbitration() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        let represent = s.merchant.respond_to_dispute(&s.id, DisputeStep::Represent, 2 * DAY)?;
+        assert_eq!(
+            s.disputes.submit(&mut s.ledger, &mut s.payments, &represent, 2 * DAY),
+            Err(Error::MissingEvidence)
+        );
+
+        let evidence = s.merchant.respond_to_dispute(&s.id, DisputeStep::Evidence { digest: [7; 32] }, 2 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &evidence, 2 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &represent, 2 * DAY)?;
+
+        let decision = s.arbiter.arbitrate(&s.id, false, 3 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &decision, 3 * DAY)?;
+
+        assert_eq!(balances(&s)?, (0, 20_000, 100_000));
+        let dispute = s.disputes.get(&s.id).ok_or(Error::DevError)?;
+        assert_eq!(dispute.stage(), DisputeStage::Resolved { in_favor_of_user: false });
+        assert_eq!(dispute.audit_trail().len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merchant_misses_deadline() -> Result<(), Error> {
+        let mut s = setup()?;
+        let deadline = s.disputes.get(&s.id).ok_or(Error::DevError)?.deadline();
+
+        assert!(s.disputes.expire(&mut s.ledger, &mut s.payments, deadline)?.is_empty());
+        assert_eq!(s.disputes.expire(&mut s.ledger, &mut s.payments, deadline + 1)?, vec![s.id]);
+
+        let late = s.merchant.respond_to_dispute(&s.id, DisputeStep::Evidence { digest: [7; 32] }, deadline + 1)?;
+        assert_eq!(
+            s.disputes.submit(&mut s.ledger, &mut s.payments, &late, deadline + 1),
+            Err(Error::InvalidDisputeStep)
+        );
+        assert_eq!(balances(&s)?, (20_000, 0, 100_000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_cannot_repay() -> Result<(), Error> {
+        let mut s = setup()?;
+        let evidence = s.merchant.respond_to_dispute(&s.id, DisputeStep::Evidence { digest: [7; 32] }, 2 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &evidence, 2 * DAY)?;
+        let represent = s.merchant.respond_to_dispute(&s.id, DisputeStep::Represent, 2 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &represent, 2 * DAY)?;
+
+        // the user spends most of the provisional credit in the meantime
+        s.ledger.withdraw(s.user.account_number(), Currency::EUR, 15_000)?;
+        let deadline = s.disputes.get(&s.id).ok_or(Error::DevError)?.deadline();
+        assert_eq!(s.disputes.expire(&mut s.ledger, &mut s.payments, deadline + 1)?, vec![s.id]);
+
+        let dispute = s.disputes.get(&s.id).ok_or(Error::DevError)?;
+        assert_eq!(dispute.stage(), DisputeStage::Resolved { in_favor_of_user: false });
+        assert_eq!(balances(&s)?, (0, 20_000, 85_000));
+        assert_eq!(s.disputes.debt(s.user.account_number(), Currency::EUR), 15_000);
+        assert!(s.disputes.expire(&mut s.ledger, &mut s.payments, deadline + 2)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_parties_may_act() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        // the user can't arbitrate its own dispute, and no one but the user can open one
+        let forged = s.user.respond_to_dispute(&s.id, DisputeStep::Arbitrate { in_favor_of_user: true }, 2 * DAY)?;
+        assert_eq!(s.disputes.submit(&mut s.ledger, &mut s.payments, &forged, 2 * DAY), Err(Error::WrongSigner));
+
+        let accept = s.merchant.respond_to_dispute(&s.id, DisputeStep::AcceptLiability, 2 * DAY)?;
+        s.disputes.submit(&mut s.ledger, &mut s.payments, &accept, 2 * DAY)?;
+        assert_eq!(
+            s.disputes.get(&s.id).map(|d| d.stage()),
+            Some(DisputeStage::Resolved { in_favor_of_user: true })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+use super::Ledger;
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_key, put_u64};
+use crate::iban::parse_valid;
+use crate::traits::ToBytes;
+use crate::transaction::{Currency, Purpose, SignedTransaction, TransactionId};
+use crate::{Error, Timestamp, IBAN};
+
+/// Identifies an escrow by the id of the transaction that funded it.
+pub type EscrowId = TransactionId;
+
+/// What a party signing an escrow approval agrees to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Decision {
+    /// Pay the held funds out to the merchants.
+    Release,
+    /// Pay the held funds back to the user.
+    Refund,
+    /// Object to the deal, which blocks conditions that require no dispute.
+    Dispute,
+}
+
+impl Decision {
+    fn as_byte(&self) -> u8 {
+        match self {
+            Self::Release => 0,
+            Self::Refund => 1,
+            Self::Dispute => 2,
+        }
+    }
+}
+
+/// A condition under which held funds may be paid out.
+///
+/// Conditions are evaluated against the keys that signed an approval for
+/// the same decision, the current time and whether the escrow is disputed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The holder of the key has signed an approval.
+    SignedBy(PublicKey),
+    /// At least `n` of the keys have signed an approval.
+    Threshold(usize, Vec<PublicKey>),
+    /// The given point in time has been reached.
+    After(Timestamp),
+    /// The given point in time has not been reached yet.
+    Before(Timestamp),
+    /// Nobody has disputed the escrow.
+    Undisputed,
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    /// Releases to the seller if buyer and seller both agree, if the arbiter
+    /// decides so, or once `deadline` passed without a dispute.
+    pub fn standard_release(buyer: PublicKey, seller: PublicKey, arbiter: PublicKey, deadline: Timestamp) -> Self {
+        Self::Any(vec![
+            Self::All(vec![Self::SignedBy(buyer), Self::SignedBy(seller)]),
+            Self::SignedBy(arbiter),
+            Self::All(vec![Self::After(deadline), Self::Undisputed]),
+        ])
+    }
+
+    /// Refunds the buyer if the seller agrees or the arbiter decides so.
+    pub fn standard_refund(seller: PublicKey, arbiter: PublicKey) -> Self {
+        Self::Any(vec![Self::SignedBy(seller), Self::SignedBy(arbiter)])
+    }
+
+    pub fn is_met(&self, signers: &[PublicKey], now: Timestamp, disputed: bool) -> bool {
+        match self {
+            Self::SignedBy(key) => signers.contains(key),
+            Self::Threshold(n, keys) => keys.iter().filter(|key| signers.contains(key)).count() >= *n,
+            Self::After(at) => now >= *at,
+            Self::Before(at) => now < *at,
+            Self::Undisputed => !disputed,
+            Self::All(conditions) => conditions.iter().all(|c| c.is_met(signers, now, disputed)),
+            Self::Any(conditions) => conditions.iter().any(|c| c.is_met(signers, now, disputed)),
+        }
+    }
+}
+
+impl ToBytes for Condition {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Self::SignedBy(key) => {
+                buf.push(0);
+                put_key(&mut buf, key);
+            }
+            Self::Threshold(n, keys) => {
+                buf.push(1);
+                put_u64(&mut buf, *n as u64);
+                put_u64(&mut buf, keys.len() as u64);
+                for key in keys {
+                    put_key(&mut buf, key);
+                }
+            }
+            Self::After(at) => {
+                buf.push(2);
+                put_u64(&mut buf, *at);
+            }
+            Self::Before(at) => {
+                buf.push(3);
+                put_u64(&mut buf, *at);
+            }
+            Self::Undisputed => buf.push(4),
+            Self::All(conditions) | Self::Any(conditions) => {
+                buf.push(if matches!(self, Self::All(_)) { 5 } else { 6 });
+                put_u64(&mut buf, conditions.len() as u64);
+                for condition in conditions {
+                    buf.extend_from_slice(&condition.as_bytes());
+                }
+            }
+        }
+        buf
+    }
+}
+
+/// Returns the hash a user signs to agree to the conditions of an escrow.
+pub(crate) fn escrow_terms(release: &Condition, refund: &Condition) -> [u8; 32] {
+    let mut buf = b"escrow-terms".to_vec();
+    buf.extend_from_slice(&release.as_bytes());
+    buf.extend_from_slice(&refund.as_bytes());
+    Sha256::digest(buf).into()
+}
+
+/// A signed statement by one party about what should happen to an escrow.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EscrowApproval {
+    escrow_id: EscrowId,
+    decision: Decision,
+    signer: PublicKey,
+    signature: Signature,
+}
+
+impl EscrowApproval {
+    pub(crate) fn new(escrow_id: EscrowId, decision: Decision, signer: PublicKey, signature: Signature) -> Self {
+        Self { escrow_id, decision, signer, signature }
+    }
+
+    pub(crate) fn signed_bytes(escrow_id: &EscrowId, decision: Decision) -> Vec<u8> {
+        let mut buf = b"escrow".to_vec();
+        buf.extend_from_slice(escrow_id);
+        buf.push(decision.as_byte());
+        buf
+    }
+
+    pub fn escrow_id(&self) -> &EscrowId {
+        &self.escrow_id
+    }
+
+    pub fn decision(&self) -> Decision {
+        self.decision
+    }
+
+    pub fn signer(&self) -> &PublicKey {
+        &self.signer
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        self.signer
+            .verify(&Self::signed_bytes(&self.escrow_id, self.decision), &self.signature)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscrowState {
+    Held,
+    Released,
+    Refunded,
+}
+
+/// Funds taken from a user and held by the bank until a condition is met.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Escrow {
+    transaction: SignedTransaction,
+    release: Condition,
+    refund: Condition,
+    disputed: bool,
+    state: EscrowState,
+}
+
+impl Escrow {
+    pub fn transaction(&self) -> &SignedTransaction {
+        &self.transaction
+    }
+
+    pub fn is_disputed(&self) -> bool {
+        self.disputed
+    }
+
+    pub fn state(&self) -> EscrowState {
+        self.state
+    }
+}
+
+/// The escrow accounts of a bank.
+#[derive(Debug, Default)]
+pub struct EscrowBook {
+    escrows: HashMap<EscrowId, Escrow>,
+}
+
+impl EscrowBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &EscrowId) -> Option<&Escrow> {
+        self.escrows.get(id)
+    }
+
+    /// Returns the buyers' accounts and the amounts held for them in
+    /// `currency`, one per escrow that is neither released nor refunded.
+    pub fn reserved(&self, currency: Currency) -> impl Iterator<Item = (&IBAN, u64)> {
+        self.escrows.values()
+            .filter(|escrow| escrow.state == EscrowState::Held)
+            .map(|escrow| escrow.transaction.transaction())
+            .filter(move |transaction| transaction.currency() == currency)
+            .map(|transaction| (transaction.user().account_number(), transaction.amount()))
+    }
+
+    /// Takes the amount of a user-signed transaction from the user's account
+    /// and holds it until either the release or the refund condition is met.
+    /// The user must have signed the transaction for an escrow under exactly
+    /// these conditions, with the key of their account at `received_at`.
+    /// Transactions signed through a delegation are refused, as the book
+    /// does not track their allowances.
+    pub fn open(
+        &mut self,
+        ledger: &mut Ledger,
+        signed: &SignedTransaction,
+        release: Condition,
+        refund: Condition,
+        received_at: Timestamp,
+    ) -> Result<EscrowId, Error> {
+        ledger.policy().verify(signed)?;
+        if !signed.delegation().is_empty() {
+            return Err(Error::UntrackedDelegation);
+        }
+        let user = signed.transaction().user();
+        ledger.authorize(user.account_number(), user.verifying_key(), received_at)?;
+        if signed.transaction().purpose() != Purpose::Escrow(escrow_terms(&release, &refund)) {
+            return Err(Error::WrongPurpose);
+        }
+
+        let id = signed.id();
+        if self.escrows.contains_key(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let transaction = signed.transaction();
+        // make sure the merchants can be paid before taking any money
+        for leg in transaction.legs() {
+            parse_valid(leg.merchant().account_number())?;
+        }
+        ledger.withdraw(transaction.user().account_number(), transaction.currency(), transaction.amount())?;
+
+        self.escrows.insert(id, Escrow {
+            transaction: signed.clone(),
+            release,
+            refund,
+            disputed: false,
+            state: EscrowState::Held,
+        });
+        Ok(id)
+    }
+
+    /// Marks an escrow as disputed. Only the user or one of the merchants of
+    /// the underlying transaction may dispute it.
+    pub fn dispute(&mut self, approval: &EscrowApproval) -> Result<(), Error> {
+        let escrow = self.held(approval.escrow_id())?;
+        approval.verify()?;
+
+        let transaction = escrow.transaction.transaction();
+        let is_party = transaction.user().verifying_key() == approval.signer()
+            || transaction.legs().iter().any(|leg| leg.merchant().verifying_key() == approval.signer());
+        if approval.decision() != Decision::Dispute || !is_party {
+            return Err(Error::WrongSigner);
+        }
+
+        escrow.disputed = true;
+        Ok(())
+    }
+
+    /// Pays the held funds to the merchants, if the release condition is met.
+    pub fn release(&mut self, ledger: &mut Ledger, id: &EscrowId, approvals: &[EscrowApproval], now: Timestamp) -> Result<(), Error> {
+        let escrow = self.held(id)?;
+        let signers = signers(id, Decision::Release, approvals)?;
+        if !escrow.release.is_met(&signers, now, escrow.disputed) {
+            return Err(Error::ConditionNotMet);
+        }
+
+        let transaction = escrow.transaction.transaction();
+        let credits = transaction.legs().iter()
+            .map(|leg| Ok((parse_valid(leg.merchant().account_number())?, leg.amount())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        ledger.deposit_many(transaction.currency(), &credits)?;
+
+        escrow.state = EscrowState::Released;
+        Ok(())
+    }
+
+    /// Pays the held funds back to the user, if the refund condition is met.
+    pub fn refund(&mut self, ledger: &mut Ledger, id: &EscrowId, approvals: &[EscrowApproval], now: Timestamp) -> Result<(), Error> {
+        let escrow = self.held(id)?;
+        let signers = signers(id, Decision::Refund, approvals)?;
+        if !escrow.refund.is_met(&signers, now, escrow.disputed) {
+            return Err(Error::ConditionNotMet);
+        }
+
+        let transaction = escrow.transaction.transaction();
+        let user = *transaction.user().account_number();
+        ledger.deposit(&user, transaction.currency(), transaction.amount())?;
+
+        escrow.state = EscrowState::Refunded;
+        Ok(())
+    }
+
+    fn held(&mut self, id: &EscrowId) -> Result<&mut Escrow, Error> {
+        let escrow = self.escrows.get_mut(id).ok_or(Error::UnknownEscrow)?;
+        if escrow.state != EscrowState::Held {
+            return Err(Error::EscrowClosed);
+        }
+
+        Ok(escrow)
+    }
+}
+
+/// Verifies the approvals and returns the keys that approved `decision` on `id`.
+fn signers(id: &EscrowId, decision: Decision, approvals: &[EscrowApproval]) -> Result<Vec<PublicKey>, Error> {
+    approvals.iter()
+        .filter(|approval| approval.escrow_id() == id && approval.decision() == decision)
+        .map(|approval| {
+            approval.verify()?;
+            Ok(*approval.signer())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+    use crate::user::{Allowance, User};
+    use crate::IBAN;
+
+    const DEADLINE: Timestamp = 1_000;
+
+    struct Setup {
+        buyer: User,
+        seller: Merchant,
+        arbiter: User,
+        ledger: Ledger,
+        book: EscrowBook,
+        id: EscrowId,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let buyer = User::new("DE89370400440532013000".parse()?);
+        let seller = Merchant::new("GB82WEST12345698765432".to_string());
+        let arbiter = User::new("FR1420041010050500013M02606".parse()?);
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&buyer, 0, None)?;
+        ledger.deposit(buyer.account_number(), Currency::EUR, 50_000)?;
+        let mut book = EscrowBook::new();
+
+        let release = Condition::standard_release(*buyer.verifying_key(), *seller.verifying_key(), *arbiter.verifying_key(), DEADLINE);
+        let refund = Condition::standard_refund(*seller.verifying_key(), *arbiter.verifying_key());
+        let transaction = Transaction::new(50_000, Currency::EUR, seller.clone(), buyer.clone(), 0).for_escrow(&release, &refund);
+        let signed = buyer.sign(transaction)?;
+        let id = book.open(&mut ledger, &signed, release, refund, 0)?;
+
+        Ok(Setup { buyer, seller, arbiter, ledger, book, id })
+    }
+
+    fn balance(setup: &Setup, account: &str) -> Result<u64, Error> {
+        Ok(setup.ledger.balance(&parse_valid(account)?, Currency::EUR))
+    }
+
+    #[test]
+    fn both_parties_release() -> Result<(), Error> {
+        let mut s = setup()?;
+        assert_eq!(s.ledger.balance(s.buyer.account_number(), Currency::EUR), 0);
+
+        let buyer = s.buyer.approve_escrow(&s.id, Decision::Release)?;
+        assert_eq!(s.book.release(&mut s.ledger, &s.id, std::slice::from_ref(&buyer), 0), Err(Error::ConditionNotMet));
+
+        let seller = s.seller.approve_escrow(&s.id, Decision::Release)?;
+        s.book.release(&mut s.ledger, &s.id, &[buyer, seller], 0)?;
+        assert_eq!(balance(&s, s.seller.account_number())?, 50_000);
+        assert_eq!(s.book.release(&mut s.ledger, &s.id, &[], DEADLINE), Err(Error::EscrowClosed));
+
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_without_dispute() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        assert_eq!(s.book.release(&mut s.ledger, &s.id, &[], DEADLINE - 1), Err(Error::ConditionNotMet));
+        s.book.release(&mut s.ledger, &s.id, &[], DEADLINE)?;
+        assert_eq!(balance(&s, s.seller.account_number())?, 50_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dispute_blocks_deadline() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        s.book.dispute(&s.buyer.approve_escrow(&s.id, Decision::Dispute)?)?;
+        assert_eq!(s.book.release(&mut s.ledger, &s.id, &[], DEADLINE), Err(Error::ConditionNotMet));
+
+        // the arbiter may only dispute through its decision
+        assert_eq!(s.book.dispute(&s.arbiter.approve_escrow(&s.id, Decision::Dispute)?), Err(Error::WrongSigner));
+
+        let decision = s.arbiter.approve_escrow(&s.id, Decision::Refund)?;
+        s.book.refund(&mut s.ledger, &s.id, &[decision], DEADLINE)?;
+        assert_eq!(s.ledger.balance(s.buyer.account_number(), Currency::EUR), 50_000);
+        assert_eq!(balance(&s, s.seller.account_number())?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn approvals_are_bound_to_decision() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        // the buyer can't refund itself, and a release approval is no refund approval
+        let buyer = s.buyer.approve_escrow(&s.id, Decision::Refund)?;
+        let seller = s.seller.approve_escrow(&s.id, Decision::Release)?;
+        assert_eq!(s.book.refund(&mut s.ledger, &s.id, &[buyer, seller], 0), Err(Error::ConditionNotMet));
+
+        Ok(())
+    }
+
+    #[test]
+    fn conditions_are_signed() -> Result<(), Error> {
+        let mut s = setup()?;
+        s.ledger.deposit(s.buyer.account_number(), Currency::EUR, 10_000)?;
+        let (buyer, seller) = (*s.buyer.verifying_key(), *s.seller.verifying_key());
+        let payment = Transaction::new(10_000, Currency::EUR, s.seller.clone(), s.buyer.clone(), 0);
+
+        // the bank can't swap in conditions the buyer did not agree to
+        let escrowed = s.buyer.sign(payment.clone().for_escrow(&Condition::SignedBy(buyer), &Condition::SignedBy(seller)))?;
+        let unsigned = Condition::SignedBy(*s.arbiter.verifying_key());
+        assert_eq!(s.book.open(&mut s.ledger, &escrowed, unsigned, Condition::SignedBy(seller), 0), Err(Error::WrongPurpose));
+
+        // nor open an escrow with a plain payment, or pay out an escrowed one directly
+        let plain = s.buyer.sign(payment)?;
+        assert_eq!(s.book.open(&mut s.ledger, &plain, Condition::SignedBy(buyer), Condition::SignedBy(seller), 0), Err(Error::WrongPurpose));
+        assert_eq!(s.ledger.apply(&escrowed, 0), Err(Error::WrongPurpose));
+        assert_eq!(s.ledger.apply(s.book.get(&s.id).ok_or(Error::DevError)?.transaction(), 0), Err(Error::WrongPurpose));
+        assert_eq!(s.ledger.balance(s.buyer.account_number(), Currency::EUR), 10_000);
+
+        s.book.open(&mut s.ledger, &escrowed, Condition::SignedBy(buyer), Condition::SignedBy(seller), 0)?;
+        Ok(())
+    }
+
+    #[test]
+    fn delegated() -> Result<(), Error> {
+        let mut s = setup()?;
+        s.ledger.deposit(s.buyer.account_number(), Currency::EUR, 10_000)?;
+        let device = User::new(*s.buyer.account_number());
+        let certificate = s.buyer.delegate(device.verifying_key(), Allowance::new(1_000, Currency::EUR, DEADLINE))?;
+        let (release, refund) = (Condition::SignedBy(*s.buyer.verifying_key()), Condition::SignedBy(*s.seller.verifying_key()));
+
+        // escrows would spend the allowance without it being counted
+        let transaction = Transaction::new(1_000, Currency::EUR, s.seller.clone(), s.buyer.clone(), 0).for_escrow(&release, &refund);
+        let signed = device.sign_delegated(transaction, vec![certificate])?;
+        assert_eq!(s.book.open(&mut s.ledger, &signed, release, refund, 0), Err(Error::UntrackedDelegation));
+        assert_eq!(s.ledger.balance(s.buyer.account_number(), Currency::EUR), 10_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn threshold() {
+        let keys = (0..3).map(|_| *User::new(IBAN::new()).verifying_key()).collect::<Vec<_>>();
+        let condition = Condition::Threshold(2, keys.clone());
+
+        assert!(!condition.is_met(&keys[..1], 0, false));
+        assert!(condition.is_met(&keys[1..], 0, false));
+    }
+}
@@ -0,0 +1,964 @@
+use std::io::{BufRead, Write};
+
+use sha2::{Digest, Sha256};
+
+use super::{
+    BankKey, Condition, DelegationBook, DirectDebitBook, DisputeBook, DisputeId, EscrowApproval, EscrowBook, EscrowId,
+    Ledger, MandateRegistry, MultisigAccounts, OfflineBook, PartiallySignedTransaction, PaymentBook, PayoutBook,
+    PayoutReport, ReconciliationReport, SettlementBook, SignedDisputeStep, TokenBook,
+};
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_str, put_u64};
+use crate::sepa::{DebitId, RefundClaim, SignedDirectDebit};
+use crate::token::{BlindSignature, Token, Withdrawal};
+use crate::transaction::{AllowanceId, Currency, LegRefund, OfflineAllowance, SignedPayoutBatch, SignedTransaction};
+use crate::user::User;
+use crate::{Error, Timestamp, IBAN};
+
+/// The hash the first entry of a journal links to.
+pub const GENESIS_HASH: [u8; 32] = [0; 32];
+
+/// The kind of change to the ledger a journal entry records, which tells
+/// what its reference is the id of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A transaction applied directly, referenced by its id.
+    Payment,
+    /// An escrow funded by a transaction, referenced by the escrow id.
+    EscrowOpened,
+    EscrowReleased,
+    EscrowRefunded,
+    /// A collected direct debit, referenced by the debit id.
+    DebitCollected,
+    DebitRefunded,
+    /// A refund of a leg, referenced by the refund id.
+    LegRefunded,
+    /// A token withdrawal, referenced by the withdrawal id.
+    TokenWithdrawn,
+    /// A deposited token, referenced by its serial.
+    TokenDeposited,
+    /// An offline allowance held back, referenced by the allowance id.
+    AllowanceIssued,
+    AllowanceReconciled,
+    /// A transaction signed through a delegation chain, referenced by its id.
+    DelegatedPayment,
+    /// A transaction approved under a multisig policy, referenced by its id.
+    CosignedPayment,
+    /// A payout batch, referenced by the batch id.
+    PayoutApplied,
+    /// A step of a dispute, referenced by the dispute id.
+    DisputeOpened,
+    DisputeStepSubmitted,
+    DisputeExpired,
+}
+
+/// Records a change applied to the ledger, linked to the entry before it by
+/// that entry's hash, so that no entry can be changed, removed or moved
+/// without breaking the chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    sequence: u64,
+    recorded_at: Timestamp,
+    kind: EntryKind,
+    reference: [u8; 32],
+    // the signature that authorized the change, if it came with a single
+    // one, so the entry commits to what was signed
+    signature: Option<Signature>,
+    previous: [u8; 32],
+}
+
+/// The bank's signature over the head of the journal after its first
+/// `entries` entries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    entries: u64,
+    head: [u8; 32],
+    at: Timestamp,
+    signature: Signature,
+}
+
+/// An append-only journal of the changes the bank applied to its ledger,
+/// signing a checkpoint over its head every `interval` entries.
+///
+/// Every book that changes balances has its changes go through one of the
+/// journal's methods, which hand them on to the book and record them once
+/// they succeeded. Deposits and withdrawals made on the [`Ledger`] directly
+/// are not recorded.
+///
+/// Journals are written as text, one entry or checkpoint per line, with
+/// hashes and signatures in hex.
+#[derive(Debug)]
+pub struct Journal {
+    key: BankKey,
+    interval: u64,
+    entries: Vec<JournalEntry>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// Checks journals written by [`Journal::write_to`] against the key of the
+/// bank that signed their checkpoints and the interval it signed them at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalVerifier {
+    bank_key: PublicKey,
+    interval: u64,
+}
+
+/// What a journal that verified contains.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalSummary {
+    entries: u64,
+    head: [u8; 32],
+    last_checkpoint: Option<Checkpoint>,
+}
+
+/// The first line of a journal that failed to verify, counted from 1, and why.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalFault {
+    line: usize,
+    error: Error,
+}
+
+impl EntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Payment => "payment",
+            Self::EscrowOpened => "escrow-opened",
+            Self::EscrowReleased => "escrow-released",
+            Self::EscrowRefunded => "escrow-refunded",
+            Self::DebitCollected => "debit-collected",
+            Self::DebitRefunded => "debit-refunded",
+            Self::LegRefunded => "leg-refunded",
+            Self::TokenWithdrawn => "token-withdrawn",
+            Self::TokenDeposited => "token-deposited",
+            Self::AllowanceIssued => "allowance-issued",
+            Self::AllowanceReconciled => "allowance-reconciled",
+            Self::DelegatedPayment => "delegated-payment",
+            Self::CosignedPayment => "cosigned-payment",
+            Self::PayoutApplied => "payout-applied",
+            Self::DisputeOpened => "dispute-opened",
+            Self::DisputeStepSubmitted => "dispute-step-submitted",
+            Self::DisputeExpired => "dispute-expired",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self, Error> {
+        [
+            Self::Payment,
+            Self::EscrowOpened,
+            Self::EscrowReleased,
+            Self::EscrowRefunded,
+            Self::DebitCollected,
+            Self::DebitRefunded,
+            Self::LegRefunded,
+            Self::TokenWithdrawn,
+            Self::TokenDeposited,
+            Self::AllowanceIssued,
+            Self::AllowanceReconciled,
+            Self::DelegatedPayment,
+            Self::CosignedPayment,
+            Self::PayoutApplied,
+            Self::DisputeOpened,
+            Self::DisputeStepSubmitted,
+            Self::DisputeExpired,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == value)
+        .ok_or(Error::JournalMalformed)
+    }
+}
+
+impl JournalEntry {
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn recorded_at(&self) -> Timestamp {
+        self.recorded_at
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    /// Returns the id of what was applied, depending on the kind of entry.
+    pub fn reference(&self) -> &[u8; 32] {
+        &self.reference
+    }
+
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+
+    /// Returns the hash of the entry before this one.
+    pub fn previous(&self) -> &[u8; 32] {
+        &self.previous
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        let mut buf = b"journal-entry".to_vec();
+        put_u64(&mut buf, self.sequence);
+        put_u64(&mut buf, self.recorded_at);
+        put_str(&mut buf, self.kind.as_str());
+        put_bytes(&mut buf, &self.reference);
+        put_bytes(&mut buf, &self.signature.as_ref().map(Signature::to_bytes).unwrap_or_default());
+        put_bytes(&mut buf, &self.previous);
+        Sha256::digest(buf).into()
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "entry {} {} {} {} {} {} {}",
+            self.sequence,
+            self.recorded_at,
+            self.kind.as_str(),
+            to_hex(&self.reference),
+            self.signature.as_ref().map_or("-".to_string(), |signature| to_hex(&signature.to_bytes())),
+            to_hex(&self.previous),
+            to_hex(&self.hash()),
+        )
+    }
+}
+
+impl Checkpoint {
+    pub(crate) fn signed_bytes(entries: u64, head: &[u8; 32], at: Timestamp) -> Vec<u8> {
+        let mut buf = b"journal-checkpoint".to_vec();
+        put_u64(&mut buf, entries);
+        put_bytes(&mut buf, head);
+        put_u64(&mut buf, at);
+        buf
+    }
+
+    /// Returns how many entries the checkpoint covers.
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns the hash of the last entry covered.
+    pub fn head(&self) -> &[u8; 32] {
+        &self.head
+    }
+
+    pub fn at(&self) -> Timestamp {
+        self.at
+    }
+
+    pub fn verify(&self, bank_key: &PublicKey) -> Result<(), Error> {
+        bank_key.verify(&Self::signed_bytes(self.entries, &self.head, self.at), &self.signature)
+    }
+
+    fn to_line(&self) -> String {
+        format!("checkpoint {} {} {} {}", self.entries, self.at, to_hex(&self.head), to_hex(&self.signature.to_bytes()))
+    }
+}
+
+impl Journal {
+    /// Creates an empty journal that `key` signs a checkpoint for every
+    /// `interval` entries, or never if `interval` is 0.
+    pub fn new(key: BankKey, interval: u64) -> Self {
+        Self { key, interval, entries: Vec::new(), checkpoints: Vec::new() }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Returns the hash of the last entry.
+    pub fn head(&self) -> [u8; 32] {
+        self.entries.last().map_or(GENESIS_HASH, JournalEntry::hash)
+    }
+
+    /// Applies a signed transaction to the ledger and records it.
+    pub fn apply(&mut self, ledger: &mut Ledger, signed: &SignedTransaction, at: Timestamp) -> Result<&JournalEntry, Error> {
+        ledger.apply(signed, at)?;
+        self.record(EntryKind::Payment, signed.id(), Some(*signed.signature()), at)
+    }
+
+    /// Settles a signed transaction through `payments`, which keeps it for
+    /// refunds, and records it.
+    pub fn settle(&mut self, payments: &mut PaymentBook, ledger: &mut Ledger, signed: &SignedTransaction, at: Timestamp) -> Result<&JournalEntry, Error> {
+        payments.settle(ledger, signed, at)?;
+        self.record(EntryKind::Payment, signed.id(), Some(*signed.signature()), at)
+    }
+
+    /// Applies a signed transaction through `settlements`, which adds it to
+    /// the current settlement period, and records it.
+    pub fn apply_for_settlement(
+        &mut self,
+        settlements: &mut SettlementBook,
+        ledger: &mut Ledger,
+        signed: &SignedTransaction,
+        at: Timestamp,
+    ) -> Result<&JournalEntry, Error> {
+        settlements.apply(ledger, signed, at)?;
+        self.record(EntryKind::Payment, signed.id(), Some(*signed.signature()), at)
+    }
+
+    /// Applies a transaction signed through a delegation chain through
+    /// `delegations` and records it.
+    pub fn apply_delegated(
+        &mut self,
+        delegations: &mut DelegationBook,
+        ledger: &mut Ledger,
+        signed: &SignedTransaction,
+        at: Timestamp,
+    ) -> Result<&JournalEntry, Error> {
+        delegations.apply(ledger, signed, at)?;
+        self.record(EntryKind::DelegatedPayment, signed.id(), Some(*signed.signature()), at)
+    }
+
+    /// Applies a transaction approved under a multisig policy through
+    /// `accounts` and records it.
+    pub fn apply_cosigned(
+        &mut self,
+        accounts: &mut MultisigAccounts,
+        ledger: &mut Ledger,
+        partial: &PartiallySignedTransaction,
+        at: Timestamp,
+    ) -> Result<&JournalEntry, Error> {
+        accounts.apply(ledger, partial)?;
+        self.record(EntryKind::CosignedPayment, partial.transaction().id(), None, at)
+    }
+
+    /// Applies a payout batch through `payouts` and records it.
+    pub fn apply_payouts(&mut self, payouts: &mut PayoutBook, ledger: &mut Ledger, signed: &SignedPayoutBatch, at: Timestamp) -> Result<PayoutReport, Error> {
+        let report = payouts.apply(ledger, signed)?;
+        self.record(EntryKind::PayoutApplied, signed.batch().id(), Some(*signed.signature()), at)?;
+        Ok(report)
+    }
+
+    /// Opens an escrow through `escrows` and records it.
+    pub fn open_escrow(
+        &mut self,
+        escrows: &mut EscrowBook,
+        ledger: &mut Ledger,
+        signed: &SignedTransaction,
+        release: Condition,
+        refund: Condition,
+        at: Timestamp,
+    ) -> Result<EscrowId, Error> {
+        let id = escrows.open(ledger, signed, release, refund, at)?;
+        self.record(EntryKind::EscrowOpened, id, Some(*signed.signature()), at)?;
+        Ok(id)
+    }
+
+    /// Releases an escrow through `escrows` and records it.
+    pub fn release_escrow(
+        &mut self,
+        escrows: &mut EscrowBook,
+        ledger: &mut Ledger,
+        id: &EscrowId,
+        approvals: &[EscrowApproval],
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        escrows.release(ledger, id, approvals, now)?;
+        self.record(EntryKind::EscrowReleased, *id, None, now)?;
+        Ok(())
+    }
+
+    /// Refunds an escrow through `escrows` and records it.
+    pub fn refund_escrow(
+        &mut self,
+        escrows: &mut EscrowBook,
+        ledger: &mut Ledger,
+        id: &EscrowId,
+        approvals: &[EscrowApproval],
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        escrows.refund(ledger, id, approvals, now)?;
+        self.record(EntryKind::EscrowRefunded, *id, None, now)?;
+        Ok(())
+    }
+
+    /// Collects a direct debit through `debits` and records it.
+    pub fn collect_debit(
+        &mut self,
+        debits: &mut DirectDebitBook,
+        ledger: &mut Ledger,
+        mandates: &mut MandateRegistry,
+        signed: &SignedDirectDebit,
+        now: Timestamp,
+    ) -> Result<DebitId, Error> {
+        let id = debits.collect(ledger, mandates, signed, now)?;
+        self.record(EntryKind::DebitCollected, id, None, now)?;
+        Ok(id)
+    }
+
+    /// Refunds a collected direct debit through `debits` and records it.
+    pub fn refund_debit(&mut self, debits: &mut DirectDebitBook, ledger: &mut Ledger, claim: &RefundClaim, now: Timestamp) -> Result<(), Error> {
+        debits.refund(ledger, claim, now)?;
+        self.record(EntryKind::DebitRefunded, *claim.debit_id(), None, now)?;
+        Ok(())
+    }
+
+    /// Refunds a leg of a settled payment through `payments` and records it.
+    pub fn refund_leg(&mut self, payments: &mut PaymentBook, ledger: &mut Ledger, refund: &LegRefund, at: Timestamp) -> Result<(), Error> {
+        payments.refund(ledger, refund)?;
+        self.record(EntryKind::LegRefunded, refund.id(), None, at)?;
+        Ok(())
+    }
+
+    /// Signs a token withdrawal through `tokens` and records it.
+    pub fn withdraw_token(
+        &mut self,
+        tokens: &mut TokenBook,
+        ledger: &mut Ledger,
+        withdrawal: &Withdrawal,
+        at: Timestamp,
+    ) -> Result<BlindSignature, Error> {
+        let signature = tokens.withdraw(ledger, withdrawal, at)?;
+        self.record(EntryKind::TokenWithdrawn, withdrawal.id(), Some(*withdrawal.signature()), at)?;
+        Ok(signature)
+    }
+
+    /// Deposits a token through `tokens` and records it.
+    pub fn deposit_token(&mut self, tokens: &mut TokenBook, ledger: &mut Ledger, token: &Token, account: &IBAN, at: Timestamp) -> Result<(), Error> {
+        tokens.deposit(ledger, token, account, at)?;
+        self.record(EntryKind::TokenDeposited, *token.serial(), None, at)?;
+        Ok(())
+    }
+
+    /// Issues an offline allowance through `offline` and records it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn issue_allowance(
+        &mut self,
+        offline: &mut OfflineBook,
+        ledger: &mut Ledger,
+        user: &User,
+        currency: Currency,
+        limit: u64,
+        valid_from: Timestamp,
+        expires_at: Timestamp,
+        at: Timestamp,
+    ) -> Result<OfflineAllowance, Error> {
+        let allowance = offline.issue(ledger, user, currency, limit, valid_from, expires_at)?;
+        self.record(EntryKind::AllowanceIssued, allowance.id(), None, at)?;
+        Ok(allowance)
+    }
+
+    /// Reconciles an offline allowance through `offline` and records it.
+    pub fn reconcile_allowance(
+        &mut self,
+        offline: &mut OfflineBook,
+        ledger: &mut Ledger,
+        id: &AllowanceId,
+        now: Timestamp,
+    ) -> Result<ReconciliationReport, Error> {
+        let report = offline.reconcile(ledger, id, now)?;
+        self.record(EntryKind::AllowanceReconciled, *id, None, now)?;
+        Ok(report)
+    }
+
+    /// Opens a dispute through `disputes`, which credits the user
+    /// provisionally, and records it.
+    pub fn open_dispute(
+        &mut self,
+        disputes: &mut DisputeBook,
+        ledger: &mut Ledger,
+        payments: &mut PaymentBook,
+        signed: &SignedDisputeStep,
+        now: Timestamp,
+    ) -> Result<DisputeId, Error> {
+        let id = disputes.open(ledger, payments, signed, now)?;
+        self.record(EntryKind::DisputeOpened, id, Some(*signed.signature()), now)?;
+        Ok(id)
+    }
+
+    /// Applies a step of a dispute through `disputes` and records it.
+    pub fn submit_dispute_step(
+        &mut self,
+        disputes: &mut DisputeBook,
+        ledger: &mut Ledger,
+        payments: &mut PaymentBook,
+        signed: &SignedDisputeStep,
+        now: Timestamp,
+    ) -> Result<(), Error> {
+        disputes.submit(ledger, payments, signed, now)?;
+        self.record(EntryKind::DisputeStepSubmitted, *signed.dispute_id(), Some(*signed.signature()), now)?;
+        Ok(())
+    }
+
+    /// Resolves the disputes past their deadline through `disputes` and
+    /// records each of them.
+    pub fn expire_disputes(
+        &mut self,
+        disputes: &mut DisputeBook,
+        ledger: &mut Ledger,
+        payments: &mut PaymentBook,
+        now: Timestamp,
+    ) -> Result<Vec<DisputeId>, Error> {
+        let expired = disputes.expire(ledger, payments, now)?;
+        for id in &expired {
+            self.record(EntryKind::DisputeExpired, *id, None, now)?;
+        }
+        Ok(expired)
+    }
+
+    /// Records a change that was applied to the ledger, signing a
+    /// checkpoint if the interval is reached.
+    pub(crate) fn record(&mut self, kind: EntryKind, reference: [u8; 32], signature: Option<Signature>, at: Timestamp) -> Result<&JournalEntry, Error> {
+        let entry = JournalEntry {
+            sequence: self.entries.len() as u64,
+            recorded_at: at,
+            kind,
+            reference,
+            signature,
+            previous: self.head(),
+        };
+        self.entries.push(entry);
+
+        if self.interval > 0 && (self.entries.len() as u64).is_multiple_of(self.interval) {
+            self.checkpoint(at)?;
+        }
+        Ok(&self.entries[self.entries.len() - 1])
+    }
+
+    /// Signs the current head of the journal.
+    pub fn checkpoint(&mut self, at: Timestamp) -> Result<&Checkpoint, Error> {
+        let entries = self.entries.len() as u64;
+        let head = self.head();
+        let signature = self.key.sign_bytes(&Checkpoint::signed_bytes(entries, &head, at))?;
+        self.checkpoints.push(Checkpoint { entries, head, at, signature });
+        Ok(&self.checkpoints[self.checkpoints.len() - 1])
+    }
+
+    /// Writes the journal with each checkpoint following the last entry it covers.
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<(), Error> {
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        let mut write = |line: String| writeln!(writer, "{line}").map_err(|_| Error::JournalUnreadable);
+
+        while let Some(checkpoint) = checkpoints.next_if(|checkpoint| checkpoint.entries == 0) {
+            write(checkpoint.to_line())?;
+        }
+        for entry in &self.entries {
+            write(entry.to_line())?;
+            while let Some(checkpoint) = checkpoints.next_if(|checkpoint| checkpoint.entries == entry.sequence + 1) {
+                write(checkpoint.to_line())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JournalVerifier {
+    /// Creates a verifier for journals that `bank_key` signed a checkpoint
+    /// for every `interval` entries, or that have no checkpoints to expect
+    /// if `interval` is 0.
+    pub fn new(bank_key: PublicKey, interval: u64) -> Self {
+        Self { bank_key, interval }
+    }
+
+    /// Walks a journal from its first line, checking that every entry hashes
+    /// to what it claims, links to the entry before it and comes in
+    /// sequence, and that every checkpoint was signed over the head at that
+    /// point. A checkpoint has to follow every `interval` entries, so that
+    /// checkpoints can not be left out to hide a rewrite.
+    ///
+    /// Entries after the last checkpoint are only as trustworthy as whoever
+    /// kept the journal, since they could have been rewritten along with
+    /// their hashes.
+    pub fn verify(&self, reader: impl BufRead) -> Result<JournalSummary, JournalFault> {
+        let mut summary = JournalSummary { entries: 0, head: GENESIS_HASH, last_checkpoint: None };
+        let mut lines = 0;
+        for (index, line) in reader.lines().enumerate() {
+            let fault = |error| JournalFault { line: index + 1, error };
+            let line = line.map_err(|_| fault(Error::JournalUnreadable))?;
+            self.verify_line(&mut summary, &line).map_err(fault)?;
+            lines = index + 1;
+        }
+
+        if self.checkpoint_due(&summary) {
+            return Err(JournalFault { line: lines + 1, error: Error::CheckpointMissing });
+        }
+        Ok(summary)
+    }
+
+    // whether the entries so far end on the interval without a checkpoint over them
+    fn checkpoint_due(&self, summary: &JournalSummary) -> bool {
+        self.interval > 0
+            && summary.entries > 0
+            && summary.entries.is_multiple_of(self.interval)
+            && summary.last_checkpoint.as_ref().map(Checkpoint::entries) != Some(summary.entries)
+    }
+
+    fn verify_line(&self, summary: &mut JournalSummary, line: &str) -> Result<(), Error> {
+        let fields = line.split(' ').collect::<Vec<_>>();
+        match fields[..] {
+            ["entry", sequence, recorded_at, kind, reference, signature, previous, hash] => {
+                if self.checkpoint_due(summary) {
+                    return Err(Error::CheckpointMissing);
+                }
+
+                let entry = JournalEntry {
+                    sequence: parse_u64(sequence)?,
+                    recorded_at: parse_u64(recorded_at)?,
+                    kind: EntryKind::parse(kind)?,
+                    reference: parse_hash(reference)?,
+                    signature: parse_signature(signature)?,
+                    previous: parse_hash(previous)?,
+                };
+                if entry.hash() != parse_hash(hash)? {
+                    return Err(Error::JournalCorrupted);
+                }
+                if entry.sequence != summary.entries || entry.previous != summary.head {
+                    return Err(Error::JournalReordered);
+                }
+
+                summary.entries += 1;
+                summary.head = entry.hash();
+            }
+            ["checkpoint", entries, at, head, signature] => {
+                let checkpoint = Checkpoint {
+                    entries: parse_u64(entries)?,
+                    at: parse_u64(at)?,
+                    head: parse_hash(head)?,
+                    signature: Signature::from_bytes(&from_hex(signature)?).map_err(|_| Error::JournalMalformed)?,
+                };
+                checkpoint.verify(&self.bank_key)?;
+                if checkpoint.entries != summary.entries || checkpoint.head != summary.head {
+                    return Err(Error::CheckpointMismatch);
+                }
+
+                summary.last_checkpoint = Some(checkpoint);
+            }
+            _ => return Err(Error::JournalMalformed),
+        }
+        Ok(())
+    }
+}
+
+impl JournalSummary {
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    /// Returns the hash of the last entry.
+    pub fn head(&self) -> &[u8; 32] {
+        &self.head
+    }
+
+    pub fn last_checkpoint(&self) -> Option<&Checkpoint> {
+        self.last_checkpoint.as_ref()
+    }
+
+    /// Returns how many entries at the end are not covered by a checkpoint.
+    pub fn unattested(&self) -> u64 {
+        self.entries - self.last_checkpoint.as_ref().map_or(0, Checkpoint::entries)
+    }
+}
+
+impl JournalFault {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn error(&self) -> Error {
+        self.error
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, Error> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(Error::JournalMalformed);
+    }
+
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| Error::JournalMalformed))
+        .collect()
+}
+
+fn parse_hash(hex: &str) -> Result<[u8; 32], Error> {
+    from_hex(hex)?.try_into().map_err(|_| Error::JournalMalformed)
+}
+
+fn parse_signature(hex: &str) -> Result<Option<Signature>, Error> {
+    if hex == "-" {
+        return Ok(None);
+    }
+    Signature::from_bytes(&from_hex(hex)?).map(Some).map_err(|_| Error::JournalMalformed)
+}
+
+fn parse_u64(value: &str) -> Result<u64, Error> {
+    // leading zeros or signs would give several encodings of the same number
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) || (value.len() > 1 && value.starts_with('0')) {
+        return Err(Error::JournalMalformed);
+    }
+    value.parse().map_err(|_| Error::JournalMalformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::bank::{Decision, DisputeStep, MultisigPolicy, ReasonCode};
+    use crate::iban::parse_valid;
+    use crate::merchant::Merchant;
+    use crate::token::{Denomination, MintKey};
+    use crate::traits::TransactionSign;
+    use crate::transaction::{CreditTransfer, PayoutBatch, Transaction};
+    use crate::user::Allowance;
+
+    fn journal(key: &BankKey) -> Result<Vec<String>, Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 10_000)?;
+
+        let mut journal = Journal::new(key.clone(), 2);
+        for at in 1..=5 {
+            let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), at))?;
+            let entry = journal.apply(&mut ledger, &signed, at)?;
+            assert_eq!(entry.sequence(), at - 1);
+        }
+        assert_eq!(journal.checkpoints().len(), 2);
+
+        let mut buf = Vec::new();
+        journal.write_to(&mut buf)?;
+        let text = String::from_utf8(buf).map_err(|_| Error::JournalMalformed)?;
+        Ok(text.lines().map(str::to_string).collect())
+    }
+
+    fn verify(key: &BankKey, lines: &[String]) -> Result<JournalSummary, JournalFault> {
+        JournalVerifier::new(*key.verifying_key(), 2).verify(lines.join("\n").as_bytes())
+    }
+
+    fn fault(line: usize, error: Error) -> Result<JournalSummary, JournalFault> {
+        Err(JournalFault { line, error })
+    }
+
+    #[test]
+    fn verify_journal() -> Result<(), Error> {
+        let key = BankKey::new();
+        let lines = journal(&key)?;
+        // five entries, with a checkpoint after the second and the fourth
+        assert_eq!(lines.len(), 7);
+        assert!(lines[2].starts_with("checkpoint 2 ") && lines[5].starts_with("checkpoint 4 "));
+
+        let summary = verify(&key, &lines).map_err(|fault| fault.error())?;
+        assert_eq!((summary.entries(), summary.unattested()), (5, 1));
+        assert_eq!(summary.last_checkpoint().map(Checkpoint::at), Some(4));
+
+        assert_eq!(verify(&BankKey::new(), &lines), fault(3, Error::InvalidSignature));
+        Ok(())
+    }
+
+    #[test]
+    fn missing_checkpoints() -> Result<(), Error> {
+        let key = BankKey::new();
+        let lines = journal(&key)?;
+
+        let mut stripped = lines.clone();
+        stripped.retain(|line| !line.starts_with("checkpoint"));
+        assert_eq!(verify(&key, &stripped), fault(3, Error::CheckpointMissing));
+
+        // a checkpoint left out at the end
+        assert_eq!(verify(&key, &lines[..5]), fault(6, Error::CheckpointMissing));
+        verify(&key, &lines[..6]).map_err(|fault| fault.error())?;
+
+        // a journal checkpointed at another interval
+        let verifier = JournalVerifier::new(*key.verifying_key(), 3);
+        assert_eq!(verifier.verify(lines.join("\n").as_bytes()), fault(5, Error::CheckpointMissing));
+        Ok(())
+    }
+
+    #[test]
+    fn every_change() -> Result<(), Error> {
+        let key = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let merchant_account = parse_valid(merchant.account_number())?;
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 20_000)?;
+        let mut journal = Journal::new(key.clone(), 0);
+
+        let mut payments = PaymentBook::new();
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 1))?;
+        journal.settle(&mut payments, &mut ledger, &signed, 1)?;
+        journal.refund_leg(&mut payments, &mut ledger, &merchant.refund_leg(&signed, 0, 400)?, 2)?;
+
+        let mut escrows = EscrowBook::new();
+        let (release, refund) = (Condition::SignedBy(*merchant.verifying_key()), Condition::SignedBy(*merchant.verifying_key()));
+        let escrowed = user.sign(Transaction::new(2_000, Currency::EUR, merchant.clone(), user.clone(), 3).for_escrow(&release, &refund))?;
+        let id = journal.open_escrow(&mut escrows, &mut ledger, &escrowed, release, refund, 3)?;
+        journal.refund_escrow(&mut escrows, &mut ledger, &id, &[merchant.approve_escrow(&id, Decision::Refund)?], 4)?;
+
+        let mut tokens = TokenBook::new();
+        let five = Denomination::new(5_000, Currency::EUR);
+        tokens.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0)));
+        let token_key = tokens.token_key(&five).ok_or(Error::UnknownDenomination)?.clone();
+        let (pending, withdrawal) = user.withdraw_token(&token_key)?;
+        let token = pending.finish(&token_key, &journal.withdraw_token(&mut tokens, &mut ledger, &withdrawal, 5)?)?;
+        journal.deposit_token(&mut tokens, &mut ledger, &token, &merchant_account, 6)?;
+
+        let mut offline = OfflineBook::with_upload_window(key.clone(), 0);
+        let allowance = journal.issue_allowance(&mut offline, &mut ledger, &user, Currency::EUR, 3_000, 7, 10, 7)?;
+        journal.reconcile_allowance(&mut offline, &mut ledger, &allowance.id(), 10)?;
+
+        // failed changes are not recorded
+        assert_eq!(journal.refund_escrow(&mut escrows, &mut ledger, &id, &[], 11), Err(Error::EscrowClosed));
+        assert_eq!(journal.withdraw_token(&mut tokens, &mut ledger, &withdrawal, 11), Err(Error::DuplicateWithdrawal));
+
+        let kinds = journal.entries().iter().map(JournalEntry::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            EntryKind::Payment,
+            EntryKind::LegRefunded,
+            EntryKind::EscrowOpened,
+            EntryKind::EscrowRefunded,
+            EntryKind::TokenWithdrawn,
+            EntryKind::TokenDeposited,
+            EntryKind::AllowanceIssued,
+            EntryKind::AllowanceReconciled,
+        ]);
+        assert_eq!(journal.entries()[4].reference(), &withdrawal.id());
+        assert_eq!(journal.entries()[5].signature(), None);
+        assert_eq!(ledger.balance(&merchant_account, Currency::EUR), 5_600);
+
+        let mut buf = Vec::new();
+        journal.write_to(&mut buf)?;
+        let summary = JournalVerifier::new(*key.verifying_key(), 0).verify(buf.as_slice()).map_err(|fault| fault.error())?;
+        assert_eq!((summary.entries(), summary.head()), (8, &journal.head()));
+        Ok(())
+    }
+
+    #[test]
+    fn every_book() -> Result<(), Error> {
+        let key = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let device = User::new(*user.account_number());
+        let company = User::new("FR1420041010050500013M02606".parse()?);
+        let officer = User::new(*company.account_number());
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let suspense = parse_valid("AT611904300234573201")?;
+        let mut ledger = Ledger::new();
+        for account in [&user, &company] {
+            ledger.keys_mut().enroll(account, 0, None)?;
+            ledger.deposit(account.account_number(), Currency::EUR, 20_000)?;
+        }
+        ledger.deposit(&suspense, Currency::EUR, 100_000)?;
+        let mut journal = Journal::new(key.clone(), 0);
+
+        let mut settlements = SettlementBook::new(key.clone(), 0);
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 1))?;
+        journal.apply_for_settlement(&mut settlements, &mut ledger, &signed, 1)?;
+
+        let mut delegations = DelegationBook::new();
+        let certificate = user.delegate(device.verifying_key(), Allowance::new(2_000, Currency::EUR, 100))?;
+        let delegated = device.sign_delegated(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 2), vec![certificate])?;
+        journal.apply_delegated(&mut delegations, &mut ledger, &delegated, 2)?;
+
+        let mut accounts = MultisigAccounts::new();
+        accounts.register(&mut ledger, *company.account_number(), MultisigPolicy::new(1, vec![*officer.verifying_key()])?);
+        let mut partial = PartiallySignedTransaction::new(Transaction::new(1_000, Currency::EUR, merchant.clone(), company.clone(), 3));
+        officer.cosign(&mut partial)?;
+        journal.apply_cosigned(&mut accounts, &mut ledger, &partial, 3)?;
+
+        let mut payouts = PayoutBook::new();
+        let transfers = vec![CreditTransfer::new("AT611904300234573201", 500, Currency::EUR, "payout".to_string())?];
+        let batch = merchant.sign_payout_batch(PayoutBatch::new(merchant.clone(), transfers, 4))?;
+        assert_eq!(journal.apply_payouts(&mut payouts, &mut ledger, &batch, 4)?.failed(), []);
+
+        let mut payments = PaymentBook::new();
+        let mut disputes = DisputeBook::new(&key, suspense);
+        let mut open = Vec::new();
+        for at in [5, 6] {
+            let signed = user.sign(Transaction::new(2_000, Currency::EUR, merchant.clone(), user.clone(), at))?;
+            journal.settle(&mut payments, &mut ledger, &signed, at)?;
+            let step = user.open_dispute(&signed, 0, 2_000, ReasonCode::NotReceived, at)?;
+            open.push(journal.open_dispute(&mut disputes, &mut ledger, &mut payments, &step, at)?);
+        }
+        let accepted = merchant.respond_to_dispute(&open[0], DisputeStep::AcceptLiability, 7)?;
+        journal.submit_dispute_step(&mut disputes, &mut ledger, &mut payments, &accepted, 7)?;
+        let deadline = disputes.get(&open[1]).ok_or(Error::DevError)?.deadline();
+        assert_eq!(journal.expire_disputes(&mut disputes, &mut ledger, &mut payments, deadline + 1)?, [open[1]]);
+
+        // failed changes are not recorded
+        assert_eq!(journal.apply_cosigned(&mut accounts, &mut ledger, &partial, 8), Err(Error::DuplicateTransaction));
+        assert_eq!(
+            journal.submit_dispute_step(&mut disputes, &mut ledger, &mut payments, &accepted, 8),
+            Err(Error::InvalidDisputeStep)
+        );
+
+        let kinds = journal.entries().iter().map(JournalEntry::kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [
+            EntryKind::Payment,
+            EntryKind::DelegatedPayment,
+            EntryKind::CosignedPayment,
+            EntryKind::PayoutApplied,
+            EntryKind::Payment,
+            EntryKind::DisputeOpened,
+            EntryKind::Payment,
+            EntryKind::DisputeOpened,
+            EntryKind::DisputeStepSubmitted,
+            EntryKind::DisputeExpired,
+        ]);
+        assert_eq!(journal.entries()[3].reference(), &batch.batch().id());
+        assert_eq!(journal.entries()[9].reference(), &open[1]);
+
+        let mut buf = Vec::new();
+        journal.write_to(&mut buf)?;
+        let summary = JournalVerifier::new(*key.verifying_key(), 0).verify(buf.as_slice()).map_err(|fault| fault.error())?;
+        assert_eq!(summary.entries(), 10);
+        Ok(())
+    }
+
+    #[test]
+    fn pinpoint_tampering() -> Result<(), Error> {
+        let key = BankKey::new();
+        let lines = journal(&key)?;
+
+        // a changed entry no longer hashes to what it claims
+        let mut tampered = lines.clone();
+        tampered[3] = tampered[3].replacen(" 3 ", " 9 ", 1);
+        assert_eq!(verify(&key, &tampered), fault(4, Error::JournalCorrupted));
+
+        // swapped entries come out of sequence
+        let mut reordered = lines.clone();
+        reordered.swap(3, 4);
+        assert_eq!(verify(&key, &reordered), fault(4, Error::JournalReordered));
+
+        // a removed entry leaves a gap
+        let mut removed = lines.clone();
+        removed.remove(3);
+        assert_eq!(verify(&key, &removed), fault(4, Error::JournalReordered));
+
+        // rewriting an entry along with all hashes after it is caught by the next checkpoint
+        let rewritten = rewrite(&lines[..2], 1, 9)?;
+        assert_eq!(verify(&key, &[rewritten, lines[2..].to_vec()].concat()), fault(3, Error::CheckpointMismatch));
+
+        let mut malformed = lines;
+        malformed[6].push_str(" extra");
+        assert_eq!(verify(&key, &malformed), fault(7, Error::JournalMalformed));
+        Ok(())
+    }
+
+    // changes when the entry at `index` was recorded and recomputes the hashes from there on
+    fn rewrite(lines: &[String], index: usize, recorded_at: Timestamp) -> Result<Vec<String>, Error> {
+        let mut previous = GENESIS_HASH;
+        let mut rewritten = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let entry = JournalEntry {
+                sequence: parse_u64(fields[1])?,
+                recorded_at: if i == index { recorded_at } else { parse_u64(fields[2])? },
+                kind: EntryKind::parse(fields[3])?,
+                reference: parse_hash(fields[4])?,
+                signature: parse_signature(fields[5])?,
+                previous,
+            };
+            previous = entry.hash();
+            rewritten.push(entry.to_line());
+        }
+        Ok(rewritten)
+    }
+}
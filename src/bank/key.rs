@@ -0,0 +1,122 @@
+use p256::ecdsa::{signature::Signer, SigningKey};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+
+use super::{
+    AccountCertificate, DisputeId, DisputeStep, IssuerCertificate, Liabilities, LiabilityStatement, Settlement, SignedDisputeStep,
+    SignedSettlement,
+};
+use crate::crypto::{PublicKey, Signature};
+use crate::user::User;
+use crate::{Error, Timestamp};
+
+/// The key a bank signs its own statements and decisions with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BankKey {
+    signing_key: Option<SigningKey>,
+    verifying_key: PublicKey,
+}
+
+impl BankKey {
+    pub fn new() -> Self {
+        Self::from_rng(&mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Creates a bank key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(rng: &mut impl CryptoRngCore) -> Self {
+        let signing_key = SigningKey::random(rng);
+        let verifying_key = PublicKey::from(*signing_key.verifying_key());
+
+        BankKey { signing_key: Some(signing_key), verifying_key }
+    }
+
+    /// Creates a bank key that can only verify, as seen by users and merchants.
+    pub fn from_verifying_key(verifying_key: PublicKey) -> Self {
+        BankKey { signing_key: None, verifying_key }
+    }
+
+    /// Returns a copy of `self` without the signing key.
+    pub fn to_public(&self) -> Self {
+        Self::from_verifying_key(self.verifying_key)
+    }
+
+    pub fn verifying_key(&self) -> &PublicKey {
+        &self.verifying_key
+    }
+
+    /// Decides a dispute that went to arbitration.
+    pub fn arbitrate(&self, id: &DisputeId, in_favor_of_user: bool, at: Timestamp) -> Result<SignedDisputeStep, Error> {
+        let step = DisputeStep::Arbitrate { in_favor_of_user };
+        let signature = self.sign_bytes(&SignedDisputeStep::signed_bytes(id, &step, at))?;
+        Ok(SignedDisputeStep::new(*id, step, at, self.verifying_key, signature))
+    }
+
+    /// Certifies that the key of `user` may debit its account, held by
+    /// `holder`, from `valid_from` until just before `valid_until`.
+    pub fn certify_account(
+        &self,
+        user: &User,
+        holder: &str,
+        valid_from: Timestamp,
+        valid_until: Timestamp,
+    ) -> Result<AccountCertificate, Error> {
+        AccountCertificate::check_holder(holder)?;
+
+        let key_id = user.verifying_key().id();
+        let issuer = self.verifying_key.id();
+        let message = AccountCertificate::signed_bytes(&key_id, user.account_number(), holder, valid_from, valid_until, &issuer);
+        let signature = self.sign_bytes(&message)?;
+
+        Ok(AccountCertificate::new(key_id, *user.account_number(), holder.to_string(), valid_from, valid_until, issuer, signature))
+    }
+
+    /// Allows `key` to issue certificates from `valid_from` until just before `valid_until`.
+    pub fn certify_issuer(&self, key: &PublicKey, valid_from: Timestamp, valid_until: Timestamp) -> Result<IssuerCertificate, Error> {
+        let issuer = self.verifying_key.id();
+        let signature = self.sign_bytes(&IssuerCertificate::signed_bytes(key, valid_from, valid_until, &issuer))?;
+
+        Ok(IssuerCertificate::new(*key, valid_from, valid_until, issuer, signature))
+    }
+
+    /// Signs the root of a settlement, so it can be published.
+    pub fn sign_settlement(&self, settlement: Settlement) -> Result<SignedSettlement, Error> {
+        let bytes = Settlement::signed_bytes(
+            &self.verifying_key,
+            settlement.start(),
+            settlement.end(),
+            settlement.leaves().len(),
+            &settlement.root(),
+        );
+        let signature = self.sign_bytes(&bytes)?;
+
+        Ok(SignedSettlement::new(settlement, self.verifying_key, signature))
+    }
+
+    /// Signs the root and total of the bank's liabilities in one currency, so they can be published.
+    pub fn sign_liabilities(&self, liabilities: &Liabilities) -> Result<LiabilityStatement, Error> {
+        let bytes = Liabilities::signed_bytes(
+            &self.verifying_key,
+            liabilities.currency(),
+            liabilities.at(),
+            liabilities.leaf_count() as u64,
+            liabilities.total(),
+            &liabilities.root(),
+        );
+        let signature = self.sign_bytes(&bytes)?;
+
+        Ok(LiabilityStatement::new(liabilities, self.verifying_key, signature))
+    }
+
+    pub(crate) fn sign_bytes(&self, message: &[u8]) -> Result<Signature, Error> {
+        let signing_key = self.signing_key.as_ref().ok_or(Error::NoPrivateKey)?;
+        let signature: p256::ecdsa::Signature = signing_key.sign(message);
+        Ok(signature.into())
+    }
+}
+
+impl Default for BankKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,481 @@
+use std::collections::{HashMap, HashSet};
+
+use super::VerificationPolicy;
+use crate::crypto::PublicKey;
+use crate::transaction::SignedTransaction;
+use crate::time::WEEK;
+use crate::user::{
+    GuardianAppointment, Guardians, KeyAuthority, KeyRevocation, KeyRotation, RecoveryCancellation, RecoveryId, RecoveryRequest, User,
+};
+use crate::{Error, Timestamp, IBAN};
+
+/// How long a guardian recovery waits by default before it takes effect.
+pub const DEFAULT_RECOVERY_DELAY: Timestamp = WEEK;
+
+/// How long after the guardians approved it a recovery may be requested.
+pub const MAX_RECOVERY_AGE: Timestamp = WEEK;
+
+/// The time span in which a key was the key of an account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPeriod {
+    key: PublicKey,
+    valid_from: Timestamp,
+    // set once the key was rotated away from
+    valid_until: Option<Timestamp>,
+    revoked_at: Option<Timestamp>,
+}
+
+impl KeyPeriod {
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    pub fn valid_from(&self) -> Timestamp {
+        self.valid_from
+    }
+
+    pub fn valid_until(&self) -> Option<Timestamp> {
+        self.valid_until
+    }
+
+    pub fn revoked_at(&self) -> Option<Timestamp> {
+        self.revoked_at
+    }
+
+    fn is_valid_at(&self, at: Timestamp) -> bool {
+        let end = match (self.valid_until, self.revoked_at) {
+            (Some(until), Some(revoked)) => until.min(revoked),
+            (until, revoked) => until.or(revoked).unwrap_or(Timestamp::MAX),
+        };
+
+        self.valid_from <= at && at < end
+    }
+}
+
+/// Every key an account ever had, so that signatures can be checked
+/// against the key that was valid when they were made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyHistory {
+    recovery_key: Option<PublicKey>,
+    guardians: Option<Guardians>,
+    periods: Vec<KeyPeriod>,
+    rotations: Vec<KeyRotation>,
+    recoveries: Vec<RecoveryRequest>,
+}
+
+/// A recovery approved by the guardians, waiting for its time lock to pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingRecovery {
+    request: RecoveryRequest,
+    effective_at: Timestamp,
+}
+
+impl PendingRecovery {
+    pub fn request(&self) -> &RecoveryRequest {
+        &self.request
+    }
+
+    /// Returns the time from which the recovery can be completed.
+    pub fn effective_at(&self) -> Timestamp {
+        self.effective_at
+    }
+}
+
+impl KeyHistory {
+    pub fn recovery_key(&self) -> Option<&PublicKey> {
+        self.recovery_key.as_ref()
+    }
+
+    pub fn guardians(&self) -> Option<&Guardians> {
+        self.guardians.as_ref()
+    }
+
+    pub fn periods(&self) -> &[KeyPeriod] {
+        &self.periods
+    }
+
+    /// Returns the signed rotations that led to the current key.
+    pub fn rotations(&self) -> &[KeyRotation] {
+        &self.rotations
+    }
+
+    /// Returns the completed guardian recoveries.
+    pub fn recoveries(&self) -> &[RecoveryRequest] {
+        &self.recoveries
+    }
+
+    /// Returns the key that was valid at `at`, if any.
+    pub fn key_at(&self, at: Timestamp) -> Option<&PublicKey> {
+        self.periods.iter().find(|period| period.is_valid_at(at)).map(|period| &period.key)
+    }
+
+    fn current(&mut self) -> &mut KeyPeriod {
+        // there is always at least the key the account was enrolled with
+        self.periods.last_mut().expect("key history is never empty")
+    }
+
+    fn replace_key(&mut self, key: PublicKey, at: Timestamp) {
+        self.current().valid_until = Some(at);
+        self.periods.push(KeyPeriod { key, valid_from: at, valid_until: None, revoked_at: None });
+    }
+}
+
+/// The key histories of all accounts of a bank.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRegistry {
+    accounts: HashMap<IBAN, KeyHistory>,
+    // the old key can cancel a recovery during this time
+    recovery_delay: Timestamp,
+    pending: HashMap<IBAN, PendingRecovery>,
+    cancelled: HashSet<RecoveryId>,
+    policy: VerificationPolicy,
+}
+
+impl Default for KeyRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyRegistry {
+    pub fn new() -> Self {
+        Self::with_recovery_delay(DEFAULT_RECOVERY_DELAY)
+    }
+
+    /// Creates a registry in which guardian recoveries only take effect
+    /// `recovery_delay` after they were requested.
+    pub fn with_recovery_delay(recovery_delay: Timestamp) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            recovery_delay,
+            pending: HashMap::new(),
+            cancelled: HashSet::new(),
+            policy: VerificationPolicy::default(),
+        }
+    }
+
+    /// Only verifies transactions signed with algorithms `policy` accepts.
+    pub fn with_policy(mut self, policy: VerificationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn history(&self, account: &IBAN) -> Option<&KeyHistory> {
+        self.accounts.get(account)
+    }
+
+    /// Registers the key of a user as valid from `valid_from` on, along
+    /// with an optional recovery key that may rotate or revoke it later.
+    pub fn enroll(&mut self, user: &User, valid_from: Timestamp, recovery_key: Option<PublicKey>) -> Result<(), Error> {
+        let account = *user.account_number();
+        if self.accounts.contains_key(&account) {
+            return Err(Error::DuplicateAccount);
+        }
+
+        let period = KeyPeriod { key: *user.verifying_key(), valid_from, valid_until: None, revoked_at: None };
+        self.accounts.insert(account, KeyHistory {
+            recovery_key,
+            guardians: None,
+            periods: vec![period],
+            rotations: Vec::new(),
+            recoveries: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Replaces the current key of an account. Once the key was revoked,
+    /// only the recovery key can replace it.
+    pub fn rotate(&mut self, rotation: &KeyRotation) -> Result<(), Error> {
+        let history = self.accounts.get_mut(rotation.account_number()).ok_or(Error::UnknownAccount)?;
+        rotation.verify(history.recovery_key.as_ref())?;
+
+        let current = history.current();
+        if current.key != *rotation.old_key() {
+            return Err(Error::WrongSigner);
+        }
+        if current.revoked_at.is_some() && rotation.authority() == KeyAuthority::Own {
+            return Err(Error::KeyRevoked);
+        }
+        if rotation.effective_at() < current.valid_from {
+            return Err(Error::KeyNotValid);
+        }
+
+        history.replace_key(*rotation.new_key(), rotation.effective_at());
+        history.rotations.push(rotation.clone());
+        Ok(())
+    }
+
+    /// Revokes a key of an account, so that every signature made with it
+    /// from the revocation time on fails verification.
+    pub fn revoke(&mut self, revocation: &KeyRevocation) -> Result<(), Error> {
+        let history = self.accounts.get_mut(revocation.account_number()).ok_or(Error::UnknownAccount)?;
+        revocation.verify(history.recovery_key.as_ref())?;
+
+        let period = history.periods.iter_mut()
+            .find(|period| period.key == *revocation.key())
+            .ok_or(Error::UnknownSigner)?;
+        period.revoked_at = Some(period.revoked_at.map_or(revocation.revoked_at(), |at| at.min(revocation.revoked_at())));
+        Ok(())
+    }
+
+    /// Sets the guardians of an account, appointed by its current key.
+    pub fn appoint_guardians(&mut self, appointment: &GuardianAppointment) -> Result<(), Error> {
+        let history = self.accounts.get_mut(appointment.account_number()).ok_or(Error::UnknownAccount)?;
+        appointment.verify(&history.current().key)?;
+
+        history.guardians = Some(appointment.guardians().clone());
+        Ok(())
+    }
+
+    /// Returns the recovery waiting to take effect on an account, if any.
+    pub fn pending_recovery(&self, account: &IBAN) -> Option<&PendingRecovery> {
+        self.pending.get(account)
+    }
+
+    /// Starts a recovery approved by enough guardians. It only takes effect
+    /// once the recovery delay has passed, so the old key can still cancel it.
+    ///
+    /// Requests made more than [`MAX_RECOVERY_AGE`] before `now`, or after
+    /// it, are refused, as are requests the old key cancelled before.
+    pub fn request_recovery(&mut self, request: &RecoveryRequest, now: Timestamp) -> Result<(), Error> {
+        if request.requested_at() > now || now - request.requested_at() > MAX_RECOVERY_AGE {
+            return Err(Error::RecoveryExpired);
+        }
+        if self.cancelled.contains(&request.id()) {
+            return Err(Error::RecoveryCancelled);
+        }
+
+        let account = *request.account_number();
+        let history = self.accounts.get_mut(&account).ok_or(Error::UnknownAccount)?;
+        history.guardians.as_ref().ok_or(Error::NoGuardians)?.verify(request)?;
+
+        if history.current().key != *request.old_key() {
+            return Err(Error::WrongSigner);
+        }
+        if self.pending.contains_key(&account) {
+            return Err(Error::RecoveryPending);
+        }
+
+        let effective_at = now.saturating_add(self.recovery_delay);
+        self.pending.insert(account, PendingRecovery { request: request.clone(), effective_at });
+        Ok(())
+    }
+
+    /// Cancels a pending recovery on behalf of the key it would replace, for good.
+    pub fn cancel_recovery(&mut self, cancellation: &RecoveryCancellation) -> Result<(), Error> {
+        let (account, pending) = self.pending.iter()
+            .find(|(_, pending)| pending.request.id() == *cancellation.recovery_id())
+            .ok_or(Error::UnknownRecovery)?;
+        cancellation.verify(pending.request.old_key())?;
+
+        let account = *account;
+        self.pending.remove(&account);
+        self.cancelled.insert(*cancellation.recovery_id());
+        Ok(())
+    }
+
+    /// Replaces the key of an account by the one of its pending recovery,
+    /// once the recovery delay has passed.
+    pub fn complete_recovery(&mut self, account: &IBAN, now: Timestamp) -> Result<(), Error> {
+        let pending = self.pending.get(account).ok_or(Error::UnknownRecovery)?;
+        if now < pending.effective_at {
+            return Err(Error::RecoveryLocked);
+        }
+
+        let pending = self.pending.remove(account).ok_or(Error::UnknownRecovery)?;
+        let history = self.accounts.get_mut(account).ok_or(Error::UnknownAccount)?;
+        // the key was rotated in the meantime, which the old key could only
+        // do if it was not lost after all
+        if history.current().key != *pending.request.old_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        history.replace_key(*pending.request.new_key(), pending.effective_at);
+        history.recoveries.push(pending.request);
+        Ok(())
+    }
+
+    /// Verifies a signed transaction, and that the key of its user was the
+    /// valid key of the account at `received_at`, when the bank received it.
+    ///
+    /// The signer picks the transaction's own timestamp, so a replaced or
+    /// revoked key could otherwise keep signing by backdating.
+    pub fn verify(&self, signed: &SignedTransaction, received_at: Timestamp) -> Result<(), Error> {
+        self.policy.verify(signed)?;
+
+        let user = signed.transaction().user();
+        self.check_key(user.account_number(), user.verifying_key(), received_at)
+    }
+
+    /// Checks that `key` was the valid key of `account` at `at`.
+    pub(crate) fn check_key(&self, account: &IBAN, key: &PublicKey, at: Timestamp) -> Result<(), Error> {
+        let history = self.accounts.get(account).ok_or(Error::UnknownAccount)?;
+        if history.key_at(at) != Some(key) {
+            return Err(Error::KeyNotValid);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+
+    fn pay(user: &User, at: Timestamp) -> Result<SignedTransaction, Error> {
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), at))
+    }
+
+    #[test]
+    fn old_signatures_stay_valid() -> Result<(), Error> {
+        let old = User::new("DE89370400440532013000".parse()?);
+        let mut registry = KeyRegistry::new();
+        registry.enroll(&old, 0, None)?;
+
+        let (new, rotation) = old.rotate_key(100)?;
+        registry.rotate(&rotation)?;
+
+        registry.verify(&pay(&old, 99)?, 99)?;
+        registry.verify(&pay(&new, 100)?, 100)?;
+        assert_eq!(registry.verify(&pay(&old, 100)?, 100), Err(Error::KeyNotValid));
+        assert_eq!(registry.verify(&pay(&new, 99)?, 99), Err(Error::KeyNotValid));
+
+        // the old key can't go on signing by backdating
+        assert_eq!(registry.verify(&pay(&old, 50)?, 150), Err(Error::KeyNotValid));
+        registry.verify(&pay(&new, 50)?, 150)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_after_compromise() -> Result<(), Error> {
+        let lost = User::new("DE89370400440532013000".parse()?);
+        let recovery = User::new("DE89370400440532013000".parse()?);
+        let replacement = User::new("DE89370400440532013000".parse()?);
+        let mut registry = KeyRegistry::new();
+        registry.enroll(&lost, 0, Some(*recovery.verifying_key()))?;
+
+        registry.revoke(&recovery.revoke_key(lost.account_number(), lost.verifying_key(), 50)?)?;
+        registry.verify(&pay(&lost, 49)?, 49)?;
+        assert_eq!(registry.verify(&pay(&lost, 50)?, 50), Err(Error::KeyNotValid));
+
+        let rotation = recovery.recover_key(lost.verifying_key(), replacement.verifying_key(), 60)?;
+        registry.rotate(&rotation)?;
+        registry.verify(&pay(&replacement, 60)?, 60)?;
+
+        // only the current key or the recovery key can rotate
+        let stranger = User::new("DE89370400440532013000".parse()?);
+        assert_eq!(
+            registry.rotate(&stranger.recover_key(replacement.verifying_key(), stranger.verifying_key(), 70)?),
+            Err(Error::InvalidSignature)
+        );
+        registry.rotate(&replacement.rotate_key(70)?.1)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn revoked_key_can_not_rotate() -> Result<(), Error> {
+        let lost = User::new("DE89370400440532013000".parse()?);
+        let recovery = User::new("DE89370400440532013000".parse()?);
+        let mut registry = KeyRegistry::new();
+        registry.enroll(&lost, 0, Some(*recovery.verifying_key()))?;
+        registry.revoke(&recovery.revoke_key(lost.account_number(), lost.verifying_key(), 50)?)?;
+
+        // whoever holds the revoked key can't rotate to a key of their own
+        let (thief, rotation) = lost.rotate_key(60)?;
+        assert_eq!(registry.rotate(&rotation), Err(Error::KeyRevoked));
+        assert_eq!(registry.verify(&pay(&thief, 60)?, 60), Err(Error::KeyNotValid));
+        assert_eq!(registry.verify(&pay(&lost, 40)?, 60), Err(Error::KeyNotValid));
+
+        Ok(())
+    }
+
+    fn guarded(registry: &mut KeyRegistry, user: &User, guardians: &[User]) -> Result<(), Error> {
+        registry.enroll(user, 0, None)?;
+        let keys = guardians.iter().map(|guardian| *guardian.verifying_key()).collect();
+        registry.appoint_guardians(&user.appoint_guardians(Guardians::new(2, keys)?)?)
+    }
+
+    #[test]
+    fn guardian_recovery() -> Result<(), Error> {
+        let lost = User::new("DE89370400440532013000".parse()?);
+        let replacement = User::new("DE89370400440532013000".parse()?);
+        let guardian_account = "GB82WEST12345698765432".parse()?;
+        let guardians: Vec<_> = (0..3).map(|_| User::new(guardian_account)).collect();
+        let mut registry = KeyRegistry::with_recovery_delay(100);
+        guarded(&mut registry, &lost, &guardians)?;
+
+        let mut request = RecoveryRequest::new(
+            *lost.account_number(),
+            *lost.verifying_key(),
+            *replacement.verifying_key(),
+            10,
+        );
+        guardians[0].approve_recovery(&mut request)?;
+        assert_eq!(registry.request_recovery(&request, 10), Err(Error::NotEnoughSignatures));
+        assert_eq!(guardians[0].approve_recovery(&mut request), Err(Error::DuplicateSigner));
+        guardians[2].approve_recovery(&mut request)?;
+        // approvals don't stay usable forever
+        assert_eq!(registry.request_recovery(&request, 11 + MAX_RECOVERY_AGE), Err(Error::RecoveryExpired));
+        assert_eq!(registry.request_recovery(&request, 9), Err(Error::RecoveryExpired));
+        registry.request_recovery(&request, 10)?;
+
+        let account = *lost.account_number();
+        assert_eq!(registry.complete_recovery(&account, 109), Err(Error::RecoveryLocked));
+        registry.complete_recovery(&account, 110)?;
+
+        registry.verify(&pay(&lost, 109)?, 109)?;
+        registry.verify(&pay(&replacement, 110)?, 110)?;
+        assert_eq!(registry.verify(&pay(&lost, 110)?, 110), Err(Error::KeyNotValid));
+        assert_eq!(registry.history(&account).map(|history| history.recoveries().len()), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn old_key_cancels_recovery() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let attacker = User::new("DE89370400440532013000".parse()?);
+        let guardian_account = "GB82WEST12345698765432".parse()?;
+        let guardians: Vec<_> = (0..3).map(|_| User::new(guardian_account)).collect();
+        let mut registry = KeyRegistry::new();
+        guarded(&mut registry, &user, &guardians)?;
+
+        let mut request = RecoveryRequest::new(
+            *user.account_number(),
+            *user.verifying_key(),
+            *attacker.verifying_key(),
+            0,
+        );
+        guardians[0].approve_recovery(&mut request)?;
+        attacker.approve_recovery(&mut request)?;
+        assert_eq!(registry.request_recovery(&request, 0), Err(Error::UnknownSigner));
+
+        let mut request = RecoveryRequest::new(
+            *user.account_number(),
+            *user.verifying_key(),
+            *attacker.verifying_key(),
+            0,
+        );
+        guardians[0].approve_recovery(&mut request)?;
+        guardians[1].approve_recovery(&mut request)?;
+        registry.request_recovery(&request, 0)?;
+
+        assert_eq!(attacker.cancel_recovery(&request), Err(Error::WrongSigner));
+        registry.cancel_recovery(&user.cancel_recovery(&request)?)?;
+
+        // the same approvals can't start the recovery again
+        assert_eq!(registry.request_recovery(&request, 0), Err(Error::RecoveryCancelled));
+
+        let account = *user.account_number();
+        assert_eq!(registry.complete_recovery(&account, DEFAULT_RECOVERY_DELAY), Err(Error::UnknownRecovery));
+        registry.verify(&pay(&user, DEFAULT_RECOVERY_DELAY)?, DEFAULT_RECOVERY_DELAY)?;
+
+        Ok(())
+    }
+}
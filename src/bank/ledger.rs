@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{KeyRegistry, VerificationPolicy};
+use crate::crypto::PublicKey;
+use crate::transaction::{Currency, Purpose, SignedTransaction, Transaction, TransactionId};
+use crate::iban::parse_valid;
+use crate::{Error, Timestamp, IBAN};
+
+/// The balances of all accounts held at a bank, per currency.
+///
+/// Amounts are counted in thousandths, like `Transaction::amount`. Only the
+/// key registered for an account in [`Ledger::keys`] can pay from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ledger {
+    balances: HashMap<(IBAN, Currency), u64>,
+    // accounts whose payments need the approvals of their multisig policy
+    multisig: HashSet<IBAN>,
+    policy: VerificationPolicy,
+    keys: KeyRegistry,
+    applied: HashSet<TransactionId>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a ledger that only applies transactions signed with
+    /// algorithms `policy` accepts.
+    pub fn with_policy(policy: VerificationPolicy) -> Self {
+        Self { keys: KeyRegistry::new().with_policy(policy.clone()), policy, ..Self::default() }
+    }
+
+    pub fn policy(&self) -> &VerificationPolicy {
+        &self.policy
+    }
+
+    /// Returns the keys of the accounts, which decide who may pay from them.
+    pub fn keys(&self) -> &KeyRegistry {
+        &self.keys
+    }
+
+    /// Returns the keys of the accounts, to enroll, rotate or revoke them.
+    pub fn keys_mut(&mut self) -> &mut KeyRegistry {
+        &mut self.keys
+    }
+
+    /// Checks that `key` may pay from `account` at `at`, the time the bank
+    /// received the request, since anyone can sign for any account number
+    /// with a key of their own. No single key may pay from a multisig account.
+    pub fn authorize(&self, account: &IBAN, key: &PublicKey, at: Timestamp) -> Result<(), Error> {
+        self.check_single_signer(account)?;
+        self.keys.check_key(account, key, at)
+    }
+
+    /// Refuses to debit `account` on a single signature if its payments
+    /// need the approvals of a multisig policy.
+    pub fn check_single_signer(&self, account: &IBAN) -> Result<(), Error> {
+        if self.multisig.contains(account) {
+            return Err(Error::MultisigRequired);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the balance of `account` in `currency`.
+    pub fn balance(&self, account: &IBAN, currency: Currency) -> u64 {
+        self.balances.get(&(*account, currency)).copied().unwrap_or(0)
+    }
+
+    /// Returns every balance the ledger holds, in no particular order.
+    pub fn balances(&self) -> impl Iterator<Item = (&IBAN, Currency, u64)> {
+        self.balances.iter().map(|((account, currency), balance)| (account, *currency, *balance))
+    }
+
+    /// Adds `amount` to the balance of `account`.
+    pub fn deposit(&mut self, account: &IBAN, currency: Currency, amount: u64) -> Result<(), Error> {
+        let balance = self.balances.entry((*account, currency)).or_insert(0);
+        *balance = balance.checked_add(amount).ok_or(Error::AmountOverflow)?;
+        Ok(())
+    }
+
+    /// Adds funds to several accounts. Either all balances change or, on
+    /// error, none do.
+    pub fn deposit_many(&mut self, currency: Currency, credits: &[(IBAN, u64)]) -> Result<(), Error> {
+        let mut staged = HashMap::new();
+        for (to, amount) in credits {
+            let current = staged.get(to).copied().unwrap_or_else(|| self.balance(to, currency));
+            staged.insert(*to, current.checked_add(*amount).ok_or(Error::AmountOverflow)?);
+        }
+
+        for (account, balance) in staged {
+            self.balances.insert((account, currency), balance);
+        }
+        Ok(())
+    }
+
+    /// Removes `amount` from the balance of `account`.
+    pub fn withdraw(&mut self, account: &IBAN, currency: Currency, amount: u64) -> Result<(), Error> {
+        let balance = self.balance(account, currency);
+        if balance < amount {
+            return Err(Error::InsufficientFunds);
+        }
+
+        self.balances.insert((*account, currency), balance - amount);
+        Ok(())
+    }
+
+    /// Moves `amount` from one account to another. Either both balances
+    /// change or, on error, neither does.
+    pub fn transfer(&mut self, from: &IBAN, to: &IBAN, currency: Currency, amount: u64) -> Result<(), Error> {
+        self.transfer_many(from, currency, &[(*to, amount)])
+    }
+
+    /// Moves funds from one account to several others. Either all balances
+    /// change or, on error, none do.
+    pub fn transfer_many(&mut self, from: &IBAN, currency: Currency, credits: &[(IBAN, u64)]) -> Result<(), Error> {
+        let total = credits.iter()
+            .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+            .ok_or(Error::AmountOverflow)?;
+
+        let balance = self.balance(from, currency);
+        if balance < total {
+            return Err(Error::InsufficientFunds);
+        }
+
+        // withdraw first, and put the balance back if one of the credits overflows
+        self.balances.insert((*from, currency), balance - total);
+        if let Err(err) = self.deposit_many(currency, credits) {
+            self.balances.insert((*from, currency), balance);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Verifies a signed transaction received at `received_at` and moves
+    /// its amount from the user's account to the accounts of all merchants
+    /// it pays, atomically. Each transaction is applied once.
+    ///
+    /// Transactions signed through a delegation are refused, as their
+    /// allowances are tracked by a [`DelegationBook`](super::DelegationBook).
+    pub fn apply(&mut self, signed: &SignedTransaction, received_at: Timestamp) -> Result<(), Error> {
+        self.policy.verify(signed)?;
+        if !signed.delegation().is_empty() {
+            return Err(Error::UntrackedDelegation);
+        }
+        let id = signed.id();
+        if self.applied.contains(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let user = signed.transaction().user();
+        self.authorize(user.account_number(), user.verifying_key(), received_at)?;
+        self.book_cosigned(signed.transaction())?;
+
+        self.applied.insert(id);
+        Ok(())
+    }
+
+    /// Refuses transactions paid from `account` unless they were approved
+    /// under its multisig policy.
+    pub(crate) fn require_multisig(&mut self, account: IBAN) {
+        self.multisig.insert(account);
+    }
+
+    /// Moves the amount of an already authorized transaction. Transactions
+    /// signed for anything but a payment, or paid from a multisig account,
+    /// are refused.
+    pub(crate) fn book(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        self.check_single_signer(transaction.user().account_number())?;
+        self.book_cosigned(transaction)
+    }
+
+    /// Like [`Ledger::book`], for transactions approved under the multisig
+    /// policy of the paying account.
+    pub(crate) fn book_cosigned(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        if transaction.purpose() != Purpose::Payment {
+            return Err(Error::WrongPurpose);
+        }
+
+        let from = *transaction.user().account_number();
+        let credits = transaction.legs().iter()
+            .map(|leg| Ok((parse_valid(leg.merchant().account_number())?, leg.amount())))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.transfer_many(&from, transaction.currency(), &credits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::Leg;
+    use crate::user::User;
+
+    #[test]
+    fn transfer() -> Result<(), Error> {
+        let from = IBAN::try_from("DE89370400440532013000")?;
+        let to = IBAN::try_from("GB82WEST12345698765432")?;
+        let mut ledger = Ledger::new();
+
+        ledger.deposit(&from, Currency::EUR, 5_000)?;
+        ledger.transfer(&from, &to, Currency::EUR, 3_000)?;
+        assert_eq!(ledger.balance(&from, Currency::EUR), 2_000);
+        assert_eq!(ledger.balance(&to, Currency::EUR), 3_000);
+
+        assert_eq!(ledger.transfer(&from, &to, Currency::EUR, 3_000), Err(Error::InsufficientFunds));
+        assert_eq!(ledger.transfer(&from, &to, Currency::USD, 1), Err(Error::InsufficientFunds));
+        assert_eq!(ledger.balance(&from, Currency::EUR), 2_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_signed_transaction() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 1_500)?;
+
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 0))?;
+        ledger.apply(&signed, 0)?;
+        // a replayed transaction is not paid again, even if it is covered
+        ledger.deposit(user.account_number(), Currency::EUR, 1_000)?;
+        assert_eq!(ledger.apply(&signed, 0), Err(Error::DuplicateTransaction));
+
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 1_500);
+        assert_eq!(ledger.balance(&parse_valid(merchant.account_number())?, Currency::EUR), 1_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply_split_atomically() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let seller = Merchant::new("GB82WEST12345698765432".to_string());
+        let platform = Merchant::new("FR1420041010050500013M02606".to_string());
+        let legs = vec![Leg::new(seller.clone(), 900), Leg::new(platform.clone(), 100)];
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 1_500)?;
+        ledger.deposit(&parse_valid(platform.account_number())?, Currency::EUR, u64::MAX)?;
+
+        // crediting the platform overflows, so the seller must not be credited either
+        let signed = user.sign(Transaction::split(1_000, Currency::EUR, legs, user.clone(), 0)?)?;
+        assert_eq!(ledger.apply(&signed, 0), Err(Error::AmountOverflow));
+
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 1_500);
+        assert_eq!(ledger.balance(&parse_valid(seller.account_number())?, Currency::EUR), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn foreign_key() -> Result<(), Error> {
+        let victim = User::new("DE89370400440532013000".parse()?);
+        let thief = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&victim, 0, None)?;
+        ledger.deposit(victim.account_number(), Currency::EUR, 1_500)?;
+
+        // a key of their own signs validly for the victim's account number
+        let signed = thief.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), thief.clone(), 0))?;
+        ledger.policy().verify(&signed)?;
+        assert_eq!(ledger.apply(&signed, 0), Err(Error::KeyNotValid));
+
+        // accounts without a registered key can't pay at all
+        let stranger = User::new("FR1420041010050500013M02606".parse()?);
+        ledger.deposit(stranger.account_number(), Currency::EUR, 1_500)?;
+        let signed = stranger.sign(Transaction::new(1_000, Currency::EUR, merchant, stranger.clone(), 0))?;
+        assert_eq!(ledger.apply(&signed, 0), Err(Error::UnknownAccount));
+
+        assert_eq!(ledger.balance(victim.account_number(), Currency::EUR), 1_500);
+        Ok(())
+    }
+
+    #[test]
+    fn rotated_and_revoked_keys() -> Result<(), Error> {
+        let old = User::new("DE89370400440532013000".parse()?);
+        let recovery = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&old, 0, Some(*recovery.verifying_key()))?;
+        ledger.deposit(old.account_number(), Currency::EUR, 5_000)?;
+        let pay = |user: &User, at| user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), at));
+
+        let (new, rotation) = old.rotate_key(100)?;
+        ledger.keys_mut().rotate(&rotation)?;
+        ledger.apply(&pay(&old, 50)?, 50)?;
+        assert_eq!(ledger.apply(&pay(&old, 100)?, 100), Err(Error::KeyNotValid));
+        ledger.apply(&pay(&new, 100)?, 100)?;
+
+        ledger.keys_mut().revoke(&recovery.revoke_key(new.account_number(), new.verifying_key(), 200)?)?;
+        assert_eq!(ledger.apply(&pay(&new, 200)?, 200), Err(Error::KeyNotValid));
+        assert_eq!(ledger.balance(old.account_number(), Currency::EUR), 3_000);
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use super::Ledger;
+use crate::sepa::DirectDebit;
+use crate::transaction::{Currency, MandateId, MandateRevocation, MandatedTransaction, SignedMandate};
+use crate::{Error, Timestamp};
+
+/// Keeps track of the mandates known to a bank and of how much of each
+/// mandate's allowance has been used up.
+#[derive(Debug, Default)]
+pub struct MandateRegistry {
+    mandates: HashMap<MandateId, SignedMandate>,
+    revocations: HashMap<MandateId, Timestamp>,
+    // amount collected per mandate and period index
+    collected: HashMap<(MandateId, u64), u64>,
+}
+
+/// What a mandate's allowance for one period will have been used up to once
+/// a collection is booked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Charge {
+    period: (MandateId, u64),
+    total: u64,
+}
+
+impl MandateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a signed mandate received at `received_at`, after verifying
+    /// the user's signature and that it was made with the key of their
+    /// account at that time.
+    pub fn register(&mut self, ledger: &Ledger, mandate: SignedMandate, received_at: Timestamp) -> Result<MandateId, Error> {
+        mandate.verify()?;
+        let user = mandate.mandate().user();
+        ledger.authorize(user.account_number(), user.verifying_key(), received_at)?;
+
+        let id = mandate.id();
+        self.mandates.insert(id, mandate);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &MandateId) -> Option<&SignedMandate> {
+        self.mandates.get(id)
+    }
+
+    /// Revokes a registered mandate.
+    ///
+    /// Collections the bank processes at or after the revocation time are refused.
+    pub fn revoke(&mut self, revocation: &MandateRevocation) -> Result<(), Error> {
+        let mandate = self.mandates.get(revocation.mandate_id()).ok_or(Error::UnknownMandate)?;
+        revocation.verify(mandate.mandate())?;
+
+        let revoked_at = self.revocations.entry(*revocation.mandate_id()).or_insert(revocation.revoked_at());
+        // a later revocation can not push back an earlier one
+        *revoked_at = (*revoked_at).min(revocation.revoked_at());
+        Ok(())
+    }
+
+    /// Returns how much may still be collected through the mandate in the
+    /// period `at` falls into.
+    pub fn remaining(&self, id: &MandateId, at: Timestamp) -> Result<u64, Error> {
+        let mandate = self.mandates.get(id).ok_or(Error::UnknownMandate)?.mandate();
+        let index = mandate.period().index(mandate.start(), at);
+        let collected = self.collected.get(&(*id, index)).copied().unwrap_or(0);
+
+        Ok(mandate.max_amount().saturating_sub(collected))
+    }
+
+    /// Checks a merchant-initiated transaction the bank processes at `now`
+    /// against its mandate and, if it is covered, books its amount against
+    /// the mandate's allowance for the period `now` falls into.
+    ///
+    /// The merchant chooses when the transaction claims to be created, so
+    /// revocation and periods are judged by `now` instead.
+    pub fn authorize(&mut self, transaction: &MandatedTransaction, now: Timestamp) -> Result<(), Error> {
+        let id = transaction.mandate_id();
+        let mandate = self.mandates.get(id).ok_or(Error::UnknownMandate)?.mandate();
+        transaction.verify(mandate)?;
+
+        let charge = self.charge(id, transaction.transaction().amount(), now)?;
+        self.book(charge);
+        Ok(())
+    }
+
+    /// Checks a direct debit the bank collects at `now` against the mandate
+    /// its debtor signed, like [`MandateRegistry::authorize`], without
+    /// booking it yet.
+    pub(crate) fn check_debit(&self, debit: &DirectDebit, now: Timestamp) -> Result<Charge, Error> {
+        let mandate = self.mandates.get(debit.mandate_id()).ok_or(Error::UnknownMandate)?.mandate();
+        if mandate.merchant().verifying_key() != debit.creditor().verifying_key()
+            || mandate.user().verifying_key() != debit.debtor().verifying_key()
+            || mandate.user().account_number() != debit.debtor_iban()
+            || mandate.currency() != Currency::EUR
+            || mandate.direct_debit() != Some((debit.creditor_id(), debit.mandate_reference()))
+        {
+            return Err(Error::MandateMismatch);
+        }
+
+        self.charge(debit.mandate_id(), debit.amount(), now)
+    }
+
+    pub(crate) fn book(&mut self, charge: Charge) {
+        self.collected.insert(charge.period, charge.total);
+    }
+
+    fn charge(&self, id: &MandateId, amount: u64, now: Timestamp) -> Result<Charge, Error> {
+        let mandate = self.mandates.get(id).ok_or(Error::UnknownMandate)?.mandate();
+        if !mandate.is_active(now) {
+            return Err(Error::MandateNotActive);
+        }
+
+        if let Some(revoked_at) = self.revocations.get(id) {
+            if now >= *revoked_at {
+                return Err(Error::MandateRevoked);
+            }
+        }
+
+        let period = (*id, mandate.period().index(mandate.start(), now));
+        let collected = self.collected.get(&period).copied().unwrap_or(0);
+        let total = collected.checked_add(amount)
+            .filter(|total| *total <= mandate.max_amount())
+            .ok_or(Error::MandateLimitExceeded)?;
+
+        Ok(Charge { period, total })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::sepa::CreditorId;
+    use crate::transaction::{Currency, Mandate, Period};
+    use crate::user::User;
+
+    // 2024-01-15T00:00:00Z
+    const START: Timestamp = 1_705_276_800;
+    // 2024-02-01T00:00:00Z
+    const FEBRUARY: Timestamp = 1_706_745_600;
+    // 2025-01-15T00:00:00Z
+    const END: Timestamp = 1_736_899_200;
+
+    fn setup() -> Result<(User, Merchant, SignedMandate, MandateRegistry), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mandate = Mandate::new(user.clone(), merchant.clone(), Currency::EUR, 10_000, Period::Monthly, START, END)?;
+        let signed = user.sign_mandate(mandate)?;
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        let mut registry = MandateRegistry::new();
+        registry.register(&ledger, signed.clone(), START)?;
+
+        Ok((user, merchant, signed, registry))
+    }
+
+    #[test]
+    fn collect_within_limit() -> Result<(), Error> {
+        let (_, merchant, mandate, mut registry) = setup()?;
+
+        registry.authorize(&merchant.collect(&mandate, 6_000, START)?, START)?;
+        registry.authorize(&merchant.collect(&mandate, 4_000, START + 60)?, START + 60)?;
+        assert_eq!(registry.remaining(&mandate.id(), START)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn limit_per_period() -> Result<(), Error> {
+        let (_, merchant, mandate, mut registry) = setup()?;
+
+        registry.authorize(&merchant.collect(&mandate, 6_000, START)?, START)?;
+        let over = merchant.collect(&mandate, 6_000, START + 60)?;
+        assert_eq!(registry.authorize(&over, START + 60), Err(Error::MandateLimitExceeded));
+
+        // the allowance renews with the next calendar month
+        registry.authorize(&merchant.collect(&mandate, 6_000, FEBRUARY)?, FEBRUARY)?;
+        assert_eq!(registry.remaining(&mandate.id(), FEBRUARY)?, 4_000);
+
+        // a collection dated back to January still counts against February
+        registry.authorize(&merchant.collect(&mandate, 4_000, START + 120)?, FEBRUARY + 60)?;
+        assert_eq!(registry.remaining(&mandate.id(), START)?, 4_000);
+        assert_eq!(registry.remaining(&mandate.id(), FEBRUARY)?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn outside_validity() -> Result<(), Error> {
+        let (_, merchant, mandate, mut registry) = setup()?;
+
+        let early = merchant.collect(&mandate, 1_000, START - 1)?;
+        let late = merchant.collect(&mandate, 1_000, END)?;
+        assert_eq!(registry.authorize(&early, START), Err(Error::MandateNotActive));
+        assert_eq!(registry.authorize(&late, END), Err(Error::MandateNotActive));
+
+        // a collection dated within the mandate is not processed after it ended
+        let backdated = merchant.collect(&mandate, 1_000, END - 1)?;
+        assert_eq!(registry.authorize(&backdated, END), Err(Error::MandateNotActive));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_terms() -> Result<(), Error> {
+        let (user, merchant, _, _) = setup()?;
+        let terms = |amount, start, end| Mandate::new(user.clone(), merchant.clone(), Currency::EUR, amount, Period::Monthly, start, end);
+
+        assert_eq!(terms(0, START, END), Err(Error::InvalidMandate));
+        assert_eq!(terms(10_000, END, START), Err(Error::InvalidMandate));
+        assert_eq!(terms(10_000, START, START), Err(Error::InvalidMandate));
+        let terms = terms(10_000, START, START + 1)?;
+
+        let creditor_id = CreditorId::try_from("DE98ZZZ09999999999")?;
+        assert_eq!(terms.clone().for_direct_debit(creditor_id.clone(), String::new()), Err(Error::InvalidMandateReference));
+        assert_eq!(terms.clone().for_direct_debit(creditor_id.clone(), "M".repeat(36)), Err(Error::InvalidMandateReference));
+        terms.for_direct_debit(creditor_id, "M".repeat(35))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn other_merchant() -> Result<(), Error> {
+        let (_, _, mandate, _) = setup()?;
+        let other = Merchant::new("GB82WEST12345698765432".to_string());
+
+        assert_eq!(other.collect(&mandate, 1_000, START), Err(Error::WrongSigner));
+        Ok(())
+    }
+
+    #[test]
+    fn revoked() -> Result<(), Error> {
+        let (user, merchant, mandate, mut registry) = setup()?;
+
+        let before = merchant.collect(&mandate, 1_000, FEBRUARY - 1)?;
+        let after = merchant.collect(&mandate, 1_000, FEBRUARY)?;
+        registry.revoke(&user.revoke_mandate(&mandate, FEBRUARY)?)?;
+
+        registry.authorize(&before, FEBRUARY - 1)?;
+        assert_eq!(registry.authorize(&after, FEBRUARY), Err(Error::MandateRevoked));
+
+        // nor can a collection be dated back to before the revocation
+        let backdated = merchant.collect(&mandate, 1_000, FEBRUARY - 1)?;
+        assert_eq!(registry.authorize(&backdated, FEBRUARY + 60), Err(Error::MandateRevoked));
+
+        Ok(())
+    }
+
+    #[test]
+    fn revocation_by_other_user() -> Result<(), Error> {
+        let (_, _, mandate, _) = setup()?;
+        let other = User::new("DE89370400440532013000".parse()?);
+
+        assert_eq!(other.revoke_mandate(&mandate, START), Err(Error::WrongSigner));
+        Ok(())
+    }
+}
@@ -1 +1,43 @@
-pub struct Bank {}
\ No newline at end of file
+pub use certificates::{AccountCertificate, CertificateId, CertificateVerifier, IssuerCertificate, MAX_HOLDER_NAME_LEN};
+pub use delegations::DelegationBook;
+pub use direct_debits::{CollectedDebit, DirectDebitBook};
+pub use disputes::{
+    dispute_id, AuditEntry, Dispute, DisputeBook, DisputeDeadlines, DisputeId, DisputeStage, DisputeStep, ReasonCode,
+    SignedDisputeStep,
+};
+pub use escrow::{Condition, Decision, Escrow, EscrowApproval, EscrowBook, EscrowId, EscrowState};
+pub(crate) use escrow::escrow_terms;
+pub use journal::{Checkpoint, EntryKind, Journal, JournalEntry, JournalFault, JournalSummary, JournalVerifier, GENESIS_HASH};
+pub use key::BankKey;
+pub use key_history::{KeyHistory, KeyPeriod, KeyRegistry, PendingRecovery, DEFAULT_RECOVERY_DELAY, MAX_RECOVERY_AGE};
+pub use ledger::Ledger;
+pub use mandates::MandateRegistry;
+pub use multisig::{MultisigAccounts, MultisigPolicy, PartiallySignedTransaction};
+pub use offline::{OfflineBook, ReconciliationReport, DEFAULT_UPLOAD_WINDOW};
+pub use payments::{PaymentBook, SettledPayment};
+pub use payouts::{PayoutBook, PayoutReport};
+pub use revocation::{CertificateRegistry, CertificateStatus, RevocationList, StatusCache, StatusResponse};
+pub use settlement::{Settlement, SettlementBook, SettlementLeaf, SettlementProof, SettlementSalt, SignedSettlement};
+pub use solvency::{Liabilities, LiabilityProof, LiabilitySalt, LiabilityStatement, LIABILITY_PARTS};
+pub use tokens::TokenBook;
+pub use verification::{BatchVerifier, VerificationPolicy, VerificationReport};
+
+mod certificates;
+mod delegations;
+mod direct_debits;
+mod disputes;
+mod escrow;
+mod journal;
+mod key;
+mod key_history;
+mod ledger;
+mod mandates;
+mod multisig;
+mod offline;
+mod payments;
+mod payouts;
+mod revocation;
+mod settlement;
+mod solvency;
+mod tokens;
+mod verification;
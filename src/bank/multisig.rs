@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+
+use super::Ledger;
+use crate::crypto::{PublicKey, Signature};
+use crate::traits::ToBytes;
+use crate::transaction::{Transaction, TransactionId};
+use crate::{Error, IBAN};
+
+/// Requires `threshold` of the registered keys to sign a transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultisigPolicy {
+    threshold: usize,
+    signers: Vec<PublicKey>,
+}
+
+/// A transaction collecting signatures from several parties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartiallySignedTransaction {
+    transaction: Transaction,
+    signatures: Vec<(PublicKey, Signature)>,
+}
+
+impl MultisigPolicy {
+    /// Creates a policy requiring `threshold` of `signers`. Fails if the
+    /// threshold can never or always be reached, or a key is listed twice.
+    pub fn new(threshold: usize, signers: Vec<PublicKey>) -> Result<Self, Error> {
+        if threshold == 0 || threshold > signers.len() {
+            return Err(Error::InvalidThreshold);
+        }
+        if signers.iter().enumerate().any(|(i, key)| signers[..i].contains(key)) {
+            return Err(Error::DuplicateSigner);
+        }
+
+        Ok(Self { threshold, signers })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn signers(&self) -> &[PublicKey] {
+        &self.signers
+    }
+
+    /// Verifies that enough distinct registered signers signed the transaction.
+    pub fn verify(&self, partial: &PartiallySignedTransaction) -> Result<(), Error> {
+        partial.transaction.validate()?;
+
+        let bytes = partial.transaction.as_bytes();
+        for (i, (signer, signature)) in partial.signatures.iter().enumerate() {
+            if !self.signers.contains(signer) {
+                return Err(Error::UnknownSigner);
+            }
+            if partial.signatures[..i].iter().any(|(other, _)| other == signer) {
+                return Err(Error::DuplicateSigner);
+            }
+
+            signer.verify(&bytes, signature)?;
+        }
+
+        if partial.signatures.len() < self.threshold {
+            return Err(Error::NotEnoughSignatures);
+        }
+
+        Ok(())
+    }
+}
+
+impl PartiallySignedTransaction {
+    pub fn new(transaction: Transaction) -> Self {
+        Self { transaction, signatures: Vec::new() }
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    /// Returns the keys that have signed so far.
+    pub fn signers(&self) -> Vec<PublicKey> {
+        self.signatures.iter().map(|(signer, _)| *signer).collect()
+    }
+
+    /// Adds a signature, unless the key already signed.
+    pub(crate) fn add_signature(&mut self, signer: PublicKey, signature: Signature) -> Result<(), Error> {
+        if self.signatures.iter().any(|(other, _)| *other == signer) {
+            return Err(Error::DuplicateSigner);
+        }
+
+        self.signatures.push((signer, signature));
+        Ok(())
+    }
+
+    /// Takes over the signatures another party collected on the same transaction.
+    pub fn merge(&mut self, other: PartiallySignedTransaction) -> Result<(), Error> {
+        if other.transaction != self.transaction {
+            return Err(Error::TransactionMismatch);
+        }
+
+        for (signer, signature) in other.signatures {
+            if !self.signatures.iter().any(|(known, _)| *known == signer) {
+                self.signatures.push((signer, signature));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The accounts of a bank that need several approvals for outgoing payments.
+#[derive(Debug, Default)]
+pub struct MultisigAccounts {
+    policies: HashMap<IBAN, MultisigPolicy>,
+    applied: HashSet<TransactionId>,
+}
+
+impl MultisigAccounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `account` under `policy`. From then on, `ledger` refuses
+    /// payments from the account that were not applied through here.
+    pub fn register(&mut self, ledger: &mut Ledger, account: IBAN, policy: MultisigPolicy) {
+        ledger.require_multisig(account);
+        self.policies.insert(account, policy);
+    }
+
+    pub fn policy(&self, account: &IBAN) -> Option<&MultisigPolicy> {
+        self.policies.get(account)
+    }
+
+    /// Verifies a transaction against the policy of the paying account and
+    /// applies it to the ledger. Each transaction is applied once.
+    pub fn apply(&mut self, ledger: &mut Ledger, partial: &PartiallySignedTransaction) -> Result<(), Error> {
+        let account = *partial.transaction.user().account_number();
+        let policy = self.policies.get(&account).ok_or(Error::UnknownAccount)?;
+        policy.verify(partial)?;
+
+        let id = partial.transaction.id();
+        if self.applied.contains(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        ledger.book_cosigned(&partial.transaction)?;
+        self.applied.insert(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::bank::{BankKey, Condition, EscrowBook, OfflineBook, TokenBook};
+    use crate::merchant::Merchant;
+    use crate::token::{Denomination, MintKey};
+    use crate::traits::TransactionSign;
+    use crate::transaction::Currency;
+    use crate::user::User;
+
+    struct Setup {
+        company: User,
+        officers: Vec<User>,
+        merchant: Merchant,
+        accounts: MultisigAccounts,
+        ledger: Ledger,
+        partial: PartiallySignedTransaction,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let company = User::new("DE89370400440532013000".parse()?);
+        let officers = (0..3).map(|_| User::new(*company.account_number())).collect::<Vec<_>>();
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let account = *company.account_number();
+
+        let mut accounts = MultisigAccounts::new();
+        let keys = officers.iter().map(|officer| *officer.verifying_key()).collect();
+        let mut ledger = Ledger::new();
+        // the key the account was opened with
+        ledger.keys_mut().enroll(&company, 0, None)?;
+        ledger.deposit(&account, Currency::EUR, 100_000)?;
+        accounts.register(&mut ledger, account, MultisigPolicy::new(2, keys)?);
+        let partial = PartiallySignedTransaction::new(Transaction::new(100_000, Currency::EUR, merchant.clone(), company.clone(), 0));
+
+        Ok(Setup { company, officers, merchant, accounts, ledger, partial })
+    }
+
+    #[test]
+    fn two_of_three() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        s.officers[0].cosign(&mut s.partial)?;
+        assert_eq!(s.accounts.apply(&mut s.ledger, &s.partial), Err(Error::NotEnoughSignatures));
+
+        // the second officer signs their own copy, which is merged back
+        let mut copy = PartiallySignedTransaction::new(s.partial.transaction().clone());
+        s.officers[2].cosign(&mut copy)?;
+        s.partial.merge(copy)?;
+
+        s.accounts.apply(&mut s.ledger, &s.partial)?;
+        assert_eq!(s.ledger.balance(&IBAN::try_from(s.merchant.account_number())?, Currency::EUR), 100_000);
+
+        // the approvals can't be spent again
+        s.ledger.deposit(s.partial.transaction().user().account_number(), Currency::EUR, 100_000)?;
+        assert_eq!(s.accounts.apply(&mut s.ledger, &s.partial), Err(Error::DuplicateTransaction));
+
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_and_unknown_signers() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        s.officers[0].cosign(&mut s.partial)?;
+        assert_eq!(s.officers[0].cosign(&mut s.partial), Err(Error::DuplicateSigner));
+
+        // a duplicate sneaked in by hand is rejected as well
+        let mut forged = s.partial.clone();
+        forged.signatures.push(forged.signatures[0]);
+        assert_eq!(s.accounts.apply(&mut s.ledger, &forged), Err(Error::DuplicateSigner));
+
+        let outsider = User::new("DE89370400440532013000".parse()?);
+        outsider.cosign(&mut s.partial)?;
+        assert_eq!(s.accounts.apply(&mut s.ledger, &s.partial), Err(Error::UnknownSigner));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bypassing_the_policy() -> Result<(), Error> {
+        let mut s = setup()?;
+
+        // a single officer can't pay from the account on their own
+        let single = s.officers[0].sign(Transaction::new(100_000, Currency::EUR, s.merchant.clone(), s.officers[0].clone(), 0))?;
+        assert_eq!(s.ledger.apply(&single, 0), Err(Error::MultisigRequired));
+
+        // nor can the key the account was opened with, through any book
+        let payment = Transaction::new(100_000, Currency::EUR, s.merchant.clone(), s.company.clone(), 0);
+        assert_eq!(s.ledger.apply(&s.company.sign(payment.clone())?, 0), Err(Error::MultisigRequired));
+        let (release, refund) = (Condition::SignedBy(*s.company.verifying_key()), Condition::SignedBy(*s.merchant.verifying_key()));
+        let escrowed = s.company.sign(payment.for_escrow(&release, &refund))?;
+        assert_eq!(EscrowBook::new().open(&mut s.ledger, &escrowed, release, refund, 0), Err(Error::MultisigRequired));
+        let five = Denomination::new(5_000, Currency::EUR);
+        let mut tokens = TokenBook::new();
+        tokens.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0)));
+        let token_key = tokens.token_key(&five).ok_or(Error::DevError)?.clone();
+        assert_eq!(tokens.withdraw(&mut s.ledger, &s.company.withdraw_token(&token_key)?.1, 0), Err(Error::MultisigRequired));
+        let offline = OfflineBook::new(BankKey::new()).issue(&mut s.ledger, &s.company, Currency::EUR, 5_000, 0, 100);
+        assert_eq!(offline.err(), Some(Error::MultisigRequired));
+        assert_eq!(s.ledger.balance(s.officers[0].account_number(), Currency::EUR), 100_000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let keys = (0..2).map(|_| *User::new(IBAN::new()).verifying_key()).collect::<Vec<_>>();
+
+        assert_eq!(MultisigPolicy::new(0, keys.clone()), Err(Error::InvalidThreshold));
+        assert_eq!(MultisigPolicy::new(3, keys.clone()), Err(Error::InvalidThreshold));
+        assert_eq!(MultisigPolicy::new(1, vec![keys[0], keys[0]]), Err(Error::DuplicateSigner));
+    }
+}
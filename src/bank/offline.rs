@@ -0,0 +1,345 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{BankKey, Ledger};
+use crate::iban::parse_valid;
+use crate::time::WEEK;
+use crate::transaction::{AllowanceId, Currency, OfflineAllowance, OfflinePayment, TransactionId};
+use crate::user::{User, MAX_CLOCK_SKEW};
+use crate::{Error, Timestamp, IBAN};
+
+/// How long after an allowance expires merchants may still upload payments.
+pub const DEFAULT_UPLOAD_WINDOW: Timestamp = WEEK;
+
+/// The outcome of reconciling an allowance, one result per uploaded
+/// payment in the order they were judged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconciliationReport {
+    results: Vec<(TransactionId, Result<(), Error>)>,
+    paid: u64,
+    returned: u64,
+}
+
+/// Issues offline allowances, holding back their limit on the user's
+/// account, and settles the payments made against them once merchants
+/// uploaded them.
+#[derive(Debug)]
+pub struct OfflineBook {
+    key: BankKey,
+    upload_window: Timestamp,
+    allowances: HashMap<AllowanceId, Reservation>,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    allowance: OfflineAllowance,
+    payments: HashMap<TransactionId, OfflinePayment>,
+    reconciled: bool,
+}
+
+impl ReconciliationReport {
+    pub fn results(&self) -> &[(TransactionId, Result<(), Error>)] {
+        &self.results
+    }
+
+    /// Returns the ids of all transactions that were paid.
+    pub fn succeeded(&self) -> Vec<TransactionId> {
+        self.results.iter().filter(|(_, r)| r.is_ok()).map(|(id, _)| *id).collect()
+    }
+
+    /// Returns the ids of all transactions that were refused, along with the reason.
+    pub fn failed(&self) -> Vec<(TransactionId, Error)> {
+        self.results.iter().filter_map(|(id, r)| r.err().map(|e| (*id, e))).collect()
+    }
+
+    /// Returns the amount paid out to merchants.
+    pub fn paid(&self) -> u64 {
+        self.paid
+    }
+
+    /// Returns the unspent part of the allowance, released back to the user.
+    pub fn returned(&self) -> u64 {
+        self.returned
+    }
+}
+
+impl OfflineBook {
+    pub fn new(key: BankKey) -> Self {
+        Self::with_upload_window(key, DEFAULT_UPLOAD_WINDOW)
+    }
+
+    pub fn with_upload_window(key: BankKey, upload_window: Timestamp) -> Self {
+        Self { key, upload_window, allowances: HashMap::new() }
+    }
+
+    /// Returns the key merchants check allowances against.
+    pub fn bank_key(&self) -> BankKey {
+        self.key.to_public()
+    }
+
+    /// Allows `user` to pay up to `limit` offline between `valid_from` and
+    /// `expires_at`, holding back `limit` on the user's account. The key of
+    /// `user` has to be the key of the account at `valid_from`.
+    pub fn issue(
+        &mut self,
+        ledger: &mut Ledger,
+        user: &User,
+        currency: Currency,
+        limit: u64,
+        valid_from: Timestamp,
+        expires_at: Timestamp,
+    ) -> Result<OfflineAllowance, Error> {
+        ledger.authorize(user.account_number(), user.verifying_key(), valid_from)?;
+        let user = user.to_public();
+        let bank_key = *self.key.verifying_key();
+        let message = OfflineAllowance::signed_bytes(&user, currency, limit, valid_from, expires_at, &bank_key);
+        let signature = self.key.sign_bytes(&message)?;
+        let allowance = OfflineAllowance::new(user, currency, limit, valid_from, expires_at, bank_key, signature);
+
+        ledger.withdraw(allowance.user().account_number(), currency, limit)?;
+        let reservation = Reservation { allowance: allowance.clone(), payments: HashMap::new(), reconciled: false };
+        self.allowances.insert(allowance.id(), reservation);
+        Ok(allowance)
+    }
+
+    /// Returns the accounts and amounts held back for allowances in
+    /// `currency` that were not reconciled yet, one per allowance.
+    pub fn reserved(&self, currency: Currency) -> impl Iterator<Item = (&IBAN, u64)> {
+        self.allowances.values()
+            .filter(move |reservation| !reservation.reconciled && reservation.allowance.currency() == currency)
+            .map(|reservation| (reservation.allowance.user().account_number(), reservation.allowance.limit()))
+    }
+
+    /// Accepts a payment uploaded by a merchant at `now`, until the upload
+    /// window of its allowance closes. Uploading the same payment again
+    /// changes nothing.
+    ///
+    /// The payment has to have been made while the allowance was valid, and
+    /// can't be dated after it was uploaded.
+    pub fn upload(&mut self, payment: OfflinePayment, now: Timestamp) -> Result<(), Error> {
+        let made_at = payment.transaction().created_at();
+        if made_at > now.saturating_add(MAX_CLOCK_SKEW) {
+            return Err(Error::OfflinePaymentPostdated);
+        }
+        if now >= payment.allowance().expires_at().saturating_add(self.upload_window) {
+            return Err(Error::UploadWindowClosed);
+        }
+        payment.verify(self.key.verifying_key(), made_at)?;
+
+        let reservation = self.allowances.get_mut(&payment.allowance().id()).ok_or(Error::UnknownAllowance)?;
+        if reservation.reconciled {
+            return Err(Error::AllowanceReconciled);
+        }
+
+        reservation.payments.insert(payment.signed().id(), payment);
+        Ok(())
+    }
+
+    /// Settles all payments uploaded for an allowance, once its upload
+    /// window has passed, and releases what is left to the user.
+    ///
+    /// Payments are judged in the order of their counters, then of their
+    /// transaction ids, so the outcome does not depend on the order they
+    /// were uploaded in. A payment reusing the counter of an earlier one is
+    /// a double spend, and payments going over the limit are overspent;
+    /// both are refused.
+    pub fn reconcile(&mut self, ledger: &mut Ledger, id: &AllowanceId, now: Timestamp) -> Result<ReconciliationReport, Error> {
+        let reservation = self.allowances.get_mut(id).ok_or(Error::UnknownAllowance)?;
+        let allowance = &reservation.allowance;
+        if reservation.reconciled {
+            return Err(Error::AllowanceReconciled);
+        }
+        if now < allowance.expires_at().saturating_add(self.upload_window) {
+            return Err(Error::ReconciliationTooEarly);
+        }
+
+        let mut payments = reservation.payments.iter().collect::<Vec<_>>();
+        payments.sort_by_key(|(id, payment)| (payment.counter(), **id));
+
+        let mut counters = HashSet::new();
+        let mut credits = Vec::new();
+        let mut paid = 0;
+        let mut results = Vec::new();
+        for (id, payment) in payments {
+            let result = judge(payment, &mut counters, allowance.limit() - paid).map(|legs| {
+                credits.extend(legs);
+                paid += payment.transaction().amount();
+            });
+            results.push((*id, result));
+        }
+
+        let returned = allowance.limit() - paid;
+        credits.push((*allowance.user().account_number(), returned));
+        ledger.deposit_many(allowance.currency(), &credits)?;
+
+        reservation.reconciled = true;
+        Ok(ReconciliationReport { results, paid, returned })
+    }
+}
+
+// returns the credits of a payment, if it reuses no counter and stays within `remaining`
+fn judge(payment: &OfflinePayment, counters: &mut HashSet<u64>, remaining: u64) -> Result<Vec<(IBAN, u64)>, Error> {
+    if !counters.insert(payment.counter()) {
+        return Err(Error::DoubleSpend);
+    }
+    if payment.transaction().amount() > remaining {
+        return Err(Error::AllowanceExceeded);
+    }
+
+    payment.transaction().legs().iter()
+        .map(|leg| Ok((parse_valid(leg.merchant().account_number())?, leg.amount())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::signer::{Signer, SoftwareSigner};
+    use crate::traits::TransactionSign;
+    use crate::transaction::Transaction;
+    use crate::user::OfflineWallet;
+
+    const EXPIRES_AT: Timestamp = 1_000;
+
+    struct Setup {
+        book: OfflineBook,
+        ledger: Ledger,
+        user: User,
+        merchants: [Merchant; 2],
+        wallet: OfflineWallet,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let mut book = OfflineBook::with_upload_window(BankKey::new(), 100);
+        let mut ledger = Ledger::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchants = [
+            Merchant::new("GB82WEST12345698765432".to_string()),
+            Merchant::new("FR1420041010050500013M02606".to_string()),
+        ];
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 10_000)?;
+
+        let allowance = book.issue(&mut ledger, &user, Currency::EUR, 5_000, 0, EXPIRES_AT)?;
+        let wallet = OfflineWallet::new(user.clone(), allowance)?;
+        Ok(Setup { book, ledger, user, merchants, wallet })
+    }
+
+    fn balance(s: &Setup, merchant: usize) -> Result<u64, Error> {
+        Ok(s.ledger.balance(&parse_valid(s.merchants[merchant].account_number())?, Currency::EUR))
+    }
+
+    #[test]
+    fn reconcile() -> Result<(), Error> {
+        let mut s = setup()?;
+        assert_eq!(s.ledger.balance(s.user.account_number(), Currency::EUR), 5_000);
+        assert_eq!(s.book.reserved(Currency::EUR).collect::<Vec<_>>(), [(s.user.account_number(), 5_000)]);
+
+        let allowance = s.wallet.allowance().clone();
+        let first = s.wallet.pay(Transaction::new(1_000, Currency::EUR, s.merchants[0].clone(), s.user.clone(), 10).for_offline(&allowance))?;
+        let second = s.wallet.pay(Transaction::new(1_500, Currency::EUR, s.merchants[1].clone(), s.user.clone(), 20).for_offline(&allowance))?;
+        assert_eq!(s.merchants[0].accept_offline(&first, s.book.bank_key().verifying_key(), 10)?, 1_000);
+        assert_eq!(s.merchants[0].accept_offline(&second, s.book.bank_key().verifying_key(), 10), Err(Error::NotPayee));
+
+        s.book.upload(second.clone(), EXPIRES_AT)?;
+        s.book.upload(first.clone(), EXPIRES_AT)?;
+        s.book.upload(first.clone(), EXPIRES_AT)?;
+
+        let id = first.allowance().id();
+        assert_eq!(s.book.reconcile(&mut s.ledger, &id, EXPIRES_AT), Err(Error::ReconciliationTooEarly));
+        let report = s.book.reconcile(&mut s.ledger, &id, EXPIRES_AT + 100)?;
+        assert_eq!(report.succeeded(), vec![first.signed().id(), second.signed().id()]);
+        assert_eq!((report.paid(), report.returned()), (2_500, 2_500));
+
+        assert_eq!((balance(&s, 0)?, balance(&s, 1)?), (1_000, 1_500));
+        assert_eq!(s.ledger.balance(s.user.account_number(), Currency::EUR), 7_500);
+        assert_eq!(s.book.reserved(Currency::EUR).count(), 0);
+        // an offline payment can not be cashed a second time as an online one
+        assert_eq!(s.ledger.apply(first.signed(), 0), Err(Error::WrongPurpose));
+        assert_eq!(s.ledger.balance(s.user.account_number(), Currency::EUR), 7_500);
+        assert_eq!(s.book.upload(first, EXPIRES_AT), Err(Error::AllowanceReconciled));
+        assert_eq!(s.book.reconcile(&mut s.ledger, &id, EXPIRES_AT + 100), Err(Error::AllowanceReconciled));
+
+        Ok(())
+    }
+
+    #[test]
+    fn dated_against_the_clock() -> Result<(), Error> {
+        let mut s = setup()?;
+        let allowance = s.wallet.allowance().clone();
+        let bank_key = *s.book.bank_key().verifying_key();
+        let early = s.wallet.pay(Transaction::new(1_000, Currency::EUR, s.merchants[0].clone(), s.user.clone(), 10).for_offline(&allowance))?;
+        let late = s.wallet.pay(Transaction::new(1_000, Currency::EUR, s.merchants[0].clone(), s.user.clone(), 900).for_offline(&allowance))?;
+
+        // a payment dated within the allowance is still refused once it expired
+        assert_eq!(s.merchants[0].accept_offline(&early, &bank_key, EXPIRES_AT), Err(Error::AllowanceExpired));
+        assert_eq!(s.merchants[0].accept_offline(&early, &bank_key, EXPIRES_AT - 1)?, 1_000);
+
+        assert_eq!(s.book.upload(late.clone(), 100), Err(Error::OfflinePaymentPostdated));
+        assert_eq!(s.book.upload(early.clone(), EXPIRES_AT + 100), Err(Error::UploadWindowClosed));
+        s.book.upload(early, EXPIRES_AT + 99)?;
+        s.book.upload(late, 900)?;
+
+        let report = s.book.reconcile(&mut s.ledger, &allowance.id(), EXPIRES_AT + 100)?;
+        assert_eq!((report.paid(), report.returned()), (2_000, 3_000));
+        Ok(())
+    }
+
+    #[test]
+    fn double_spend() -> Result<(), Error> {
+        let mut s = setup()?;
+        // a copy of the wallet hands out the same counters again
+        let mut copy = s.wallet.clone();
+
+        let allowance = s.wallet.allowance().clone();
+        let first = s.wallet.pay(Transaction::new(3_000, Currency::EUR, s.merchants[0].clone(), s.user.clone(), 10).for_offline(&allowance))?;
+        let second = copy.pay(Transaction::new(3_000, Currency::EUR, s.merchants[1].clone(), s.user.clone(), 10).for_offline(&allowance))?;
+        // both merchants accept, since neither can see the other's payment
+        for payment in [&first, &second] {
+            payment.verify(s.book.bank_key().verifying_key(), 10)?;
+            s.book.upload(payment.clone(), EXPIRES_AT)?;
+        }
+
+        // of two payments with the same counter, the one with the lower transaction id is paid
+        let (kept, doubled) = if first.signed().id() < second.signed().id() { (first, second) } else { (second, first) };
+        let report = s.book.reconcile(&mut s.ledger, &kept.allowance().id(), EXPIRES_AT + 100)?;
+        assert_eq!(report.succeeded(), vec![kept.signed().id()]);
+        assert_eq!(report.failed(), vec![(doubled.signed().id(), Error::DoubleSpend)]);
+        assert_eq!((report.paid(), report.returned()), (3_000, 2_000));
+        assert_eq!(s.ledger.balance(s.user.account_number(), Currency::EUR), 7_000);
+
+        // allowances of other banks are not accepted
+        let mut other = setup()?;
+        assert_eq!(other.book.upload(kept, EXPIRES_AT), Err(Error::WrongSigner));
+        Ok(())
+    }
+
+    #[test]
+    fn overspend() -> Result<(), Error> {
+        let mut s = setup()?;
+        // a tampered device signs with the user's key but keeps no count of what was spent
+        let signer = SoftwareSigner::random();
+        let user = User::with_signer("AT611904300234573201".parse()?, signer.clone());
+        s.ledger.keys_mut().enroll(&user, 0, None)?;
+        s.ledger.deposit(user.account_number(), Currency::EUR, 5_000)?;
+        let allowance = s.book.issue(&mut s.ledger, &user, Currency::EUR, 5_000, 0, EXPIRES_AT)?;
+
+        let mut payments = Vec::new();
+        for (counter, amount) in [(0, 3_000), (1, 2_500), (2, 2_000)] {
+            let transaction = Transaction::new(amount, Currency::EUR, s.merchants[0].clone(), user.clone(), 10).for_offline(&allowance);
+            let signed = user.sign(transaction)?;
+            let signature = signer.sign(&OfflinePayment::signed_bytes(&allowance.id(), counter, &signed.id()))?;
+            let payment = OfflinePayment::new(allowance.clone(), signed, counter, signature);
+            s.merchants[0].accept_offline(&payment, s.book.bank_key().verifying_key(), 10)?;
+            s.book.upload(payment.clone(), EXPIRES_AT)?;
+            payments.push(payment.signed().id());
+        }
+
+        let report = s.book.reconcile(&mut s.ledger, &allowance.id(), EXPIRES_AT + 100)?;
+        assert_eq!(report.succeeded(), vec![payments[0], payments[2]]);
+        assert_eq!(report.failed(), vec![(payments[1], Error::AllowanceExceeded)]);
+        assert_eq!((report.paid(), report.returned()), (5_000, 0));
+        assert_eq!(balance(&s, 0)?, 5_000);
+        Ok(())
+    }
+}
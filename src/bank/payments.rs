@@ -0,0 +1,152 @@
+use std::collections::{HashMap, HashSet};
+
+use super::Ledger;
+use crate::iban::parse_valid;
+use crate::transaction::{LegRefund, RefundId, SignedTransaction, TransactionId};
+use crate::{Error, Timestamp};
+
+/// A transaction that has been applied to the ledger, along with how much
+/// of each leg has been refunded since.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettledPayment {
+    transaction: SignedTransaction,
+    refunded: Vec<u64>,
+}
+
+impl SettledPayment {
+    pub fn transaction(&self) -> &SignedTransaction {
+        &self.transaction
+    }
+
+    /// Returns how much of the leg at `leg` has been refunded.
+    pub fn refunded(&self, leg: usize) -> u64 {
+        self.refunded.get(leg).copied().unwrap_or(0)
+    }
+}
+
+/// Settles user-signed transactions into a ledger and keeps them around so
+/// merchants can refund them later.
+#[derive(Debug, Default)]
+pub struct PaymentBook {
+    settled: HashMap<TransactionId, SettledPayment>,
+    refunds: HashSet<RefundId>,
+}
+
+impl PaymentBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, id: &TransactionId) -> Option<&SettledPayment> {
+        self.settled.get(id)
+    }
+
+    /// Applies a signed transaction received at `received_at` to the ledger.
+    /// Each transaction can only be settled once.
+    pub fn settle(&mut self, ledger: &mut Ledger, signed: &SignedTransaction, received_at: Timestamp) -> Result<TransactionId, Error> {
+        let id = signed.id();
+        if self.settled.contains_key(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        ledger.apply(signed, received_at)?;
+
+        let refunded = vec![0; signed.transaction().legs().len()];
+        self.settled.insert(id, SettledPayment { transaction: signed.clone(), refunded });
+        Ok(id)
+    }
+
+    /// Moves a refund from the merchant of a leg back to the user. A leg can
+    /// be refunded in several parts, but never more than it paid, and each
+    /// refund only once.
+    pub fn refund(&mut self, ledger: &mut Ledger, refund: &LegRefund) -> Result<(), Error> {
+        let payment = self.settled.get(refund.transaction_id()).ok_or(Error::UnknownTransaction)?;
+        refund.verify(&payment.transaction)?;
+        if self.refunds.contains(&refund.id()) {
+            return Err(Error::AlreadyRefunded);
+        }
+
+        let (id, leg, amount) = (refund.transaction_id(), refund.leg(), refund.amount());
+        self.reserve_refund(id, leg, amount)?;
+        let payment = self.settled[id].transaction.transaction();
+        let from = parse_valid(payment.legs()[leg].merchant().account_number())?;
+        if let Err(err) = ledger.transfer(&from, payment.user().account_number(), payment.currency(), amount) {
+            self.release_refund(id, leg, amount)?;
+            return Err(err);
+        }
+
+        self.refunds.insert(refund.id());
+        Ok(())
+    }
+
+    /// Counts `amount` as refunded on a leg, so the leg is never refunded
+    /// more than it paid. Moving the funds is up to the caller.
+    pub(crate) fn reserve_refund(&mut self, id: &TransactionId, leg: usize, amount: u64) -> Result<(), Error> {
+        let payment = self.settled.get_mut(id).ok_or(Error::UnknownTransaction)?;
+        let paid = payment.transaction.transaction().legs().get(leg).ok_or(Error::UnknownLeg)?;
+        payment.refunded[leg] = payment.refunded[leg]
+            .checked_add(amount)
+            .filter(|refunded| *refunded <= paid.amount())
+            .ok_or(Error::RefundExceedsAmount)?;
+        Ok(())
+    }
+
+    /// Undoes [`PaymentBook::reserve_refund`].
+    pub(crate) fn release_refund(&mut self, id: &TransactionId, leg: usize, amount: u64) -> Result<(), Error> {
+        let payment = self.settled.get_mut(id).ok_or(Error::UnknownTransaction)?;
+        let refunded = payment.refunded.get_mut(leg).ok_or(Error::UnknownLeg)?;
+        *refunded = refunded.checked_sub(amount).ok_or(Error::RefundExceedsAmount)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Leg, Transaction};
+    use crate::user::User;
+
+    #[test]
+    fn refund_single_leg() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let seller = Merchant::new("GB82WEST12345698765432".to_string());
+        let platform = Merchant::new("FR1420041010050500013M02606".to_string());
+        let legs = vec![Leg::new(seller.clone(), 9_500), Leg::new(platform.clone(), 500)];
+        let user_iban = *user.account_number();
+        let seller_iban = parse_valid(seller.account_number())?;
+        let platform_iban = parse_valid(platform.account_number())?;
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(&user_iban, Currency::EUR, 10_000)?;
+        let mut book = PaymentBook::new();
+
+        let signed = user.sign(Transaction::split(10_000, Currency::EUR, legs, user.clone(), 0)?)?;
+        book.settle(&mut ledger, &signed, 0)?;
+        assert_eq!(book.settle(&mut ledger, &signed, 0), Err(Error::DuplicateTransaction));
+        assert_eq!(ledger.balance(&seller_iban, Currency::EUR), 9_500);
+        assert_eq!(ledger.balance(&platform_iban, Currency::EUR), 500);
+
+        let partial = seller.refund_leg(&signed, 0, 1_000)?;
+        book.refund(&mut ledger, &partial)?;
+        assert_eq!(book.refund(&mut ledger, &partial), Err(Error::AlreadyRefunded));
+        // a second refund of the same amount is not a replay
+        book.refund(&mut ledger, &seller.refund_leg(&signed, 0, 1_000)?)?;
+        book.refund(&mut ledger, &seller.refund_leg(&signed, 0, 2_000)?)?;
+        book.refund(&mut ledger, &seller.refund_leg(&signed, 0, 5_500)?)?;
+        assert_eq!(ledger.balance(&user_iban, Currency::EUR), 9_500);
+        assert_eq!(ledger.balance(&seller_iban, Currency::EUR), 0);
+        assert_eq!(ledger.balance(&platform_iban, Currency::EUR), 500);
+
+        let over = seller.refund_leg(&signed, 0, 1)?;
+        assert_eq!(book.refund(&mut ledger, &over), Err(Error::RefundExceedsAmount));
+
+        // the platform can't refund the seller's leg
+        assert_eq!(platform.refund_leg(&signed, 0, 100), Err(Error::WrongSigner));
+        assert_eq!(platform.refund_leg(&signed, 2, 100), Err(Error::UnknownLeg));
+
+        Ok(())
+    }
+}
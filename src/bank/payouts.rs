@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+use super::Ledger;
+use crate::iban::parse_valid;
+use crate::transaction::SignedPayoutBatch;
+use crate::Error;
+
+/// The outcome of applying a payout batch, one result per transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayoutReport {
+    results: Vec<Result<(), Error>>,
+}
+
+impl PayoutReport {
+    pub fn results(&self) -> &[Result<(), Error>] {
+        &self.results
+    }
+
+    /// Returns the indices of all transfers that were booked.
+    pub fn succeeded(&self) -> Vec<usize> {
+        self.results.iter().enumerate().filter(|(_, r)| r.is_ok()).map(|(i, _)| i).collect()
+    }
+
+    /// Returns the indices of all transfers that failed, along with the reason.
+    pub fn failed(&self) -> Vec<(usize, Error)> {
+        self.results.iter().enumerate().filter_map(|(i, r)| r.err().map(|e| (i, e))).collect()
+    }
+}
+
+/// Applies payout batches to a ledger, each batch at most once.
+#[derive(Debug, Default)]
+pub struct PayoutBook {
+    applied: HashSet<[u8; 32]>,
+}
+
+impl PayoutBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verifies a batch and books its transfers in order. A failing transfer
+    /// does not stop the ones after it; the report tells which went through.
+    pub fn apply(&mut self, ledger: &mut Ledger, signed: &SignedPayoutBatch) -> Result<PayoutReport, Error> {
+        signed.verify()?;
+
+        let batch = signed.batch();
+        let id = batch.id();
+        if self.applied.contains(&id) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let from = parse_valid(batch.originator().account_number())?;
+        let results = batch.transfers().iter()
+            .map(|transfer| ledger.transfer(&from, transfer.destination(), transfer.currency(), transfer.amount()))
+            .collect();
+
+        self.applied.insert(id);
+        Ok(PayoutReport { results })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::transaction::{CreditTransfer, Currency, PayoutBatch};
+
+    #[test]
+    fn per_transfer_results() -> Result<(), Error> {
+        let originator = Merchant::new("DE89370400440532013000".to_string());
+        let transfers = vec![
+            CreditTransfer::new("GB82WEST12345698765432", 6_000, Currency::EUR, "payout 1".to_string())?,
+            CreditTransfer::new("FR1420041010050500013M02606", 6_000, Currency::EUR, "payout 2".to_string())?,
+            CreditTransfer::new("AT611904300234573201", 4_000, Currency::EUR, "payout 3".to_string())?,
+        ];
+        let signed = originator.sign_payout_batch(PayoutBatch::new(originator.clone(), transfers, 0))?;
+
+        let mut ledger = Ledger::new();
+        ledger.deposit(&parse_valid(originator.account_number())?, Currency::EUR, 10_000)?;
+        let mut book = PayoutBook::new();
+
+        let report = book.apply(&mut ledger, &signed)?;
+        assert_eq!(report.succeeded(), vec![0, 2]);
+        assert_eq!(report.failed(), vec![(1, Error::InsufficientFunds)]);
+        assert_eq!(ledger.balance(&parse_valid(originator.account_number())?, Currency::EUR), 0);
+
+        assert_eq!(book.apply(&mut ledger, &signed), Err(Error::DuplicateTransaction));
+        Ok(())
+    }
+}
@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+
+use super::{AccountCertificate, BankKey, CertificateId, IssuerCertificate};
+use crate::crypto::{KeyId, PublicKey, Signature};
+use crate::encoding::{put_bytes, put_u64};
+use crate::{Error, Timestamp};
+
+/// What the bank knows about a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CertificateStatus {
+    /// Issued and not revoked.
+    Good,
+    /// Signatures made from `revoked_at` on are invalid.
+    Revoked { revoked_at: Timestamp },
+    /// Not issued by the responding key.
+    Unknown,
+}
+
+/// The certificates a bank key revoked, as of `issued_at`. Certificates
+/// issued by that key and not on the list are good until `next_update`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevocationList {
+    issuer: KeyId,
+    // sorted by certificate id
+    revoked: Vec<(CertificateId, Timestamp)>,
+    issued_at: Timestamp,
+    next_update: Timestamp,
+    signature: Signature,
+}
+
+/// The status of a single certificate, signed by the key that issued it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusResponse {
+    certificate: CertificateId,
+    status: CertificateStatus,
+    issuer: KeyId,
+    produced_at: Timestamp,
+    next_update: Timestamp,
+    signature: Signature,
+}
+
+/// Keeps track of the account and issuer certificates a bank issued and
+/// revoked, and answers for them with revocation lists and status responses.
+#[derive(Debug, Default)]
+pub struct CertificateRegistry {
+    certificates: HashMap<CertificateId, AccountCertificate>,
+    issuers: HashMap<CertificateId, IssuerCertificate>,
+    revocations: HashMap<CertificateId, Timestamp>,
+}
+
+/// Holds the revocation lists and status responses a verifier fetched, and
+/// only answers from those that are still fresh.
+///
+/// Status information is fresh from the time it was produced until its next
+/// update is due, but for at most `max_age` seconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusCache {
+    max_age: Timestamp,
+    lists: HashMap<KeyId, RevocationList>,
+    responses: HashMap<CertificateId, StatusResponse>,
+}
+
+impl CertificateStatus {
+    fn put(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Good => buf.push(0),
+            Self::Revoked { revoked_at } => {
+                buf.push(1);
+                put_u64(buf, *revoked_at);
+            }
+            Self::Unknown => buf.push(2),
+        }
+    }
+
+    /// Checks that signatures made at `at` are covered by the status.
+    pub fn check(&self, at: Timestamp) -> Result<(), Error> {
+        match self {
+            Self::Good => Ok(()),
+            Self::Revoked { revoked_at } if at < *revoked_at => Ok(()),
+            Self::Revoked { .. } => Err(Error::CertificateRevoked),
+            Self::Unknown => Err(Error::CertificateUnknown),
+        }
+    }
+}
+
+impl RevocationList {
+    pub(crate) fn new(
+        issuer: KeyId,
+        revoked: Vec<(CertificateId, Timestamp)>,
+        issued_at: Timestamp,
+        next_update: Timestamp,
+        signature: Signature,
+    ) -> Self {
+        Self { issuer, revoked, issued_at, next_update, signature }
+    }
+
+    pub(crate) fn signed_bytes(
+        issuer: &KeyId,
+        revoked: &[(CertificateId, Timestamp)],
+        issued_at: Timestamp,
+        next_update: Timestamp,
+    ) -> Vec<u8> {
+        let mut buf = b"revocation-list".to_vec();
+        put_bytes(&mut buf, issuer);
+        put_u64(&mut buf, revoked.len() as u64);
+        for (certificate, revoked_at) in revoked {
+            put_bytes(&mut buf, certificate);
+            put_u64(&mut buf, *revoked_at);
+        }
+        put_u64(&mut buf, issued_at);
+        put_u64(&mut buf, next_update);
+        buf
+    }
+
+    /// Returns the id of the key whose certificates are listed.
+    pub fn issuer(&self) -> &KeyId {
+        &self.issuer
+    }
+
+    /// Returns the revoked certificates and when they were revoked, sorted by id.
+    pub fn revoked(&self) -> &[(CertificateId, Timestamp)] {
+        &self.revoked
+    }
+
+    pub fn issued_at(&self) -> Timestamp {
+        self.issued_at
+    }
+
+    pub fn next_update(&self) -> Timestamp {
+        self.next_update
+    }
+
+    /// Returns the status of a certificate issued by the listing key.
+    pub fn status(&self, certificate: &CertificateId) -> CertificateStatus {
+        match self.revoked.binary_search_by(|(id, _)| id.cmp(certificate)) {
+            Ok(index) => CertificateStatus::Revoked { revoked_at: self.revoked[index].1 },
+            Err(_) => CertificateStatus::Good,
+        }
+    }
+
+    /// Checks the signature of the list against the key whose certificates it lists.
+    pub fn verify(&self, issuer: &PublicKey) -> Result<(), Error> {
+        if issuer.id() != self.issuer {
+            return Err(Error::CertificateChainBroken);
+        }
+
+        let message = Self::signed_bytes(&self.issuer, &self.revoked, self.issued_at, self.next_update);
+        issuer.verify(&message, &self.signature)
+    }
+}
+
+impl StatusResponse {
+    pub(crate) fn new(
+        certificate: CertificateId,
+        status: CertificateStatus,
+        issuer: KeyId,
+        produced_at: Timestamp,
+        next_update: Timestamp,
+        signature: Signature,
+    ) -> Self {
+        Self { certificate, status, issuer, produced_at, next_update, signature }
+    }
+
+    pub(crate) fn signed_bytes(
+        certificate: &CertificateId,
+        status: &CertificateStatus,
+        issuer: &KeyId,
+        produced_at: Timestamp,
+        next_update: Timestamp,
+    ) -> Vec<u8> {
+        let mut buf = b"certificate-status".to_vec();
+        put_bytes(&mut buf, certificate);
+        status.put(&mut buf);
+        put_bytes(&mut buf, issuer);
+        put_u64(&mut buf, produced_at);
+        put_u64(&mut buf, next_update);
+        buf
+    }
+
+    pub fn certificate(&self) -> &CertificateId {
+        &self.certificate
+    }
+
+    pub fn status(&self) -> CertificateStatus {
+        self.status
+    }
+
+    /// Returns the id of the key that answered.
+    pub fn issuer(&self) -> &KeyId {
+        &self.issuer
+    }
+
+    pub fn produced_at(&self) -> Timestamp {
+        self.produced_at
+    }
+
+    pub fn next_update(&self) -> Timestamp {
+        self.next_update
+    }
+
+    /// Checks the signature of the response against the key that answered.
+    pub fn verify(&self, issuer: &PublicKey) -> Result<(), Error> {
+        if issuer.id() != self.issuer {
+            return Err(Error::CertificateChainBroken);
+        }
+
+        let message = Self::signed_bytes(&self.certificate, &self.status, &self.issuer, self.produced_at, self.next_update);
+        issuer.verify(&message, &self.signature)
+    }
+}
+
+impl CertificateRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an issued certificate, so that it can be revoked and answered for.
+    pub fn register(&mut self, certificate: AccountCertificate) -> CertificateId {
+        let id = certificate.id();
+        self.certificates.insert(id, certificate);
+        id
+    }
+
+    /// Records an issued issuer certificate, so that the key it certifies
+    /// can be revoked as an issuer.
+    pub fn register_issuer(&mut self, certificate: IssuerCertificate) -> CertificateId {
+        let id = certificate.id();
+        self.issuers.insert(id, certificate);
+        id
+    }
+
+    pub fn get(&self, id: &CertificateId) -> Option<&AccountCertificate> {
+        self.certificates.get(id)
+    }
+
+    pub fn get_issuer(&self, id: &CertificateId) -> Option<&IssuerCertificate> {
+        self.issuers.get(id)
+    }
+
+    /// Revokes a registered account or issuer certificate from `revoked_at` on.
+    pub fn revoke(&mut self, id: &CertificateId, revoked_at: Timestamp) -> Result<(), Error> {
+        if self.issued_by(id).is_none() {
+            return Err(Error::CertificateUnknown);
+        }
+
+        let earliest = self.revocations.entry(*id).or_insert(revoked_at);
+        // a later revocation can not push back an earlier one
+        *earliest = (*earliest).min(revoked_at);
+        Ok(())
+    }
+
+    pub fn status(&self, id: &CertificateId) -> CertificateStatus {
+        match (self.issued_by(id), self.revocations.get(id)) {
+            (None, _) => CertificateStatus::Unknown,
+            (Some(_), Some(revoked_at)) => CertificateStatus::Revoked { revoked_at: *revoked_at },
+            (Some(_), None) => CertificateStatus::Good,
+        }
+    }
+
+    // returns the key that issued a registered certificate of either kind
+    fn issued_by(&self, id: &CertificateId) -> Option<&KeyId> {
+        self.certificates.get(id).map(AccountCertificate::issuer)
+            .or_else(|| self.issuers.get(id).map(IssuerCertificate::issuer))
+    }
+
+    /// Lists the revoked certificates that `key` issued.
+    pub fn revocation_list(&self, key: &BankKey, issued_at: Timestamp, next_update: Timestamp) -> Result<RevocationList, Error> {
+        let issuer = key.verifying_key().id();
+        let mut revoked = self.revocations.iter()
+            .filter(|(id, _)| self.issued_by(id) == Some(&issuer))
+            .map(|(id, revoked_at)| (*id, *revoked_at))
+            .collect::<Vec<_>>();
+        revoked.sort_unstable();
+
+        let signature = key.sign_bytes(&RevocationList::signed_bytes(&issuer, &revoked, issued_at, next_update))?;
+        Ok(RevocationList::new(issuer, revoked, issued_at, next_update, signature))
+    }
+
+    /// Answers for a single certificate with `key`. Certificates issued by
+    /// other keys are unknown to it.
+    pub fn respond(
+        &self,
+        key: &BankKey,
+        id: &CertificateId,
+        produced_at: Timestamp,
+        next_update: Timestamp,
+    ) -> Result<StatusResponse, Error> {
+        let issuer = key.verifying_key().id();
+        let status = match self.issued_by(id) {
+            Some(by) if *by == issuer => self.status(id),
+            _ => CertificateStatus::Unknown,
+        };
+
+        let signature = key.sign_bytes(&StatusResponse::signed_bytes(id, &status, &issuer, produced_at, next_update))?;
+        Ok(StatusResponse::new(*id, status, issuer, produced_at, next_update, signature))
+    }
+}
+
+impl StatusCache {
+    pub fn new(max_age: Timestamp) -> Self {
+        Self { max_age, lists: HashMap::new(), responses: HashMap::new() }
+    }
+
+    pub fn max_age(&self) -> Timestamp {
+        self.max_age
+    }
+
+    fn is_fresh(&self, produced_at: Timestamp, next_update: Timestamp, now: Timestamp) -> bool {
+        is_fresh(self.max_age, produced_at, next_update, now)
+    }
+
+    /// Adds a revocation list signed by `issuer`, unless a newer one is known.
+    ///
+    /// Fails if the signature does not check out or if the list is not fresh at `now`.
+    pub fn add_list(&mut self, list: RevocationList, issuer: &PublicKey, now: Timestamp) -> Result<(), Error> {
+        list.verify(issuer)?;
+        if !self.is_fresh(list.issued_at, list.next_update, now) {
+            return Err(Error::StatusNotFresh);
+        }
+
+        let known = self.lists.get(&list.issuer).map(|known| known.issued_at);
+        if known.is_none_or(|issued_at| issued_at < list.issued_at) {
+            self.lists.insert(list.issuer, list);
+        }
+        Ok(())
+    }
+
+    /// Adds a status response signed by `issuer`, unless a newer one is known.
+    ///
+    /// Fails if the signature does not check out or if the response is not fresh at `now`.
+    pub fn add_response(&mut self, response: StatusResponse, issuer: &PublicKey, now: Timestamp) -> Result<(), Error> {
+        response.verify(issuer)?;
+        if !self.is_fresh(response.produced_at, response.next_update, now) {
+            return Err(Error::StatusNotFresh);
+        }
+
+        let known = self.responses.get(&response.certificate).map(|known| known.produced_at);
+        if known.is_none_or(|produced_at| produced_at < response.produced_at) {
+            self.responses.insert(response.certificate, response);
+        }
+        Ok(())
+    }
+
+    /// Returns the status of `certificate` as of `now`, preferring a status
+    /// response over the revocation list of the key that issued it.
+    pub fn status(&self, certificate: &AccountCertificate, now: Timestamp) -> Result<CertificateStatus, Error> {
+        self.status_of(&certificate.id(), certificate.issuer(), now)
+    }
+
+    /// Like [`StatusCache::status`], for an issuer certificate.
+    pub fn issuer_status(&self, certificate: &IssuerCertificate, now: Timestamp) -> Result<CertificateStatus, Error> {
+        self.status_of(&certificate.id(), certificate.issuer(), now)
+    }
+
+    fn status_of(&self, id: &CertificateId, issuer: &KeyId, now: Timestamp) -> Result<CertificateStatus, Error> {
+        let response = self.responses.get(id)
+            .filter(|response| response.issuer == *issuer)
+            .filter(|response| self.is_fresh(response.produced_at, response.next_update, now));
+        if let Some(response) = response {
+            return Ok(response.status);
+        }
+
+        self.lists.get(issuer)
+            .filter(|list| self.is_fresh(list.issued_at, list.next_update, now))
+            .map(|list| list.status(id))
+            .ok_or(Error::StatusNotFresh)
+    }
+
+    /// Checks with the status of `certificate` as of `now` that it was
+    /// neither expired nor revoked at `at`.
+    pub fn check(&self, certificate: &AccountCertificate, at: Timestamp, now: Timestamp) -> Result<(), Error> {
+        certificate.check_period(at)?;
+        self.status(certificate, now)?.check(at)
+    }
+
+    /// Like [`StatusCache::check`], for an issuer certificate.
+    pub fn check_issuer(&self, certificate: &IssuerCertificate, at: Timestamp, now: Timestamp) -> Result<(), Error> {
+        certificate.check_period(at)?;
+        self.issuer_status(certificate, now)?.check(at)
+    }
+
+    /// Drops everything that is no longer fresh at `now`.
+    pub fn prune(&mut self, now: Timestamp) {
+        let max_age = self.max_age;
+        self.lists.retain(|_, list| is_fresh(max_age, list.issued_at, list.next_update, now));
+        self.responses.retain(|_, response| is_fresh(max_age, response.produced_at, response.next_update, now));
+    }
+}
+
+fn is_fresh(max_age: Timestamp, produced_at: Timestamp, next_update: Timestamp, now: Timestamp) -> bool {
+    produced_at <= now && now < next_update && now - produced_at <= max_age
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::CertificateVerifier;
+    use crate::merchant::Merchant;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+    use crate::user::User;
+
+    const DAY: Timestamp = 24 * 60 * 60;
+
+    struct Setup {
+        root: BankKey,
+        user: User,
+        certificate: AccountCertificate,
+        registry: CertificateRegistry,
+    }
+
+    fn setup() -> Result<Setup, Error> {
+        let root = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let certificate = root.certify_account(&user, "Erika Mustermann", 0, 365 * DAY)?;
+        let mut registry = CertificateRegistry::new();
+        registry.register(certificate.clone());
+
+        Ok(Setup { root, user, certificate, registry })
+    }
+
+    #[test]
+    fn revocation_list() -> Result<(), Error> {
+        let mut s = setup()?;
+        let other = User::new("DE89370400440532013000".parse()?);
+        let kept = s.root.certify_account(&other, "Max Mustermann", 0, 365 * DAY)?;
+        s.registry.register(kept.clone());
+        s.registry.revoke(&s.certificate.id(), 10 * DAY)?;
+
+        let mut cache = StatusCache::new(2 * DAY);
+        let list = s.registry.revocation_list(&s.root, 10 * DAY, 11 * DAY)?;
+        assert_eq!(list.revoked(), &[(s.certificate.id(), 10 * DAY)]);
+        cache.add_list(list, s.root.verifying_key(), 10 * DAY)?;
+
+        // signatures made before the revocation stay valid
+        cache.check(&s.certificate, 9 * DAY, 10 * DAY)?;
+        assert_eq!(cache.check(&s.certificate, 10 * DAY, 10 * DAY), Err(Error::CertificateRevoked));
+        cache.check(&kept, 10 * DAY, 10 * DAY)?;
+        assert_eq!(cache.check(&kept, 365 * DAY, 10 * DAY), Err(Error::CertificateExpired));
+
+        // the list is due for an update
+        assert_eq!(cache.check(&kept, 10 * DAY, 11 * DAY), Err(Error::StatusNotFresh));
+        cache.prune(11 * DAY);
+        assert_eq!(cache, StatusCache::new(2 * DAY));
+
+        Ok(())
+    }
+
+    #[test]
+    fn responses() -> Result<(), Error> {
+        let mut s = setup()?;
+        let id = s.certificate.id();
+        let mut cache = StatusCache::new(DAY);
+
+        cache.add_response(s.registry.respond(&s.root, &id, 0, 7 * DAY)?, s.root.verifying_key(), 0)?;
+        assert_eq!(cache.status(&s.certificate, 0)?, CertificateStatus::Good);
+        // too old, even though the bank said it would not change for a week
+        assert_eq!(cache.status(&s.certificate, DAY + 1), Err(Error::StatusNotFresh));
+
+        s.registry.revoke(&id, 2 * DAY)?;
+        let revoked = s.registry.respond(&s.root, &id, 2 * DAY, 3 * DAY)?;
+        cache.add_response(revoked, s.root.verifying_key(), 2 * DAY)?;
+        // an older response does not replace a newer one
+        cache.add_response(s.registry.respond(&s.root, &id, DAY, 3 * DAY)?, s.root.verifying_key(), 2 * DAY)?;
+        assert_eq!(cache.status(&s.certificate, 2 * DAY)?, CertificateStatus::Revoked { revoked_at: 2 * DAY });
+
+        let stale = s.registry.respond(&s.root, &id, 0, 7 * DAY)?;
+        assert_eq!(cache.add_response(stale, s.root.verifying_key(), 2 * DAY), Err(Error::StatusNotFresh));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_certificates() -> Result<(), Error> {
+        let mut s = setup()?;
+        let branch = BankKey::new();
+        let foreign = branch.certify_account(&s.user, "Erika Mustermann", 0, 365 * DAY)?;
+        let mut cache = StatusCache::new(DAY);
+
+        // the root key does not answer for certificates of other keys
+        let response = s.registry.respond(&s.root, &s.certificate.id(), 0, DAY)?;
+        assert_eq!(response.status(), CertificateStatus::Good);
+        let response = s.registry.respond(&branch, &s.certificate.id(), 0, DAY)?;
+        assert_eq!(response.status(), CertificateStatus::Unknown);
+        assert_eq!(cache.add_response(response.clone(), s.root.verifying_key(), 0), Err(Error::CertificateChainBroken));
+        cache.add_response(response, branch.verifying_key(), 0)?;
+        assert_eq!(cache.check(&s.certificate, 0, 0), Err(Error::StatusNotFresh));
+
+        let response = s.registry.respond(&branch, &foreign.id(), 0, DAY)?;
+        cache.add_response(response, branch.verifying_key(), 0)?;
+        assert_eq!(cache.check(&foreign, 0, 0), Err(Error::CertificateUnknown));
+        assert_eq!(s.registry.revoke(&foreign.id(), 0), Err(Error::CertificateUnknown));
+
+        Ok(())
+    }
+
+    #[test]
+    fn revoked_transaction() -> Result<(), Error> {
+        let mut s = setup()?;
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let verifier = CertificateVerifier::new(*s.root.verifying_key());
+        s.registry.revoke(&s.certificate.id(), 10 * DAY)?;
+
+        let mut cache = StatusCache::new(DAY);
+        cache.add_list(s.registry.revocation_list(&s.root, 20 * DAY, 21 * DAY)?, s.root.verifying_key(), 20 * DAY)?;
+
+        // received before the revocation
+        let early = s.user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), s.user.clone(), 9 * DAY))?;
+        verifier.verify_transaction_status(&early, &[], &s.certificate, &cache, 9 * DAY, 20 * DAY)?;
+
+        let late = s.user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), s.user.clone(), 10 * DAY))?;
+        verifier.verify_transaction(&late, &[], &s.certificate, 10 * DAY)?;
+        assert_eq!(
+            verifier.verify_transaction_status(&late, &[], &s.certificate, &cache, 10 * DAY, 20 * DAY),
+            Err(Error::CertificateRevoked)
+        );
+
+        // signed with the revoked key and backdated
+        let backdated = s.user.sign(Transaction::new(1_000, Currency::EUR, merchant, s.user.clone(), 5 * DAY))?;
+        assert_eq!(
+            verifier.verify_transaction_status(&backdated, &[], &s.certificate, &cache, 20 * DAY, 20 * DAY),
+            Err(Error::CertificateRevoked)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn revoked_issuer() -> Result<(), Error> {
+        let mut s = setup()?;
+        let branch = BankKey::new();
+        let link = s.root.certify_issuer(branch.verifying_key(), 0, 365 * DAY)?;
+        let certificate = branch.certify_account(&s.user, "Erika Mustermann", 0, 365 * DAY)?;
+        let link_id = s.registry.register_issuer(link.clone());
+        s.registry.register(certificate.clone());
+        let verifier = CertificateVerifier::new(*s.root.verifying_key());
+
+        // the branch key leaked, which revokes everything it signed since
+        s.registry.revoke(&link_id, 10 * DAY)?;
+        let mut cache = StatusCache::new(DAY);
+        cache.add_list(s.registry.revocation_list(&s.root, 20 * DAY, 21 * DAY)?, s.root.verifying_key(), 20 * DAY)?;
+        assert_eq!(
+            verifier.verify_status(&s.user, std::slice::from_ref(&link), &certificate, &cache, 9 * DAY, 20 * DAY),
+            Err(Error::StatusNotFresh)
+        );
+        cache.add_list(s.registry.revocation_list(&branch, 20 * DAY, 21 * DAY)?, branch.verifying_key(), 20 * DAY)?;
+
+        let chain = [link];
+        verifier.verify_status(&s.user, &chain, &certificate, &cache, 9 * DAY, 20 * DAY)?;
+        verifier.verify(&s.user, &chain, &certificate, 10 * DAY)?;
+        assert_eq!(
+            verifier.verify_status(&s.user, &chain, &certificate, &cache, 10 * DAY, 20 * DAY),
+            Err(Error::CertificateRevoked)
+        );
+        assert_eq!(cache.issuer_status(&chain[0], 20 * DAY)?, CertificateStatus::Revoked { revoked_at: 10 * DAY });
+
+        Ok(())
+    }
+}
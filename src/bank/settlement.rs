@@ -0,0 +1,357 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+
+use super::{BankKey, Ledger};
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_u64};
+use crate::merkle::{MerkleProof, MerkleTree};
+use crate::traits::ToBytes;
+use crate::transaction::{Currency, SignedTransaction, TransactionId};
+use crate::{Error, Timestamp, IBAN};
+
+/// A random value hashed into a balance leaf, drawn afresh for every
+/// settlement.
+pub type SettlementSalt = [u8; 32];
+
+/// Something a settlement commits to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettlementLeaf {
+    /// A transaction settled during the period, debiting `account`.
+    Transaction { id: TransactionId, account: IBAN },
+    /// The balance of an account at the end of the period.
+    ///
+    /// Salted, since the hash of a leaf is handed to others as part of
+    /// their proofs, and account numbers and balances are few enough to
+    /// try them all.
+    Balance { account: IBAN, currency: Currency, balance: u64, salt: SettlementSalt },
+}
+
+/// The transactions settled between `start` and just before `end`, and
+/// the balances of all accounts at `end`, under one Merkle root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settlement {
+    start: Timestamp,
+    end: Timestamp,
+    // the transactions in the order they were settled, then the balances
+    // ordered by account and currency
+    leaves: Vec<SettlementLeaf>,
+}
+
+/// A settlement with the bank's signature over its root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedSettlement {
+    settlement: Settlement,
+    bank_key: PublicKey,
+    signature: Signature,
+}
+
+/// Proves that a single transaction or balance is part of a settlement the
+/// bank signed, without revealing anything else in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettlementProof {
+    start: Timestamp,
+    end: Timestamp,
+    leaf: SettlementLeaf,
+    root: [u8; 32],
+    bank_key: PublicKey,
+    signature: Signature,
+    proof: MerkleProof,
+}
+
+/// Collects the transactions settled during the current period and closes
+/// periods into signed settlements.
+#[derive(Debug)]
+pub struct SettlementBook {
+    key: BankKey,
+    start: Timestamp,
+    transactions: Vec<SettlementLeaf>,
+    settlements: Vec<SignedSettlement>,
+}
+
+impl ToBytes for SettlementLeaf {
+    fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Transaction { id, account } => {
+                let mut buf = b"settled-transaction".to_vec();
+                put_bytes(&mut buf, id);
+                put_iban(&mut buf, account);
+                buf
+            }
+            Self::Balance { account, currency, balance, salt } => {
+                let mut buf = b"settled-balance".to_vec();
+                put_iban(&mut buf, account);
+                put_bytes(&mut buf, &currency.as_bytes());
+                put_u64(&mut buf, *balance);
+                put_bytes(&mut buf, salt);
+                buf
+            }
+        }
+    }
+}
+
+impl Settlement {
+    /// Creates the settlement of a period from the transactions settled
+    /// during it, in order, and the ledger as it stands at its end.
+    pub fn new(start: Timestamp, end: Timestamp, transactions: &[SignedTransaction], ledger: &Ledger) -> Result<Self, Error> {
+        Self::from_rng(start, end, transactions, ledger, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`Settlement::new`], drawing the salts of the balances from `rng`.
+    pub fn from_rng(
+        start: Timestamp,
+        end: Timestamp,
+        transactions: &[SignedTransaction],
+        ledger: &Ledger,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, Error> {
+        let transactions = transactions.iter()
+            .map(|signed| SettlementLeaf::Transaction { id: signed.id(), account: *signed.transaction().user().account_number() })
+            .collect();
+        Self::from_leaves(start, end, transactions, ledger, rng)
+    }
+
+    fn from_leaves(
+        start: Timestamp,
+        end: Timestamp,
+        mut leaves: Vec<SettlementLeaf>,
+        ledger: &Ledger,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, Error> {
+        if end <= start {
+            return Err(Error::InvalidSettlementPeriod);
+        }
+
+        let mut balances = ledger.balances().collect::<Vec<_>>();
+        balances.sort_unstable_by_key(|(account, currency, _)| (account.to_string(), *currency));
+        leaves.extend(balances.into_iter().map(|(account, currency, balance)| {
+            let mut salt = [0; 32];
+            rng.fill_bytes(&mut salt);
+            SettlementLeaf::Balance { account: *account, currency, balance, salt }
+        }));
+
+        Ok(Self { start, end, leaves })
+    }
+
+    pub fn start(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn end(&self) -> Timestamp {
+        self.end
+    }
+
+    pub fn leaves(&self) -> &[SettlementLeaf] {
+        &self.leaves
+    }
+
+    pub fn tree(&self) -> MerkleTree {
+        let leaves = self.leaves.iter().map(SettlementLeaf::as_bytes).collect::<Vec<_>>();
+        MerkleTree::new(&leaves)
+    }
+
+    /// Returns the Merkle root over all leaves.
+    pub fn root(&self) -> [u8; 32] {
+        self.tree().root()
+    }
+
+    pub(crate) fn signed_bytes(bank_key: &PublicKey, start: Timestamp, end: Timestamp, leaf_count: usize, root: &[u8; 32]) -> Vec<u8> {
+        let mut buf = b"settlement".to_vec();
+        put_key(&mut buf, bank_key);
+        put_u64(&mut buf, start);
+        put_u64(&mut buf, end);
+        put_u64(&mut buf, leaf_count as u64);
+        buf.extend_from_slice(root);
+        buf
+    }
+}
+
+impl SignedSettlement {
+    pub(crate) fn new(settlement: Settlement, bank_key: PublicKey, signature: Signature) -> Self {
+        Self { settlement, bank_key, signature }
+    }
+
+    pub fn settlement(&self) -> &Settlement {
+        &self.settlement
+    }
+
+    /// Verifies that `bank_key` signed the root over exactly these leaves.
+    pub fn verify(&self, bank_key: &PublicKey) -> Result<(), Error> {
+        if *bank_key != self.bank_key {
+            return Err(Error::WrongSigner);
+        }
+
+        let settlement = &self.settlement;
+        let bytes = Settlement::signed_bytes(bank_key, settlement.start, settlement.end, settlement.leaves.len(), &settlement.root());
+        bank_key.verify(&bytes, &self.signature)
+    }
+
+    /// Returns the inclusion proof for a transaction settled in the period.
+    pub fn transaction_proof(&self, id: &TransactionId) -> Option<SettlementProof> {
+        self.proof(|leaf| matches!(leaf, SettlementLeaf::Transaction { id: settled, .. } if settled == id))
+    }
+
+    /// Returns the inclusion proof for the balance of `account` in `currency` at the end of the period.
+    pub fn balance_proof(&self, account: &IBAN, currency: Currency) -> Option<SettlementProof> {
+        self.proof(|leaf| matches!(leaf, SettlementLeaf::Balance { account: a, currency: c, .. } if a == account && *c == currency))
+    }
+
+    fn proof(&self, find: impl Fn(&SettlementLeaf) -> bool) -> Option<SettlementProof> {
+        let index = self.settlement.leaves.iter().position(find)?;
+        let tree = self.settlement.tree();
+
+        Some(SettlementProof {
+            start: self.settlement.start,
+            end: self.settlement.end,
+            leaf: self.settlement.leaves[index],
+            root: tree.root(),
+            bank_key: self.bank_key,
+            signature: self.signature,
+            proof: tree.proof(index)?,
+        })
+    }
+}
+
+impl SettlementProof {
+    pub fn start(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn end(&self) -> Timestamp {
+        self.end
+    }
+
+    pub fn leaf(&self) -> &SettlementLeaf {
+        &self.leaf
+    }
+
+    /// Returns the root of the settlement the leaf is part of.
+    pub fn root(&self) -> &[u8; 32] {
+        &self.root
+    }
+
+    /// Verifies that the leaf is part of the settlement and that `bank_key`
+    /// signed the settlement.
+    pub fn verify(&self, bank_key: &PublicKey) -> Result<(), Error> {
+        if *bank_key != self.bank_key {
+            return Err(Error::WrongSigner);
+        }
+
+        self.proof.verify(&self.leaf.as_bytes(), &self.root)?;
+        let bytes = Settlement::signed_bytes(bank_key, self.start, self.end, self.proof.leaf_count(), &self.root);
+        bank_key.verify(&bytes, &self.signature)
+    }
+}
+
+impl SettlementBook {
+    /// Creates a book whose first period starts at `start`.
+    pub fn new(key: BankKey, start: Timestamp) -> Self {
+        Self { key, start, transactions: Vec::new(), settlements: Vec::new() }
+    }
+
+    /// Returns when the current period started.
+    pub fn period_start(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn settlements(&self) -> &[SignedSettlement] {
+        &self.settlements
+    }
+
+    /// Returns the settlement of the period `at` falls into, if it was closed.
+    pub fn settlement(&self, at: Timestamp) -> Option<&SignedSettlement> {
+        self.settlements.iter().find(|signed| signed.settlement.start <= at && at < signed.settlement.end)
+    }
+
+    /// Applies a signed transaction received at `received_at` to the ledger
+    /// and adds it to the current period.
+    pub fn apply(&mut self, ledger: &mut Ledger, signed: &SignedTransaction, received_at: Timestamp) -> Result<(), Error> {
+        ledger.apply(signed, received_at)?;
+        self.record(signed);
+        Ok(())
+    }
+
+    /// Adds a transaction that was applied to the ledger to the current period.
+    pub fn record(&mut self, signed: &SignedTransaction) {
+        let account = *signed.transaction().user().account_number();
+        self.transactions.push(SettlementLeaf::Transaction { id: signed.id(), account });
+    }
+
+    /// Ends the current period at `end`, signing the root over its
+    /// transactions and the balances in `ledger`, and starts the next one.
+    pub fn close(&mut self, ledger: &Ledger, end: Timestamp) -> Result<&SignedSettlement, Error> {
+        self.close_from_rng(ledger, end, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`SettlementBook::close`], drawing the salts of the balances from `rng`.
+    pub fn close_from_rng(&mut self, ledger: &Ledger, end: Timestamp, rng: &mut impl CryptoRngCore) -> Result<&SignedSettlement, Error> {
+        let settlement = Settlement::from_leaves(self.start, end, self.transactions.clone(), ledger, rng)?;
+        let signed = self.key.sign_settlement(settlement)?;
+
+        self.transactions.clear();
+        self.start = end;
+        self.settlements.push(signed);
+        Ok(&self.settlements[self.settlements.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::time::DAY;
+    use crate::traits::TransactionSign;
+    use crate::transaction::Transaction;
+    use crate::user::User;
+
+    #[test]
+    fn daily_settlements() -> Result<(), Error> {
+        let key = BankKey::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 10_000)?;
+        ledger.deposit(user.account_number(), Currency::USD, 1_000)?;
+        let pay = |at| user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), at));
+
+        let mut book = SettlementBook::new(key.clone(), 0);
+        let (early, late) = ([pay(10)?, pay(20)?], pay(DAY + 10)?);
+        for signed in &early {
+            book.apply(&mut ledger, signed, 0)?;
+        }
+        book.close(&ledger, DAY)?;
+        book.apply(&mut ledger, &late, DAY)?;
+        book.close(&ledger, 2 * DAY)?;
+        assert_eq!(book.close(&ledger, 2 * DAY).err(), Some(Error::InvalidSettlementPeriod));
+
+        let first = book.settlement(DAY - 1).ok_or(Error::DevError)?;
+        first.verify(key.verifying_key())?;
+        // two transactions, then the balances of both accounts
+        assert_eq!(first.settlement().leaves().len(), 2 + 3);
+
+        let proof = first.transaction_proof(&early[1].id()).ok_or(Error::DevError)?;
+        user.verify_settled(&early[1], &proof, key.verifying_key())?;
+        assert_eq!(user.verify_settled(&early[0], &proof, key.verifying_key()), Err(Error::InvalidProof));
+        assert_eq!(proof.verify(BankKey::new().verifying_key()), Err(Error::WrongSigner));
+        assert_eq!(first.transaction_proof(&late.id()), None);
+
+        let second = book.settlement(DAY).ok_or(Error::DevError)?;
+        let proof = second.balance_proof(user.account_number(), Currency::EUR).ok_or(Error::DevError)?;
+        assert_eq!(user.verify_balance(Currency::EUR, &proof, key.verifying_key()), Ok(7_000));
+        assert_eq!(user.verify_balance(Currency::USD, &proof, key.verifying_key()), Err(Error::InvalidProof));
+
+        // a proof can not be passed off for another leaf
+        let mut forged = proof.clone();
+        let SettlementLeaf::Balance { salt, .. } = *proof.leaf() else { return Err(Error::DevError) };
+        forged.leaf = SettlementLeaf::Balance { account: *user.account_number(), currency: Currency::EUR, balance: 70_000, salt };
+        assert_eq!(forged.verify(key.verifying_key()), Err(Error::InvalidProof));
+
+        // an unchanged balance hashes differently in every settlement, so
+        // its leaf can not be matched against guesses or earlier settlements
+        let usd = |settlement: &SignedSettlement| settlement.balance_proof(user.account_number(), Currency::USD).map(|proof| proof.leaf.as_bytes());
+        assert_ne!(usd(first), usd(second));
+
+        Ok(())
+    }
+}
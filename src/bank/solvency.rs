@@ -0,0 +1,452 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+
+use super::{EscrowBook, Ledger, OfflineBook, TokenBook};
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_u64};
+use crate::merkle::{hash_sum_leaf, MerkleSumProof, MerkleSumTree};
+use crate::traits::ToBytes;
+use crate::transaction::Currency;
+use crate::{Error, Timestamp, IBAN};
+
+/// A random value hashed along with an account, so that the leaf hashes a
+/// proof reveals can not be linked to the accounts of other users.
+pub type LiabilitySalt = [u8; 32];
+
+/// How many leaves every balance is split into.
+pub const LIABILITY_PARTS: usize = 4;
+
+/// What the bank owes its account holders in one currency at one point in
+/// time, as a Merkle sum tree over every balance.
+///
+/// Besides the balances in the ledger, the bank owes what it holds back for
+/// offline allowances and open escrows, which is counted with the balance
+/// of the account it was taken from, and the value of outstanding tokens,
+/// which is owed to their unknown bearers.
+///
+/// Every amount is split into [`LIABILITY_PARTS`] random parts, each a leaf
+/// of its own, and the tree is padded to a power of two with leaves worth
+/// nothing. A proof thus shows its holder random parts of other balances
+/// next to its own, but never a whole one, and the number of leaves tells
+/// only roughly how many accounts there are.
+///
+/// Balances are unsigned and sums are checked for overflow, so no account
+/// can be given a negative balance to make the total look smaller.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Liabilities {
+    currency: Currency,
+    at: Timestamp,
+    // ordered by leaf hash, so that positions in the tree say nothing about accounts
+    leaves: Vec<(Holder, u64, LiabilitySalt)>,
+    tree: MerkleSumTree,
+}
+
+// whom the value of a leaf is owed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Holder {
+    Account(IBAN),
+    Tokens,
+    Padding,
+}
+
+/// The bank's signed statement of the root and total of its liabilities in
+/// one currency, to be published.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiabilityStatement {
+    currency: Currency,
+    at: Timestamp,
+    root: [u8; 32],
+    total: u64,
+    leaf_count: u64,
+    bank_key: PublicKey,
+    signature: Signature,
+}
+
+/// Proves to the holder of an account that its balance is counted in a
+/// statement, revealing only sums of random parts of other, unnamed
+/// balances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiabilityProof {
+    account: IBAN,
+    balance: u64,
+    // one proof for each part of the balance
+    parts: Vec<(u64, LiabilitySalt, MerkleSumProof)>,
+}
+
+impl Liabilities {
+    /// Builds the tree over the balances of all accounts in `currency`,
+    /// along with the funds taken out of the ledger for unreconciled
+    /// offline allowances, open escrows and outstanding tokens.
+    pub fn new(
+        ledger: &Ledger,
+        offline: &OfflineBook,
+        escrows: &EscrowBook,
+        tokens: &TokenBook,
+        currency: Currency,
+        at: Timestamp,
+    ) -> Result<Self, Error> {
+        Self::from_rng(ledger, offline, escrows, tokens, currency, at, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`Liabilities::new`], drawing the parts and salts from `rng`.
+    pub fn from_rng(
+        ledger: &Ledger,
+        offline: &OfflineBook,
+        escrows: &EscrowBook,
+        tokens: &TokenBook,
+        currency: Currency,
+        at: Timestamp,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<Self, Error> {
+        let mut balances = HashMap::<IBAN, u64>::new();
+        let held = ledger.balances().filter(|(_, held, _)| *held == currency).map(|(account, _, balance)| (account, balance));
+        for (account, amount) in held.chain(offline.reserved(currency)).chain(escrows.reserved(currency)) {
+            let balance = balances.entry(*account).or_default();
+            *balance = balance.checked_add(amount).ok_or(Error::AmountOverflow)?;
+        }
+        let mut balances = balances.into_iter().collect::<Vec<_>>();
+        // parts and salts are drawn in a fixed order, so that a seeded `rng` gives the same tree
+        balances.sort_unstable_by_key(|(account, _)| account.to_string());
+        let owed = balances.into_iter()
+            .map(|(account, balance)| (Holder::Account(account), balance))
+            .chain([(Holder::Tokens, tokens.outstanding(currency))]);
+
+        let mut leaves = Vec::new();
+        for (holder, balance) in owed {
+            leaves.extend(split(balance, rng).into_iter().map(|part| (holder, part, salt(rng))));
+        }
+        let padding = leaves.len().next_power_of_two() - leaves.len();
+        leaves.extend((0..padding).map(|_| (Holder::Padding, 0, salt(rng))));
+        leaves.sort_unstable_by_key(|(holder, value, salt)| hash_sum_leaf(&leaf_data(holder, salt), *value));
+
+        let data = leaves.iter().map(|(holder, value, salt)| (leaf_data(holder, salt), *value)).collect::<Vec<_>>();
+        let tree = MerkleSumTree::new(&data)?;
+        Ok(Self { currency, at, leaves, tree })
+    }
+
+    /// Builds the liabilities in every currency the ledger holds balances in.
+    pub fn per_currency(ledger: &Ledger, offline: &OfflineBook, escrows: &EscrowBook, tokens: &TokenBook, at: Timestamp) -> Result<Vec<Self>, Error> {
+        let mut currencies = ledger.balances().map(|(_, currency, _)| currency).collect::<Vec<_>>();
+        currencies.sort_unstable();
+        currencies.dedup();
+
+        currencies.into_iter().map(|currency| Self::new(ledger, offline, escrows, tokens, currency, at)).collect()
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn at(&self) -> Timestamp {
+        self.at
+    }
+
+    /// Returns the Merkle root over all balances.
+    pub fn root(&self) -> [u8; 32] {
+        self.tree.root()
+    }
+
+    /// Returns the sum of all balances.
+    pub fn total(&self) -> u64 {
+        self.tree.total()
+    }
+
+    /// Returns the number of leaves, which are the parts of every balance
+    /// and the padding.
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Returns the proof for the balance of `account`, to be handed to its holder only.
+    pub fn proof(&self, account: &IBAN) -> Option<LiabilityProof> {
+        let holder = Holder::Account(*account);
+        let parts = self.leaves.iter().enumerate()
+            .filter(|(_, (leaf, _, _))| *leaf == holder)
+            .map(|(index, (_, value, salt))| Some((*value, *salt, self.tree.proof(index)?)))
+            .collect::<Option<Vec<_>>>()?;
+        if parts.is_empty() {
+            return None;
+        }
+
+        let balance = parts.iter().map(|(value, _, _)| value).sum();
+        Some(LiabilityProof { account: *account, balance, parts })
+    }
+
+    pub(crate) fn signed_bytes(
+        bank_key: &PublicKey,
+        currency: Currency,
+        at: Timestamp,
+        leaf_count: u64,
+        total: u64,
+        root: &[u8; 32],
+    ) -> Vec<u8> {
+        let mut buf = b"liabilities".to_vec();
+        put_key(&mut buf, bank_key);
+        put_bytes(&mut buf, &currency.as_bytes());
+        put_u64(&mut buf, at);
+        put_u64(&mut buf, leaf_count);
+        put_u64(&mut buf, total);
+        buf.extend_from_slice(root);
+        buf
+    }
+}
+
+impl LiabilityStatement {
+    pub(crate) fn new(liabilities: &Liabilities, bank_key: PublicKey, signature: Signature) -> Self {
+        Self {
+            currency: liabilities.currency,
+            at: liabilities.at,
+            root: liabilities.root(),
+            total: liabilities.total(),
+            leaf_count: liabilities.leaf_count() as u64,
+            bank_key,
+            signature,
+        }
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn at(&self) -> Timestamp {
+        self.at
+    }
+
+    pub fn root(&self) -> &[u8; 32] {
+        &self.root
+    }
+
+    /// Returns the sum of all balances in the currency.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the number of leaves the balances were split into.
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    /// Checks that the statement was signed by `bank_key`.
+    pub fn verify(&self, bank_key: &PublicKey) -> Result<(), Error> {
+        if *bank_key != self.bank_key {
+            return Err(Error::WrongSigner);
+        }
+
+        let bytes = Liabilities::signed_bytes(bank_key, self.currency, self.at, self.leaf_count, self.total, &self.root);
+        bank_key.verify(&bytes, &self.signature)
+    }
+}
+
+impl LiabilityProof {
+    pub fn account(&self) -> &IBAN {
+        &self.account
+    }
+
+    pub fn balance(&self) -> u64 {
+        self.balance
+    }
+
+    /// Verifies that every part of the balance is counted in the total of
+    /// `statement`. Does not check the signature of the statement.
+    pub fn verify(&self, statement: &LiabilityStatement) -> Result<(), Error> {
+        let mut indices = HashSet::new();
+        let mut balance = 0u64;
+        for (value, salt, proof) in &self.parts {
+            // a part counted twice would hide that another part is missing
+            if proof.leaf_count() as u64 != statement.leaf_count || !indices.insert(proof.index()) {
+                return Err(Error::InvalidProof);
+            }
+
+            proof.verify(&leaf_data(&Holder::Account(self.account), salt), *value, &statement.root, statement.total)?;
+            balance = balance.checked_add(*value).ok_or(Error::InvalidProof)?;
+        }
+
+        if balance != self.balance || self.parts.is_empty() {
+            return Err(Error::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+// splits `amount` into random parts that add up to it
+fn split(amount: u64, rng: &mut impl CryptoRngCore) -> Vec<u64> {
+    let mut cuts = (1..LIABILITY_PARTS).map(|_| rng.gen_range(0..=amount)).collect::<Vec<_>>();
+    cuts.sort_unstable();
+    cuts.push(amount);
+
+    let mut previous = 0;
+    cuts.into_iter()
+        .map(|cut| {
+            let part = cut - previous;
+            previous = cut;
+            part
+        })
+        .collect()
+}
+
+fn salt(rng: &mut impl CryptoRngCore) -> LiabilitySalt {
+    let mut salt = [0; 32];
+    rng.fill_bytes(&mut salt);
+    salt
+}
+
+fn leaf_data(holder: &Holder, salt: &LiabilitySalt) -> Vec<u8> {
+    let mut buf = b"liability".to_vec();
+    put_bytes(&mut buf, salt);
+    match holder {
+        Holder::Account(account) => {
+            buf.push(0);
+            put_iban(&mut buf, account);
+        }
+        Holder::Tokens => buf.push(1),
+        Holder::Padding => buf.push(2),
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{BankKey, Condition, Decision};
+    use crate::merchant::Merchant;
+    use crate::token::{Denomination, MintKey};
+    use crate::traits::TransactionSign;
+    use crate::transaction::Transaction;
+    use crate::user::User;
+
+    #[test]
+    fn prove_liabilities() -> Result<(), Error> {
+        let key = BankKey::new();
+        let users = ["DE89370400440532013000", "GB82WEST12345698765432", "FR1420041010050500013M02606"]
+            .into_iter()
+            .map(|account| Ok(User::new(account.parse()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut ledger = Ledger::new();
+        for (user, balance) in users.iter().zip([1_000, 0, 25_000]) {
+            ledger.deposit(user.account_number(), Currency::EUR, balance)?;
+        }
+        ledger.deposit(users[0].account_number(), Currency::USD, 700)?;
+
+        let all = Liabilities::per_currency(&ledger, &OfflineBook::new(key.clone()), &EscrowBook::new(), &TokenBook::new(), 100)?;
+        // four parts for each account and for the tokens, padded to a power of two
+        assert_eq!(all.iter().map(|l| (l.currency(), l.total(), l.leaf_count())).collect::<Vec<_>>(), [
+            (Currency::EUR, 26_000, 16),
+            (Currency::USD, 700, 8)
+        ]);
+
+        let statements = all.iter().map(|liabilities| key.sign_liabilities(liabilities)).collect::<Result<Vec<_>, _>>()?;
+        for (user, balance) in users.iter().zip([1_000, 0, 25_000]) {
+            let proof = all[0].proof(user.account_number()).ok_or(Error::DevError)?;
+            assert_eq!(user.verify_liabilities(&statements[0], &proof, key.verifying_key()), Ok(balance));
+            // the proof only holds for the statement it was made for
+            assert_eq!(proof.verify(&statements[1]), Err(Error::InvalidProof));
+        }
+
+        let proof = all[0].proof(users[2].account_number()).ok_or(Error::DevError)?;
+        assert_eq!(users[0].verify_liabilities(&statements[0], &proof, key.verifying_key()), Err(Error::InvalidProof));
+        assert_eq!(
+            users[2].verify_liabilities(&statements[0], &proof, BankKey::new().verifying_key()),
+            Err(Error::WrongSigner)
+        );
+
+        // a bank leaving out part of a balance is caught by its holder
+        let understated = LiabilityProof { balance: 20_000, ..proof.clone() };
+        assert_eq!(understated.verify(&statements[0]), Err(Error::InvalidProof));
+        let mut dropped = proof.clone();
+        dropped.parts.pop();
+        assert_eq!(dropped.verify(&statements[0]), Err(Error::InvalidProof));
+        // nor by counting one part twice in place of another
+        let mut counted_twice = proof.clone();
+        counted_twice.parts[1] = proof.parts[0].clone();
+        counted_twice.balance = counted_twice.parts.iter().map(|(value, _, _)| value).sum();
+        assert_eq!(counted_twice.verify(&statements[0]), Err(Error::InvalidProof));
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_salts() -> Result<(), Error> {
+        let mut ledger = Ledger::new();
+        ledger.deposit(&"DE89370400440532013000".parse()?, Currency::EUR, 1_000)?;
+        ledger.deposit(&"GB82WEST12345698765432".parse()?, Currency::EUR, 2_000)?;
+
+        let (offline, escrows, tokens) = (OfflineBook::new(BankKey::new()), EscrowBook::new(), TokenBook::new());
+        let tree = |seed| Liabilities::from_rng(&ledger, &offline, &escrows, &tokens, Currency::EUR, 0, &mut ChaCha20Rng::seed_from_u64(seed));
+        assert_eq!(tree(1)?, tree(1)?);
+        assert_ne!(tree(1)?.root(), tree(2)?.root());
+        Ok(())
+    }
+
+    #[test]
+    fn hidden_balances() -> Result<(), Error> {
+        let key = BankKey::new();
+        let users = ["DE89370400440532013000", "GB82WEST12345698765432", "FR1420041010050500013M02606"]
+            .into_iter()
+            .map(|account| Ok(User::new(account.parse()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let balances = [1_000, 7_000, 25_000];
+        let mut ledger = Ledger::new();
+        for (user, balance) in users.iter().zip(balances) {
+            ledger.keys_mut().enroll(user, 0, None)?;
+            ledger.deposit(user.account_number(), Currency::EUR, balance)?;
+        }
+
+        // funds taken out of the ledger are still owed
+        let mut offline = OfflineBook::new(key.clone());
+        offline.issue(&mut ledger, &users[1], Currency::EUR, 2_000, 0, 100)?;
+        let five = Denomination::new(5_000, Currency::EUR);
+        let mut tokens = TokenBook::new();
+        tokens.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0)));
+        let token_key = tokens.token_key(&five).ok_or(Error::DevError)?.clone();
+        tokens.withdraw(&mut ledger, &users[2].withdraw_token(&token_key)?.1, 0)?;
+
+        let escrows = EscrowBook::new();
+        let liabilities = Liabilities::from_rng(&ledger, &offline, &escrows, &tokens, Currency::EUR, 0, &mut ChaCha20Rng::seed_from_u64(0))?;
+        assert_eq!((liabilities.total(), liabilities.leaf_count()), (33_000, 16));
+        let statement = key.sign_liabilities(&liabilities)?;
+
+        for (user, balance) in users.iter().zip([1_000, 7_000, 20_000]) {
+            let proof = liabilities.proof(user.account_number()).ok_or(Error::DevError)?;
+            assert_eq!(user.verify_liabilities(&statement, &proof, key.verifying_key()), Ok(balance));
+            assert_eq!(proof.parts.len(), LIABILITY_PARTS);
+
+            // the leaf next to each part is a random part or padding, never another whole balance
+            for (_, _, part) in &proof.parts {
+                let neighbour = part.sibling_sums().next().ok_or(Error::DevError)?;
+                assert!([1_000, 7_000, 20_000, 5_000].iter().all(|other| *other == balance || *other != neighbour));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn open_escrows() -> Result<(), Error> {
+        let key = BankKey::new();
+        let buyer = User::new("DE89370400440532013000".parse()?);
+        let seller = Merchant::new("GB82WEST12345698765432".to_string());
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&buyer, 0, None)?;
+        ledger.deposit(buyer.account_number(), Currency::EUR, 10_000)?;
+
+        let mut escrows = EscrowBook::new();
+        let mut open = |amount, at| {
+            let (release, refund) = (Condition::SignedBy(*seller.verifying_key()), Condition::SignedBy(*seller.verifying_key()));
+            let signed = buyer.sign(Transaction::new(amount, Currency::EUR, seller.clone(), buyer.clone(), at).for_escrow(&release, &refund))?;
+            escrows.open(&mut ledger, &signed, release, refund, at)
+        };
+        open(3_000, 1)?;
+        let released = open(2_000, 2)?;
+        escrows.release(&mut ledger, &released, &[seller.approve_escrow(&released, Decision::Release)?], 3)?;
+        assert_eq!(ledger.balance(buyer.account_number(), Currency::EUR), 5_000);
+
+        // the open escrow is still owed to the buyer, the released one to the seller
+        let liabilities = Liabilities::new(&ledger, &OfflineBook::new(key.clone()), &escrows, &TokenBook::new(), Currency::EUR, 3)?;
+        assert_eq!(liabilities.total(), 10_000);
+        let statement = key.sign_liabilities(&liabilities)?;
+        let proof = liabilities.proof(buyer.account_number()).ok_or(Error::DevError)?;
+        assert_eq!(buyer.verify_liabilities(&statement, &proof, key.verifying_key()), Ok(8_000));
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::{HashMap, HashSet};
+
+use super::Ledger;
+use crate::token::{BlindSignature, Denomination, MintKey, Token, TokenKey, TokenKeyId, TokenSerial, Withdrawal, WithdrawalId};
+use crate::transaction::Currency;
+use crate::{Error, Timestamp, IBAN};
+
+/// Issues blind-signed tokens against account balances and takes them back
+/// in, keeping every deposited serial so that no token is paid out twice.
+#[derive(Debug, Default)]
+pub struct TokenBook {
+    // every key tokens were ever signed with, so tokens outlive key changes
+    keys: HashMap<TokenKeyId, MintKey>,
+    // the key new tokens of each denomination are signed with
+    current: HashMap<Denomination, TokenKeyId>,
+    withdrawn: HashSet<WithdrawalId>,
+    // the value of the tokens withdrawn but not deposited yet
+    outstanding: HashMap<Currency, u64>,
+    // the double-spend database, with the time each token was deposited
+    spent: HashMap<TokenSerial, Timestamp>,
+}
+
+impl TokenBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts issuing tokens of the key's denomination with it.
+    ///
+    /// The previous key of the denomination is retired: it signs no more
+    /// tokens, but the tokens it signed can still be deposited.
+    pub fn add_key(&mut self, key: MintKey) {
+        let id = key.token_key().id();
+        self.current.insert(key.denomination(), id);
+        self.keys.insert(id, key);
+    }
+
+    /// Returns the key users blind tokens of `denomination` for.
+    pub fn token_key(&self, denomination: &Denomination) -> Option<&TokenKey> {
+        self.current.get(denomination).and_then(|id| self.keys.get(id)).map(MintKey::token_key)
+    }
+
+    /// Signs the blinded token of a withdrawal, debiting the user's account
+    /// with its denomination. The user must have signed the withdrawal with
+    /// the key of their account at `received_at`. Each withdrawal is
+    /// processed once.
+    pub fn withdraw(&mut self, ledger: &mut Ledger, withdrawal: &Withdrawal, received_at: Timestamp) -> Result<BlindSignature, Error> {
+        withdrawal.verify()?;
+        let user = withdrawal.user();
+        ledger.authorize(user.account_number(), user.verifying_key(), received_at)?;
+        let id = withdrawal.id();
+        if self.withdrawn.contains(&id) {
+            return Err(Error::DuplicateWithdrawal);
+        }
+
+        let denomination = withdrawal.denomination();
+        let current = self.current.get(&denomination).ok_or(Error::UnknownDenomination)?;
+        if current != withdrawal.key_id() {
+            return Err(Error::TokenKeyRetired);
+        }
+
+        let signature = self.keys[current].sign_blinded(withdrawal.blinded())?;
+        let outstanding = self.outstanding(denomination.currency()).checked_add(denomination.amount()).ok_or(Error::AmountOverflow)?;
+        ledger.withdraw(withdrawal.user().account_number(), denomination.currency(), denomination.amount())?;
+
+        self.outstanding.insert(denomination.currency(), outstanding);
+        self.withdrawn.insert(id);
+        Ok(signature)
+    }
+
+    /// Credits `account` with the denomination of a token, unless the token
+    /// is forged or was deposited before.
+    pub fn deposit(&mut self, ledger: &mut Ledger, token: &Token, account: &IBAN, at: Timestamp) -> Result<(), Error> {
+        let key = self.keys.get(token.key_id()).ok_or(Error::InvalidToken)?;
+        key.token_key().verify(token)?;
+        if self.spent.contains_key(token.serial()) {
+            return Err(Error::DoubleSpend);
+        }
+
+        let denomination = token.denomination();
+        ledger.deposit(account, denomination.currency(), denomination.amount())?;
+        let outstanding = self.outstanding(denomination.currency()).saturating_sub(denomination.amount());
+        self.outstanding.insert(denomination.currency(), outstanding);
+        self.spent.insert(*token.serial(), at);
+        Ok(())
+    }
+
+    /// Returns the value of the tokens in `currency` that were withdrawn and
+    /// not deposited yet, which the bank still owes their bearers.
+    pub fn outstanding(&self, currency: Currency) -> u64 {
+        self.outstanding.get(&currency).copied().unwrap_or(0)
+    }
+
+    /// Returns when a token was deposited, if it was.
+    pub fn spent_at(&self, serial: &TokenSerial) -> Option<Timestamp> {
+        self.spent.get(serial).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::transaction::Currency;
+    use crate::user::User;
+
+    #[test]
+    fn withdraw_and_deposit() -> Result<(), Error> {
+        let five = Denomination::new(5_000, Currency::EUR);
+        let mut book = TokenBook::new();
+        book.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0)));
+        let key = book.token_key(&five).ok_or(Error::UnknownDenomination)?.clone();
+
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let merchant_account = merchant.account_number().parse()?;
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 7_000)?;
+
+        let (pending, withdrawal) = user.withdraw_token(&key)?;
+        let token = pending.finish(&key, &book.withdraw(&mut ledger, &withdrawal, 0)?)?;
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 2_000);
+        assert_eq!(book.outstanding(Currency::EUR), 5_000);
+        // a replayed withdrawal debits the user only once
+        assert_eq!(book.withdraw(&mut ledger, &withdrawal, 0), Err(Error::DuplicateWithdrawal));
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 2_000);
+
+        // the merchant checks the token offline and deposits it later
+        key.verify(&token)?;
+        book.deposit(&mut ledger, &token, &merchant_account, 100)?;
+        assert_eq!(ledger.balance(&merchant_account, Currency::EUR), 5_000);
+        assert_eq!(book.spent_at(token.serial()), Some(100));
+        assert_eq!(book.outstanding(Currency::EUR), 0);
+
+        assert_eq!(book.deposit(&mut ledger, &token, user.account_number(), 200), Err(Error::DoubleSpend));
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 2_000);
+
+        // the second withdrawal is not covered
+        let (_, withdrawal) = user.withdraw_token(&key)?;
+        assert_eq!(book.withdraw(&mut ledger, &withdrawal, 0), Err(Error::InsufficientFunds));
+
+        Ok(())
+    }
+
+    #[test]
+    fn refused() -> Result<(), Error> {
+        let five = Denomination::new(5_000, Currency::EUR);
+        let mint = MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0));
+        let key = mint.token_key().clone();
+        let mut book = TokenBook::new();
+        let mut ledger = Ledger::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 5_000)?;
+
+        let (pending, withdrawal) = user.withdraw_token(&key)?;
+        assert_eq!(book.withdraw(&mut ledger, &withdrawal, 0), Err(Error::UnknownDenomination));
+
+        // someone else can not have the user pay for their tokens
+        let (_, own) = User::new(*user.account_number()).withdraw_token(&key)?;
+        let forged = Withdrawal::new(user.to_public(), &key, own.blinded().to_vec(), *own.signature());
+        book.add_key(mint);
+        assert_eq!(book.withdraw(&mut ledger, &forged, 0), Err(Error::InvalidSignature));
+        // nor with a key of their own for the user's account
+        assert_eq!(book.withdraw(&mut ledger, &own, 0), Err(Error::KeyNotValid));
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 5_000);
+
+        let token = pending.finish(&key, &book.withdraw(&mut ledger, &withdrawal, 0)?)?;
+        let forged = Token::new(five, key.id(), [0; 32], vec![0; 256]);
+        assert_eq!(book.deposit(&mut ledger, &forged, user.account_number(), 0), Err(Error::InvalidToken));
+        book.deposit(&mut ledger, &token, user.account_number(), 0)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_rotation() -> Result<(), Error> {
+        let five = Denomination::new(5_000, Currency::EUR);
+        let mut book = TokenBook::new();
+        book.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(0)));
+        let old = book.token_key(&five).ok_or(Error::UnknownDenomination)?.clone();
+
+        let user = User::new("DE89370400440532013000".parse()?);
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 10_000)?;
+        let (pending, withdrawal) = user.withdraw_token(&old)?;
+        let token = pending.finish(&old, &book.withdraw(&mut ledger, &withdrawal, 0)?)?;
+
+        book.add_key(MintKey::from_rng(five, &mut ChaCha20Rng::seed_from_u64(1)));
+        let new = book.token_key(&five).ok_or(Error::UnknownDenomination)?.clone();
+        assert_ne!(new.id(), old.id());
+
+        // the retired key signs nothing new, but its tokens are still good
+        let (_, withdrawal) = user.withdraw_token(&old)?;
+        assert_eq!(book.withdraw(&mut ledger, &withdrawal, 0), Err(Error::TokenKeyRetired));
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 5_000);
+        book.deposit(&mut ledger, &token, user.account_number(), 0)?;
+        assert_eq!(ledger.balance(user.account_number(), Currency::EUR), 10_000);
+
+        let (pending, withdrawal) = user.withdraw_token(&new)?;
+        pending.finish(&new, &book.withdraw(&mut ledger, &withdrawal, 0)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,224 @@
+use rayon::prelude::*;
+
+use crate::crypto::{Algorithm, KeyCache, PublicKey};
+use crate::transaction::SignedTransaction;
+use crate::Error;
+
+/// The signature algorithms a bank accepts.
+///
+/// Enforced wherever a bank verifies user-signed transactions: by
+/// [`Ledger`](super::Ledger), [`KeyRegistry`](super::KeyRegistry),
+/// [`CertificateVerifier`](super::CertificateVerifier) and [`BatchVerifier`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationPolicy {
+    accepted: Vec<Algorithm>,
+}
+
+impl Default for VerificationPolicy {
+    /// Accepts every algorithm rustpay supports.
+    fn default() -> Self {
+        Self::new(vec![Algorithm::EcdsaP256, Algorithm::Ed25519])
+    }
+}
+
+impl VerificationPolicy {
+    pub fn new(accepted: Vec<Algorithm>) -> Self {
+        Self { accepted }
+    }
+
+    pub fn accepted(&self) -> &[Algorithm] {
+        &self.accepted
+    }
+
+    pub fn accepts(&self, algorithm: Algorithm) -> bool {
+        self.accepted.contains(&algorithm)
+    }
+
+    /// Checks that every key involved in a signed transaction uses an
+    /// accepted algorithm: the user's, all along the delegation chain and
+    /// the one of the signature, without verifying any signature.
+    pub fn check(&self, signed: &SignedTransaction) -> Result<(), Error> {
+        let delegation = signed.delegation().iter().flat_map(|certificate| [certificate.delegator(), certificate.delegate()]);
+        let keys = std::iter::once(signed.transaction().user().verifying_key()).chain(delegation);
+
+        if !keys.map(PublicKey::algorithm).chain([signed.algorithm()]).all(|algorithm| self.accepts(algorithm)) {
+            return Err(Error::AlgorithmNotAccepted);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the algorithms of a signed transaction, then verifies it.
+    pub fn verify(&self, signed: &SignedTransaction) -> Result<(), Error> {
+        self.check(signed)?;
+        signed.verify()
+    }
+}
+
+/// Verifies large numbers of signed transactions at once, e.g. the
+/// end-of-day files a bank settles.
+#[derive(Debug, Default)]
+pub struct BatchVerifier {
+    policy: VerificationPolicy,
+    keys: KeyCache,
+}
+
+/// The outcome of verifying a batch, one result per transaction in the
+/// order they were given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    results: Vec<Result<(), Error>>,
+}
+
+impl BatchVerifier {
+    pub fn new(policy: VerificationPolicy) -> Self {
+        Self { policy, keys: KeyCache::new() }
+    }
+
+    /// Bounds the number of signing keys the verifier keeps by key id.
+    pub fn with_key_capacity(mut self, capacity: usize) -> Self {
+        self.keys = KeyCache::with_capacity(capacity);
+        self
+    }
+
+    pub fn policy(&self) -> &VerificationPolicy {
+        &self.policy
+    }
+
+    /// Returns the signing keys seen by [`BatchVerifier::verify`], shared
+    /// across batches so that regular customers' keys stay cached.
+    pub fn keys(&self) -> &KeyCache {
+        &self.keys
+    }
+
+    /// Verifies every transaction against the policy, spread across all
+    /// threads of the global rayon pool. One invalid transaction does not
+    /// affect the results of the others.
+    pub fn verify(&self, batch: &[SignedTransaction]) -> VerificationReport {
+        let results = batch
+            .par_iter()
+            .map(|signed| {
+                self.policy.check(signed)?;
+                signed.verify_cached(&self.keys)
+            })
+            .collect();
+        VerificationReport { results }
+    }
+}
+
+impl VerificationReport {
+    pub fn results(&self) -> &[Result<(), Error>] {
+        &self.results
+    }
+
+    /// Returns the indices of all transactions that verified.
+    pub fn succeeded(&self) -> Vec<usize> {
+        self.results.iter().enumerate().filter(|(_, r)| r.is_ok()).map(|(i, _)| i).collect()
+    }
+
+    /// Returns the indices of all transactions that failed, along with the reason.
+    pub fn failed(&self) -> Vec<(usize, Error)> {
+        self.results.iter().enumerate().filter_map(|(i, r)| r.err().map(|e| (i, e))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{BankKey, CertificateVerifier, KeyRegistry, Ledger};
+    use crate::merchant::Merchant;
+    use crate::signer::Ed25519Signer;
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+    use crate::user::{Allowance, User};
+
+    #[test]
+    fn ed25519_user() -> Result<(), Error> {
+        let user = User::with_signer("DE89370400440532013000".parse()?, Ed25519Signer::random());
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0))?;
+
+        assert_eq!(signed.algorithm(), Algorithm::Ed25519);
+        VerificationPolicy::default().verify(&signed)?;
+        assert_eq!(
+            VerificationPolicy::new(vec![Algorithm::EcdsaP256]).verify(&signed),
+            Err(Error::AlgorithmNotAccepted)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let other = User::with_signer("AT611904300234573201".parse()?, Ed25519Signer::random());
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+
+        let mut batch = (0..20)
+            .map(|i| user.sign(Transaction::new(1_000 + i, Currency::EUR, merchant.clone(), user.clone(), i)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        batch[7] = other.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), other.clone(), 0))?;
+        // signed by a stranger on behalf of user
+        let signature = *batch[7].signature();
+        batch[12] = SignedTransaction::new(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0), signature);
+
+        let report = BatchVerifier::new(VerificationPolicy::new(vec![Algorithm::EcdsaP256])).verify(&batch);
+        assert_eq!(report.results().len(), 20);
+        assert_eq!(report.failed(), [(7, Error::AlgorithmNotAccepted), (12, Error::AlgorithmNotAccepted)]);
+        assert_eq!(report.succeeded().len(), 18);
+
+        let verifier = BatchVerifier::default().with_key_capacity(1);
+        let report = verifier.verify(&batch);
+        assert_eq!(report.failed(), [(12, Error::InvalidSignature)]);
+        assert_eq!(verifier.keys().len(), 1);
+        assert_eq!(verifier.verify(&batch), report);
+
+        Ok(())
+    }
+
+    #[test]
+    fn enforced_by_the_bank() -> Result<(), Error> {
+        let user = User::with_signer("DE89370400440532013000".parse()?, Ed25519Signer::random());
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0))?;
+        let p256_only = VerificationPolicy::new(vec![Algorithm::EcdsaP256]);
+
+        let mut ledger = Ledger::with_policy(p256_only.clone());
+        ledger.deposit(user.account_number(), Currency::EUR, 1_000)?;
+        assert_eq!(ledger.apply(&signed, 0), Err(Error::AlgorithmNotAccepted));
+
+        let mut registry = KeyRegistry::new().with_policy(p256_only.clone());
+        registry.enroll(&user, 0, None)?;
+        assert_eq!(registry.verify(&signed, 0), Err(Error::AlgorithmNotAccepted));
+
+        let root = BankKey::new();
+        let certificate = root.certify_account(&user, "Erika Mustermann", 0, 100)?;
+        let verifier = CertificateVerifier::new(*root.verifying_key()).with_policy(p256_only);
+        assert_eq!(verifier.verify_transaction(&signed, &[], &certificate, 0), Err(Error::AlgorithmNotAccepted));
+
+        Ok(())
+    }
+
+    #[test]
+    fn delegation_across_algorithms() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let device = User::with_signer("DE89370400440532013000".parse()?, Ed25519Signer::random());
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+
+        let certificate = user.delegate(device.verifying_key(), Allowance::new(5_000, Currency::EUR, 100))?;
+        let transaction = Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0);
+        let signed = device.sign_delegated(transaction, vec![certificate])?;
+
+        VerificationPolicy::default().verify(&signed)?;
+        assert_eq!(
+            VerificationPolicy::new(vec![Algorithm::EcdsaP256]).check(&signed),
+            Err(Error::AlgorithmNotAccepted)
+        );
+        assert_eq!(
+            VerificationPolicy::new(vec![Algorithm::Ed25519]).check(&signed),
+            Err(Error::AlgorithmNotAccepted)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,359 @@
+//! Keys and signatures of every algorithm rustpay supports.
+//!
+//! Both carry their algorithm, and so does their encoding, so that signed
+//! data stays unambiguous as algorithms are added.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, MutexGuard};
+
+use p256::ecdsa::signature::Verifier as _;
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+/// Identifies a public key by the SHA-256 hash of its encoding.
+pub type KeyId = [u8; 32];
+
+/// How many keys a [`KeyCache`] holds by default.
+pub const DEFAULT_KEY_CACHE_CAPACITY: usize = 65_536;
+
+/// A signature algorithm, identified by a single byte in encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    /// ECDSA over P-256 with SHA-256.
+    EcdsaP256,
+    /// Ed25519 as in RFC 8032.
+    Ed25519,
+}
+
+/// A public key of any supported algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublicKey {
+    EcdsaP256(p256::ecdsa::VerifyingKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+/// Decoded public keys by the id of their encoding, so that a key seen many
+/// times, e.g. throughout a settlement file, is only decompressed once.
+///
+/// Holds at most `capacity` keys. Once it is full, the least recently used
+/// key makes room for the next one, so that a stream of distinct keys can't
+/// make it grow without bound nor push out the keys that recur.
+///
+/// Can be shared between threads.
+#[derive(Debug)]
+pub struct KeyCache {
+    entries: Mutex<CacheEntries>,
+    capacity: usize,
+}
+
+/// The keys of a [`KeyCache`] along with when they were last used.
+#[derive(Debug, Default)]
+struct CacheEntries {
+    keys: HashMap<KeyId, (PublicKey, u64)>,
+    // the id of every cached key by its last use
+    uses: BTreeMap<u64, KeyId>,
+    clock: u64,
+}
+
+/// A signature of any supported algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature {
+    EcdsaP256(p256::ecdsa::Signature),
+    Ed25519(ed25519_dalek::Signature),
+}
+
+impl Algorithm {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::EcdsaP256 => 1,
+            Self::Ed25519 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            1 => Ok(Self::EcdsaP256),
+            2 => Ok(Self::Ed25519),
+            _ => Err(Error::UnknownAlgorithm),
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EcdsaP256 => write!(f, "ECDSA-P256-SHA256"),
+            Self::Ed25519 => write!(f, "Ed25519"),
+        }
+    }
+}
+
+impl PublicKey {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::EcdsaP256(_) => Algorithm::EcdsaP256,
+            Self::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+
+    /// Verifies a signature over `message`, which has to be of the same algorithm as the key.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), Error> {
+        let valid = match (self, signature) {
+            (Self::EcdsaP256(key), Signature::EcdsaP256(signature)) => key.verify(message, signature).is_ok(),
+            // strict verification rejects malleable and small order encodings
+            (Self::Ed25519(key), Signature::Ed25519(signature)) => key.verify_strict(message, signature).is_ok(),
+            _ => false,
+        };
+
+        if !valid {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(())
+    }
+
+    pub fn id(&self) -> KeyId {
+        Sha256::digest(self.to_bytes()).into()
+    }
+
+    /// Returns the algorithm id followed by the compressed SEC1 point or the 32 byte Ed25519 key.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.algorithm().id()];
+        match self {
+            Self::EcdsaP256(key) => bytes.extend_from_slice(key.to_encoded_point(true).as_bytes()),
+            Self::Ed25519(key) => bytes.extend_from_slice(key.as_bytes()),
+        }
+        bytes
+    }
+
+    /// Decodes a key encoded by [`PublicKey::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (id, key) = bytes.split_first().ok_or(Error::InvalidKey)?;
+        match Algorithm::from_id(*id)? {
+            Algorithm::EcdsaP256 => p256::ecdsa::VerifyingKey::from_sec1_bytes(key).map(Self::EcdsaP256).map_err(|_| Error::InvalidKey),
+            Algorithm::Ed25519 => key
+                .try_into()
+                .ok()
+                .and_then(|key| ed25519_dalek::VerifyingKey::from_bytes(key).ok())
+                .map(Self::Ed25519)
+                .ok_or(Error::InvalidKey),
+        }
+    }
+}
+
+impl Hash for PublicKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state);
+    }
+}
+
+impl From<p256::ecdsa::VerifyingKey> for PublicKey {
+    fn from(key: p256::ecdsa::VerifyingKey) -> Self {
+        Self::EcdsaP256(key)
+    }
+}
+
+impl From<ed25519_dalek::VerifyingKey> for PublicKey {
+    fn from(key: ed25519_dalek::VerifyingKey) -> Self {
+        Self::Ed25519(key)
+    }
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_KEY_CACHE_CAPACITY)
+    }
+}
+
+impl KeyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { entries: Mutex::new(CacheEntries::default()), capacity }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of cached keys.
+    pub fn len(&self) -> usize {
+        self.lock().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &KeyId) -> Option<PublicKey> {
+        self.lock().touch(id)
+    }
+
+    /// Decodes a key like [`PublicKey::from_bytes`], unless the same
+    /// encoding was decoded before.
+    pub fn decode(&self, bytes: &[u8]) -> Result<PublicKey, Error> {
+        let id: KeyId = Sha256::digest(bytes).into();
+        if let Some(key) = self.get(&id) {
+            return Ok(key);
+        }
+
+        let key = PublicKey::from_bytes(bytes)?;
+        self.lock().insert(id, key, self.capacity);
+        Ok(key)
+    }
+
+    /// Returns the cached key with the id of `key`, caching `key` if there
+    /// is none yet.
+    pub fn resolve(&self, key: &PublicKey) -> PublicKey {
+        let id = key.id();
+        let mut entries = self.lock();
+        entries.touch(&id).unwrap_or_else(|| {
+            entries.insert(id, *key, self.capacity);
+            *key
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheEntries> {
+        // the entries are never left half updated, so a panic elsewhere doesn't matter
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl CacheEntries {
+    /// Returns the key with the given id and marks it as the most recently used.
+    fn touch(&mut self, id: &KeyId) -> Option<PublicKey> {
+        let (key, used) = self.keys.get_mut(id)?;
+        self.uses.remove(used);
+        self.clock += 1;
+        *used = self.clock;
+        self.uses.insert(self.clock, *id);
+        Some(*key)
+    }
+
+    /// Caches a key, evicting the least recently used ones to stay within `capacity`.
+    fn insert(&mut self, id: KeyId, key: PublicKey, capacity: usize) {
+        if self.touch(&id).is_some() {
+            return;
+        }
+
+        while self.keys.len() >= capacity.max(1) {
+            let Some((_, oldest)) = self.uses.pop_first() else { break };
+            self.keys.remove(&oldest);
+        }
+
+        self.clock += 1;
+        self.keys.insert(id, (key, self.clock));
+        self.uses.insert(self.clock, id);
+    }
+}
+
+impl Signature {
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::EcdsaP256(_) => Algorithm::EcdsaP256,
+            Self::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+
+    /// Returns the algorithm id followed by the 64 byte signature.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.algorithm().id()];
+        match self {
+            Self::EcdsaP256(signature) => bytes.extend_from_slice(&signature.to_bytes()),
+            Self::Ed25519(signature) => bytes.extend_from_slice(&signature.to_bytes()),
+        }
+        bytes
+    }
+
+    /// Decodes a signature encoded by [`Signature::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let (id, signature) = bytes.split_first().ok_or(Error::InvalidSignature)?;
+        match Algorithm::from_id(*id)? {
+            Algorithm::EcdsaP256 => p256::ecdsa::Signature::from_slice(signature)
+                .map(Self::EcdsaP256)
+                .map_err(|_| Error::InvalidSignature),
+            Algorithm::Ed25519 => ed25519_dalek::Signature::from_slice(signature)
+                .map(Self::Ed25519)
+                .map_err(|_| Error::InvalidSignature),
+        }
+    }
+}
+
+impl From<p256::ecdsa::Signature> for Signature {
+    fn from(signature: p256::ecdsa::Signature) -> Self {
+        Self::EcdsaP256(signature)
+    }
+}
+
+impl From<ed25519_dalek::Signature> for Signature {
+    fn from(signature: ed25519_dalek::Signature) -> Self {
+        Self::Ed25519(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::Signer as _;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<(), Error> {
+        let p256_key = p256::ecdsa::SigningKey::from_slice(&[1; 32]).map_err(|_| Error::InvalidKey)?;
+        let ed25519_key = ed25519_dalek::SigningKey::from_bytes(&[1; 32]);
+
+        let keys = [PublicKey::from(*p256_key.verifying_key()), PublicKey::from(ed25519_key.verifying_key())];
+        let signatures = [
+            Signature::from(p256::ecdsa::signature::Signer::<p256::ecdsa::Signature>::sign(&p256_key, b"message")),
+            Signature::from(ed25519_key.sign(b"message")),
+        ];
+
+        for (key, signature) in keys.iter().zip(&signatures) {
+            assert_eq!(PublicKey::from_bytes(&key.to_bytes())?, *key);
+            assert_eq!(Signature::from_bytes(&signature.to_bytes())?, *signature);
+            key.verify(b"message", signature)?;
+            assert_eq!(key.verify(b"other", signature), Err(Error::InvalidSignature));
+        }
+
+        // a signature never verifies under a key of another algorithm
+        assert_eq!(keys[0].verify(b"message", &signatures[1]), Err(Error::InvalidSignature));
+        assert_eq!(keys[1].verify(b"message", &signatures[0]), Err(Error::InvalidSignature));
+        assert_eq!(PublicKey::from_bytes(&[9; 33]), Err(Error::UnknownAlgorithm));
+
+        Ok(())
+    }
+
+    #[test]
+    fn cache() -> Result<(), Error> {
+        let key = PublicKey::from(ed25519_dalek::SigningKey::from_bytes(&[1; 32]).verifying_key());
+        let cache = KeyCache::new();
+
+        assert_eq!(cache.decode(&key.to_bytes())?, key);
+        assert_eq!(cache.decode(&key.to_bytes())?, key);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&key.id()), Some(key));
+
+        assert_eq!(cache.decode(&[2; 5]), Err(Error::InvalidKey));
+        assert_eq!(cache.len(), 1);
+
+        let bounded = KeyCache::with_capacity(2);
+        let keys: Vec<_> =
+            (1..=3).map(|seed| PublicKey::from(ed25519_dalek::SigningKey::from_bytes(&[seed; 32]).verifying_key())).collect();
+        assert_eq!(bounded.decode(&keys[0].to_bytes())?, keys[0]);
+        assert_eq!(bounded.resolve(&keys[1]), keys[1]);
+        // using the first key again makes the second one the least recently used
+        assert_eq!(bounded.get(&keys[0].id()), Some(keys[0]));
+        assert_eq!(bounded.decode(&keys[2].to_bytes())?, keys[2]);
+        assert_eq!(bounded.len(), 2);
+        assert_eq!(bounded.get(&keys[0].id()), Some(keys[0]));
+        assert_eq!(bounded.get(&keys[1].id()), None);
+        assert_eq!(bounded.get(&keys[2].id()), Some(keys[2]));
+
+        Ok(())
+    }
+}
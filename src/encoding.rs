@@ -0,0 +1,28 @@
+//! Helpers for building the canonical byte representations that get signed.
+//!
+//! Every variable length field is prefixed with its length, so that two
+//! different structures can never encode to the same bytes.
+
+use crate::crypto::PublicKey;
+use crate::IBAN;
+
+pub(crate) fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_u64(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+pub(crate) fn put_str(buf: &mut Vec<u8>, value: &str) {
+    put_bytes(buf, value.as_bytes());
+}
+
+pub(crate) fn put_iban(buf: &mut Vec<u8>, iban: &IBAN) {
+    put_str(buf, &iban.to_string());
+}
+
+pub(crate) fn put_key(buf: &mut Vec<u8>, key: &PublicKey) {
+    put_bytes(buf, &key.to_bytes());
+}
@@ -7,6 +7,108 @@ pub enum Error {
     NoPrivateKey,
     DevError,
     InvalidKey,
+    InvalidSignature,
+    WrongSigner,
+    UnknownMandate,
+    MandateRevoked,
+    MandateNotActive,
+    MandateMismatch,
+    MandateLimitExceeded,
+    InsufficientFunds,
+    AmountOverflow,
+    InvalidCreditorId,
+    InvalidMandateReference,
+    InvalidSequence,
+    InvalidPreNotification,
+    PreNotificationTooLate,
+    DebitNotDue,
+    DuplicateDebit,
+    UnknownDebit,
+    RefundWindowClosed,
+    AlreadyRefunded,
+    InvalidLegs,
+    DuplicateTransaction,
+    UnknownTransaction,
+    UnknownLeg,
+    RefundExceedsAmount,
+    InvalidReference,
+    InvalidProof,
+    UnknownEscrow,
+    EscrowClosed,
+    ConditionNotMet,
+    UnknownDispute,
+    DuplicateDispute,
+    InvalidDisputeStep,
+    MissingEvidence,
+    DeadlinePassed,
+    PolicyAmountExceeded,
+    PolicyLimitExceeded,
+    PolicyMerchantDenied,
+    PolicyCategoryDenied,
+    PolicyOutsideHours,
+    DelegationChainBroken,
+    DelegationExpired,
+    DelegationOutOfScope,
+    InvalidThreshold,
+    DuplicateSigner,
+    UnknownSigner,
+    NotEnoughSignatures,
+    UnknownAccount,
+    TransactionMismatch,
+    DuplicateAccount,
+    KeyNotValid,
+    NoGuardians,
+    UnknownRecovery,
+    RecoveryPending,
+    RecoveryLocked,
+    InvalidMnemonic,
+    InvalidDerivationPath,
+    SignerUnavailable,
+    SignerRejected,
+    UnknownAlgorithm,
+    AlgorithmNotAccepted,
+    CannotEncryptMemo,
+    CannotDecryptMemo,
+    InvalidHolderName,
+    CertificateNotValid,
+    CertificateMismatch,
+    CertificateChainBroken,
+    CertificateExpired,
+    CertificateRevoked,
+    CertificateUnknown,
+    StatusNotFresh,
+    UnknownDenomination,
+    InvalidToken,
+    DoubleSpend,
+    AllowanceMismatch,
+    AllowanceExpired,
+    AllowanceExceeded,
+    UnknownAllowance,
+    AllowanceReconciled,
+    ReconciliationTooEarly,
+    NotPayee,
+    JournalUnreadable,
+    JournalMalformed,
+    JournalCorrupted,
+    JournalReordered,
+    CheckpointMismatch,
+    InvalidSettlementPeriod,
+    DebitNotNotified,
+    WrongPurpose,
+    PolicyBackdated,
+    DelegationExhausted,
+    UntrackedDelegation,
+    MultisigRequired,
+    KeyRevoked,
+    RecoveryExpired,
+    RecoveryCancelled,
+    DuplicateWithdrawal,
+    TokenKeyRetired,
+    CheckpointMissing,
+    InvalidMandate,
+    PolicyPostdated,
+    UploadWindowClosed,
+    OfflinePaymentPostdated,
 }
 
 impl Display for Error {
@@ -23,6 +125,108 @@ impl Error {
             Self::NoPrivateKey => "the entity did not contain a private (signing) key",
             Self::DevError => "error for testing",
             Self::InvalidKey => "the provided bytes did not represent a valid key",
+            Self::InvalidSignature => "the signature could not be verified",
+            Self::WrongSigner => "the signing entity is not the party required to sign",
+            Self::UnknownMandate => "no mandate with the referenced id is registered",
+            Self::MandateRevoked => "the mandate has been revoked by the user",
+            Self::MandateNotActive => "the mandate is not yet valid or has already ended",
+            Self::MandateMismatch => "the transaction does not match the terms of the mandate",
+            Self::MandateLimitExceeded => "the transaction exceeds the amount the mandate allows for this period",
+            Self::InsufficientFunds => "the account does not hold enough funds",
+            Self::AmountOverflow => "the resulting amount is too large to be represented",
+            Self::InvalidCreditorId => "the provided string is not a valid creditor identifier",
+            Self::InvalidMandateReference => "the mandate reference must be between 1 and 35 characters long",
+            Self::InvalidSequence => "the sequence type is not allowed after the previous debits of the mandate",
+            Self::InvalidPreNotification => "the pre-notification does not match the debit",
+            Self::PreNotificationTooLate => "the debtor was not notified early enough before the due date",
+            Self::DebitNotDue => "the debit can not be collected before its due date",
+            Self::DuplicateDebit => "the debit has already been collected",
+            Self::UnknownDebit => "no collected debit with the referenced id exists",
+            Self::RefundWindowClosed => "the period in which a refund could be claimed has ended",
+            Self::AlreadyRefunded => "the debit has already been refunded",
+            Self::InvalidLegs => "the transaction has no legs or they do not sum up to its amount",
+            Self::DuplicateTransaction => "the transaction has already been settled",
+            Self::UnknownTransaction => "no settled transaction with the referenced id exists",
+            Self::UnknownLeg => "the transaction has no leg with the referenced index",
+            Self::RefundExceedsAmount => "the refund exceeds what is left of the amount paid",
+            Self::InvalidReference => "the reference is longer than 140 characters",
+            Self::InvalidProof => "the inclusion proof does not lead to the expected root",
+            Self::UnknownEscrow => "no escrow with the referenced id exists",
+            Self::EscrowClosed => "the escrow has already been released or refunded",
+            Self::ConditionNotMet => "the condition for paying out the escrow is not met",
+            Self::UnknownDispute => "no dispute with the referenced id exists",
+            Self::DuplicateDispute => "the leg of the transaction is already disputed",
+            Self::InvalidDisputeStep => "the step can not be taken in the current stage of the dispute",
+            Self::MissingEvidence => "a dispute can only be represented after submitting evidence",
+            Self::DeadlinePassed => "the deadline for this step has passed",
+            Self::PolicyAmountExceeded => "the spending policy does not allow transactions of this amount",
+            Self::PolicyLimitExceeded => "the transaction would exceed a spending limit of the policy",
+            Self::PolicyMerchantDenied => "the spending policy does not allow paying this merchant",
+            Self::PolicyCategoryDenied => "the spending policy does not allow paying merchants of this category",
+            Self::PolicyOutsideHours => "the spending policy does not allow transactions at this time of day",
+            Self::DelegationChainBroken => "the delegation chain does not lead from the user to the signer",
+            Self::DelegationExpired => "a delegation in the chain has expired",
+            Self::DelegationOutOfScope => "the transaction exceeds the allowance of a delegation in the chain",
+            Self::InvalidThreshold => "the threshold must be at least one and at most the number of signers",
+            Self::DuplicateSigner => "the same key signed more than once",
+            Self::UnknownSigner => "the signing key is not registered for the account",
+            Self::NotEnoughSignatures => "fewer keys signed than the account requires",
+            Self::UnknownAccount => "no account with the referenced number is registered",
+            Self::TransactionMismatch => "the signatures were made over a different transaction",
+            Self::DuplicateAccount => "an account with this number is already registered",
+            Self::KeyNotValid => "the key was not the valid key of the account at the given time",
+            Self::NoGuardians => "no guardians were appointed for the account",
+            Self::UnknownRecovery => "no such recovery is pending",
+            Self::RecoveryPending => "a recovery is already pending for the account",
+            Self::RecoveryLocked => "the recovery is still within its time lock",
+            Self::InvalidMnemonic => "the mnemonic has an unknown word, a wrong length or a wrong checksum",
+            Self::InvalidDerivationPath => "the derivation path is malformed",
+            Self::SignerUnavailable => "the signer could not be reached",
+            Self::SignerRejected => "the signer refused to sign",
+            Self::UnknownAlgorithm => "the signature algorithm is not known",
+            Self::AlgorithmNotAccepted => "the signature algorithm is not accepted",
+            Self::CannotEncryptMemo => "a memo can only be encrypted to the P-256 key of a single merchant",
+            Self::CannotDecryptMemo => "the memo could not be decrypted",
+            Self::InvalidHolderName => "the holder name is blank, too long or contains control characters",
+            Self::CertificateNotValid => "the certificate is not valid yet",
+            Self::CertificateMismatch => "the certificate was issued for a different key or account",
+            Self::CertificateChainBroken => "the certificate does not lead back to the root key",
+            Self::CertificateExpired => "the certificate has expired",
+            Self::CertificateRevoked => "the certificate was revoked",
+            Self::CertificateUnknown => "the bank did not issue the certificate",
+            Self::StatusNotFresh => "no fresh status information is known for the certificate",
+            Self::UnknownDenomination => "the bank does not issue tokens of this denomination",
+            Self::InvalidToken => "the token was not signed by the bank's key for its denomination",
+            Self::DoubleSpend => "the token or offline payment was already spent",
+            Self::AllowanceMismatch => "the transaction does not match the offline allowance",
+            Self::AllowanceExpired => "the offline allowance is not valid at that time",
+            Self::AllowanceExceeded => "the payment goes over the offline allowance",
+            Self::UnknownAllowance => "the offline allowance was not issued by this bank",
+            Self::AllowanceReconciled => "the offline allowance was already reconciled",
+            Self::ReconciliationTooEarly => "payments may still be uploaded for the offline allowance",
+            Self::NotPayee => "the payment does not pay this merchant",
+            Self::JournalUnreadable => "the journal could not be read or written",
+            Self::JournalMalformed => "the journal line could not be parsed",
+            Self::JournalCorrupted => "the journal entry does not match its hash",
+            Self::JournalReordered => "the journal entry is out of sequence or does not link to the one before",
+            Self::CheckpointMismatch => "the journal checkpoint does not match the entries before it",
+            Self::InvalidSettlementPeriod => "the settlement period must end after it starts",
+            Self::DebitNotNotified => "the debtor was not notified of the debit",
+            Self::WrongPurpose => "the transaction was signed for another purpose",
+            Self::PolicyBackdated => "the transaction is dated before one already signed under the spending policy",
+            Self::DelegationExhausted => "the transaction goes over what is left of a delegation's allowance",
+            Self::UntrackedDelegation => "delegated transactions have to be applied through the delegation book",
+            Self::MultisigRequired => "payments from the account need the approvals of its multisig policy",
+            Self::KeyRevoked => "the key was revoked and can only be replaced by the recovery key",
+            Self::RecoveryExpired => "the recovery request is too old or dated in the future",
+            Self::RecoveryCancelled => "the recovery request was cancelled",
+            Self::DuplicateWithdrawal => "the token withdrawal has already been processed",
+            Self::TokenKeyRetired => "the bank no longer signs tokens with this key",
+            Self::CheckpointMissing => "the journal lacks a checkpoint it should have at this point",
+            Self::InvalidMandate => "the mandate must allow a positive amount and end after it starts",
+            Self::PolicyPostdated => "the transaction is dated too far after the current time of the spending policy",
+            Self::UploadWindowClosed => "payments may no longer be uploaded for the offline allowance",
+            Self::OfflinePaymentPostdated => "the offline payment is dated too far after it was uploaded",
         }
     }
-}
\ No newline at end of file
+}
@@ -2,11 +2,13 @@ mod countrycodes;
 mod from_implementations;
 
 use std::ops::{Deref, DerefMut};
+use std::str::FromStr;
 
 use countrycodes::CountryCode;
 use serde::{Deserialize, Serialize};
 
 pub use crate::traits::ToBytes;
+use crate::Error;
 
 /// IBAN length by country code
 const IBAN_LENGTHS: &[(&str, usize)] = &[
@@ -26,7 +28,7 @@ const IBAN_LENGTHS: &[(&str, usize)] = &[
 /// 
 /// If a country uses less than the full 34 digits, the rest will be padded
 /// with NUL. It's ensured that NUL's are only on the right of content.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct IBAN([char; 34]);
 
 impl IBAN {
@@ -54,6 +56,11 @@ impl IBAN {
         length
     }
 
+    /// Returns whether the IBAN is empty, i.e. all fields are NUL.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Sets the country of the IBAN.
     pub fn set_country(&mut self, country: CountryCode) {
         let code = country.as_code();
@@ -62,11 +69,6 @@ impl IBAN {
         self[1] = code.1;
     }
 
-    /// Creates a new String from IBAN
-    pub fn to_string(&self) -> String {
-        self.iter().filter(|c| c.is_ascii()).collect::<String>()
-    }
-
     /// Verifies the validity of the IBAN according to its standard
     pub fn is_valid(&self) -> bool {
         let iban = self.to_string();
@@ -88,29 +90,7 @@ impl IBAN {
         // Rearrange: Move the first four characters to the end of the string
         let rearranged_iban = format!("{}{}", &iban[4..], &iban[0..4]);
 
-        // Convert characters to digits
-        let numeric_iban = rearranged_iban.chars().filter_map(|c| {
-            match c {
-                '0'..='9' => Some(c.to_digit(10).unwrap() as u8),
-                'A'..='Z' => Some((c as u8 - 'A' as u8 + 10) as u8),
-                _ => None,
-            }
-        }).collect::<Vec<_>>();
-
-        // Convert the Vec<u8> to a single large number string
-        let numeric_iban_str = numeric_iban.iter()
-            .map(|&num| num.to_string())
-            .collect::<String>();
-
-        // Perform the Modulo 97 operation
-        let mut remainder = 0u128;
-        for chunk in numeric_iban_str.as_bytes().chunks(9) {
-            let part_str = std::str::from_utf8(chunk).unwrap();
-            let part_num: u128 = part_str.parse().unwrap();
-            remainder = (remainder * 10u128.pow(part_str.len() as u32) + part_num) % 97;
-        }
-
-        remainder == 1
+        mod97(&rearranged_iban) == 1
     }
 
     /// Returns `self` as a byte slice, without sanity checks, albeit faster.
@@ -124,6 +104,55 @@ impl IBAN {
     }
 }
 
+impl FromStr for IBAN {
+    type Err = Error;
+
+    /// Parses an account number into an IBAN, checking its check digits.
+    fn from_str(account_number: &str) -> Result<Self, Self::Err> {
+        parse_valid(account_number)
+    }
+}
+
+/// Parses an account number into an IBAN, rejecting invalid ones.
+pub(crate) fn parse_valid(account_number: &str) -> Result<IBAN, Error> {
+    let iban = IBAN::try_from(account_number)?;
+    if !iban.is_valid() {
+        return Err(Error::NotAnIBAN);
+    }
+
+    Ok(iban)
+}
+
+/// Computes the ISO 7064 MOD 97-10 remainder of an alphanumeric string, with
+/// letters counting as two digit numbers (A = 10, ..., Z = 35).
+///
+/// Characters other than ASCII digits and uppercase letters are skipped.
+pub(crate) fn mod97(input: &str) -> u32 {
+    // Convert characters to digits
+    let numeric = input.chars().filter_map(|c| {
+        match c {
+            '0'..='9' => Some(c.to_digit(10).unwrap() as u8),
+            'A'..='Z' => Some(c as u8 - b'A' + 10),
+            _ => None,
+        }
+    }).collect::<Vec<_>>();
+
+    // Convert the Vec<u8> to a single large number string
+    let numeric_str = numeric.iter()
+        .map(|&num| num.to_string())
+        .collect::<String>();
+
+    // Perform the Modulo 97 operation
+    let mut remainder = 0u128;
+    for chunk in numeric_str.as_bytes().chunks(9) {
+        let part_str = std::str::from_utf8(chunk).unwrap();
+        let part_num: u128 = part_str.parse().unwrap();
+        remainder = (remainder * 10u128.pow(part_str.len() as u32) + part_num) % 97;
+    }
+
+    remainder as u32
+}
+
 impl ToBytes for IBAN {
     /// Returns `self` as a byte slice.
     /// # Panics
@@ -137,6 +166,20 @@ impl ToBytes for IBAN {
     }
 }
 
+impl Default for IBAN {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for IBAN {
+    /// Writes the IBAN without any padding.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let iban = self.iter().filter(|c| **c != '\0' && c.is_ascii()).collect::<String>();
+        write!(f, "{}", iban)
+    }
+}
+
 impl Deref for IBAN {
     type Target = [char; 34];
 
@@ -200,7 +243,6 @@ impl<'de> Deserialize<'de> for IBAN {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::Error;
 
     #[test]
     fn zero_length() {
@@ -290,6 +332,15 @@ mod tests {
         assert_eq!(results, expected_results)
     }
 
+    #[test]
+    fn parse() -> Result<(), Error> {
+        let iban: IBAN = "DE89 3704 0044 0532 0130 00".parse()?;
+        assert_eq!(iban.to_string(), "DE89370400440532013000");
+
+        assert_eq!("DE51 2131 1231 5532 1234 42".parse::<IBAN>(), Err(Error::NotAnIBAN));
+        Ok(())
+    }
+
     #[test]
     fn as_bytes() -> Result<(), Error> {
         let iban = IBAN::new();
@@ -3,12 +3,20 @@
 //! 
 
 pub mod bank;
+pub mod crypto;
 pub mod merchant;
+pub mod sepa;
+pub mod signer;
+pub mod token;
 pub mod transaction;
 pub mod user;
+mod encoding;
 mod error;
 mod iban;
+pub mod merkle;
+mod time;
 pub mod traits;
 
 pub use error::Error;
-pub use iban::IBAN;
\ No newline at end of file
+pub use iban::IBAN;
+pub use time::Timestamp;
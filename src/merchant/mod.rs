@@ -1,2 +1,187 @@
+use p256::ecdsa::{signature::Signer, SigningKey};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+
+use crate::bank::{Decision, DisputeId, DisputeStep, EscrowApproval, EscrowId, SignedDisputeStep};
+use crate::crypto::{PublicKey, Signature};
+use crate::sepa::{DirectDebit, PreNotification, SignedDirectDebit};
+use crate::traits::ToBytes;
+use crate::transaction::{
+    LegRefund, MandatedTransaction, OfflinePayment, PayoutBatch, SignedMandate, SignedPayoutBatch, SignedTransaction, Transaction,
+};
+use crate::{Error, Timestamp};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Merchant {}
\ No newline at end of file
+pub struct Merchant {
+    account_number: String,
+    signing_key: Option<SigningKey>,
+    verifying_key: PublicKey,
+    // ISO 18245 merchant category code, e.g. 5411 for grocery stores
+    category_code: Option<u16>,
+}
+
+impl Merchant {
+    pub fn new(account_number: String) -> Self {
+        Self::from_rng(account_number, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Creates a merchant with a key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(account_number: String, rng: &mut impl CryptoRngCore) -> Self {
+        let signing_key = SigningKey::random(rng);
+        let verifying_key = PublicKey::from(*signing_key.verifying_key());
+
+        Merchant { account_number, signing_key: Some(signing_key), verifying_key, category_code: None }
+    }
+
+    /// Creates a merchant that only knows its public key, as seen by banks and users.
+    pub fn from_verifying_key(account_number: String, verifying_key: PublicKey) -> Self {
+        Merchant { account_number, signing_key: None, verifying_key, category_code: None }
+    }
+
+    /// Creates a merchant from a public key encoded by [`PublicKey::to_bytes`].
+    pub fn from_public_bytes(account_number: String, bytes: &[u8]) -> Result<Self, Error> {
+        let verifying_key = PublicKey::from_bytes(bytes)?;
+
+        Ok(Self::from_verifying_key(account_number, verifying_key))
+    }
+
+    /// Sets the merchant category code.
+    pub fn with_category_code(mut self, category_code: u16) -> Self {
+        self.category_code = Some(category_code);
+        self
+    }
+
+    /// Returns a copy of `self` without the signing key.
+    pub fn to_public(&self) -> Self {
+        Merchant { signing_key: None, ..self.clone() }
+    }
+
+    pub fn account_number(&self) -> &str {
+        &self.account_number
+    }
+
+    pub fn verifying_key(&self) -> &PublicKey {
+        &self.verifying_key
+    }
+
+    pub fn category_code(&self) -> Option<u16> {
+        self.category_code
+    }
+
+    /// Pulls `amount` from the user of `mandate`, initiated at `created_at`.
+    ///
+    /// Whether the mandate actually covers the payment is decided by the bank.
+    pub fn collect(&self, mandate: &SignedMandate, amount: u64, created_at: Timestamp) -> Result<MandatedTransaction, Error> {
+        let terms = mandate.mandate();
+        if terms.merchant().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let transaction = Transaction::new(amount, terms.currency(), self.to_public(), terms.user().clone(), created_at);
+        let bytes = MandatedTransaction::signed_bytes(&transaction, &mandate.id());
+        let signature = self.sign_bytes(&bytes)?;
+
+        Ok(MandatedTransaction::new(transaction, mandate.id(), signature))
+    }
+
+    /// Signs a direct debit this merchant is the creditor of.
+    pub fn sign_direct_debit(&self, debit: DirectDebit) -> Result<SignedDirectDebit, Error> {
+        if debit.creditor().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let signature = self.sign_bytes(&debit.as_bytes())?;
+        Ok(SignedDirectDebit::new(debit, signature))
+    }
+
+    /// Records that the debtor of `debit` was notified of it at `sent_at`.
+    pub fn pre_notify(&self, debit: &DirectDebit, sent_at: Timestamp) -> Result<PreNotification, Error> {
+        if debit.creditor().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let bytes = PreNotification::signed_bytes(&debit.id(), debit.amount(), debit.due_date(), sent_at);
+        let signature = self.sign_bytes(&bytes)?;
+        Ok(PreNotification::new(debit, sent_at, signature))
+    }
+
+    /// Refunds `amount` of the leg at index `leg` of a transaction that paid this merchant.
+    pub fn refund_leg(&self, signed: &SignedTransaction, leg: usize, amount: u64) -> Result<LegRefund, Error> {
+        self.refund_leg_from_rng(signed, leg, amount, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`Merchant::refund_leg`], drawing the refund's nonce from `rng`.
+    pub fn refund_leg_from_rng(
+        &self,
+        signed: &SignedTransaction,
+        leg: usize,
+        amount: u64,
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<LegRefund, Error> {
+        let paid = signed.transaction().legs().get(leg).ok_or(Error::UnknownLeg)?;
+        if paid.merchant().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let mut nonce = [0; 16];
+        rng.fill_bytes(&mut nonce);
+        let signature = self.sign_bytes(&LegRefund::signed_bytes(&signed.id(), leg, amount, &nonce))?;
+        Ok(LegRefund::new(signed.id(), leg, amount, nonce, signature))
+    }
+
+    /// Signs a payout batch this merchant is the originator of.
+    pub fn sign_payout_batch(&self, batch: PayoutBatch) -> Result<SignedPayoutBatch, Error> {
+        if batch.originator().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let bytes = PayoutBatch::signed_bytes(batch.originator(), batch.created_at(), batch.transfers().len(), &batch.root());
+        let signature = self.sign_bytes(&bytes)?;
+        Ok(SignedPayoutBatch::new(batch, signature))
+    }
+
+    /// Signs an approval of `decision` for the escrow with id `id`.
+    pub fn approve_escrow(&self, id: &EscrowId, decision: Decision) -> Result<EscrowApproval, Error> {
+        let signature = self.sign_bytes(&EscrowApproval::signed_bytes(id, decision))?;
+        Ok(EscrowApproval::new(*id, decision, self.verifying_key, signature))
+    }
+
+    /// Signs a step of the dispute with id `id`, e.g. submitting evidence.
+    pub fn respond_to_dispute(&self, id: &DisputeId, step: DisputeStep, at: Timestamp) -> Result<SignedDisputeStep, Error> {
+        let signature = self.sign_bytes(&SignedDisputeStep::signed_bytes(id, &step, at))?;
+        Ok(SignedDisputeStep::new(*id, step, at, self.verifying_key, signature))
+    }
+
+    /// Accepts a payment made against an offline allowance signed by
+    /// `bank_key`, without talking to the bank, as long as the allowance is
+    /// still valid at `now`.
+    ///
+    /// Returns the amount paid to this merchant. Whether the user stayed
+    /// within the allowance across all merchants is only known once the bank
+    /// reconciles the uploaded payments.
+    pub fn accept_offline(&self, payment: &OfflinePayment, bank_key: &PublicKey, now: Timestamp) -> Result<u64, Error> {
+        payment.verify(bank_key, now)?;
+
+        match payment.amount_for(self) {
+            0 => Err(Error::NotPayee),
+            amount => Ok(amount),
+        }
+    }
+
+    /// Decrypts the memo of a transaction paying this merchant, if it has one.
+    pub fn read_memo(&self, transaction: &Transaction) -> Result<Option<String>, Error> {
+        let Some(memo) = transaction.memo() else {
+            return Ok(None);
+        };
+
+        let signing_key = self.signing_key.as_ref().ok_or(Error::NoPrivateKey)?;
+        memo.open(signing_key, &transaction.memo_aad()).map(Some)
+    }
+
+    fn sign_bytes(&self, message: &[u8]) -> Result<Signature, Error> {
+        let signing_key = self.signing_key.as_ref().ok_or(Error::NoPrivateKey)?;
+        let signature: p256::ecdsa::Signature = signing_key.sign(message);
+        Ok(signature.into())
+    }
+}
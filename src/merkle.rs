@@ -0,0 +1,360 @@
+//! Binary SHA-256 Merkle trees with inclusion proofs.
+//!
+//! Leaves and inner nodes are hashed with different prefixes, so a leaf can
+//! never be passed off as an inner node. If a level has an odd number of
+//! nodes, the last one is carried up unchanged instead of being duplicated.
+//!
+//! Merkle sum trees additionally carry a value in every node, the sum of
+//! the values below it, which is hashed along with the children.
+
+use sha2::{Digest, Sha256};
+
+use crate::Error;
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const SUM_LEAF_PREFIX: u8 = 0x02;
+const SUM_NODE_PREFIX: u8 = 0x03;
+
+/// Hashes the data of a leaf.
+pub fn hash_leaf(data: &[u8]) -> [u8; 32] {
+    Sha256::new().chain_update([LEAF_PREFIX]).chain_update(data).finalize().into()
+}
+
+/// Hashes two child nodes into their parent.
+pub fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    Sha256::new().chain_update([NODE_PREFIX]).chain_update(left).chain_update(right).finalize().into()
+}
+
+/// Hashes the data and value of a leaf of a sum tree.
+pub fn hash_sum_leaf(data: &[u8], value: u64) -> [u8; 32] {
+    Sha256::new().chain_update([SUM_LEAF_PREFIX]).chain_update(value.to_be_bytes()).chain_update(data).finalize().into()
+}
+
+/// Hashes two child nodes of a sum tree into their parent, returning it
+/// with the sum of their values, or `None` if the sum overflows.
+pub fn hash_sum_node(left: &([u8; 32], u64), right: &([u8; 32], u64)) -> Option<([u8; 32], u64)> {
+    let sum = left.1.checked_add(right.1)?;
+    let hash = Sha256::new()
+        .chain_update([SUM_NODE_PREFIX])
+        .chain_update(left.0)
+        .chain_update(left.1.to_be_bytes())
+        .chain_update(right.0)
+        .chain_update(right.1.to_be_bytes())
+        .finalize();
+    Some((hash.into(), sum))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree {
+    // levels[0] holds the leaf hashes, the last level holds the root
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+/// Proves that a leaf is part of a tree with a given root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    index: usize,
+    leaf_count: usize,
+    siblings: Vec<[u8; 32]>,
+}
+
+/// A Merkle tree whose root commits to the sum of the values of all leaves.
+///
+/// Values are unsigned and sums are checked for overflow, so no leaf can
+/// lower the total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleSumTree {
+    // levels[0] holds the leaf hashes and values, the last level holds the root
+    levels: Vec<Vec<([u8; 32], u64)>>,
+}
+
+/// Proves that a leaf with a given value is part of a sum tree with a given
+/// root and total.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleSumProof {
+    index: usize,
+    leaf_count: usize,
+    siblings: Vec<([u8; 32], u64)>,
+}
+
+impl MerkleTree {
+    /// Builds a tree over the data of each leaf.
+    pub fn new<T: AsRef<[u8]>>(leaves: &[T]) -> Self {
+        Self::from_leaf_hashes(leaves.iter().map(|leaf| hash_leaf(leaf.as_ref())).collect())
+    }
+
+    /// Builds a tree over already hashed leaves.
+    pub fn from_leaf_hashes(leaves: Vec<[u8; 32]>) -> Self {
+        let mut levels = vec![leaves];
+
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_node(left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(parents);
+        }
+
+        Self { levels }
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Returns the root of the tree. The root of an empty tree is all zeros.
+    pub fn root(&self) -> [u8; 32] {
+        self.levels.last().and_then(|level| level.first()).copied().unwrap_or([0; 32])
+    }
+
+    /// Returns the proof for the leaf at `index`, if there is one.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+
+        Some(MerkleProof { index, leaf_count: self.leaf_count(), siblings })
+    }
+}
+
+impl MerkleProof {
+    /// Returns the index of the proven leaf.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Computes the root of the tree from the hash of the proven leaf.
+    pub fn root(&self, leaf_hash: [u8; 32]) -> Result<[u8; 32], Error> {
+        if self.index >= self.leaf_count {
+            return Err(Error::InvalidProof);
+        }
+
+        let mut hash = leaf_hash;
+        let mut siblings = self.siblings.iter();
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            let is_right = position % 2 == 1;
+            // the last node of an odd level has no sibling and is carried up
+            if is_right || position + 1 < width {
+                let sibling = siblings.next().ok_or(Error::InvalidProof)?;
+                hash = if is_right { hash_node(sibling, &hash) } else { hash_node(&hash, sibling) };
+            }
+
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return Err(Error::InvalidProof);
+        }
+
+        Ok(hash)
+    }
+
+    /// Verifies that `data` is the leaf at `self.index()` of the tree with `root`.
+    pub fn verify(&self, data: &[u8], root: &[u8; 32]) -> Result<(), Error> {
+        if self.root(hash_leaf(data))? != *root {
+            return Err(Error::InvalidProof);
+        }
+
+        Ok(())
+    }
+}
+
+impl MerkleSumTree {
+    /// Builds a tree over the data and value of each leaf, failing if the
+    /// values add up to more than fits into a `u64`.
+    pub fn new<T: AsRef<[u8]>>(leaves: &[(T, u64)]) -> Result<Self, Error> {
+        let leaves = leaves.iter().map(|(data, value)| (hash_sum_leaf(data.as_ref(), *value), *value)).collect::<Vec<_>>();
+        let mut levels = vec![leaves];
+
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level.chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_sum_node(left, right).ok_or(Error::AmountOverflow),
+                    [single] => Ok(*single),
+                    _ => unreachable!(),
+                })
+                .collect::<Result<_, _>>()?;
+            levels.push(parents);
+        }
+
+        Ok(Self { levels })
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Returns the root hash of the tree. The root of an empty tree is all zeros.
+    pub fn root(&self) -> [u8; 32] {
+        self.levels.last().and_then(|level| level.first()).map_or([0; 32], |(hash, _)| *hash)
+    }
+
+    /// Returns the sum of the values of all leaves.
+    pub fn total(&self) -> u64 {
+        self.levels.last().and_then(|level| level.first()).map_or(0, |(_, sum)| *sum)
+    }
+
+    /// Returns the proof for the leaf at `index`, if there is one.
+    pub fn proof(&self, index: usize) -> Option<MerkleSumProof> {
+        if index >= self.leaf_count() {
+            return None;
+        }
+
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            position /= 2;
+        }
+
+        Some(MerkleSumProof { index, leaf_count: self.leaf_count(), siblings })
+    }
+}
+
+impl MerkleSumProof {
+    /// Returns the index of the proven leaf.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_count
+    }
+
+    /// Returns the values of the siblings on the path to the root, which
+    /// are all the proof reveals about the other leaves. The first is the
+    /// value of a single other leaf.
+    pub fn sibling_sums(&self) -> impl Iterator<Item = u64> + '_ {
+        self.siblings.iter().map(|(_, sum)| *sum)
+    }
+
+    /// Computes the root hash and total of the tree from the proven leaf.
+    pub fn root(&self, data: &[u8], value: u64) -> Result<([u8; 32], u64), Error> {
+        if self.index >= self.leaf_count {
+            return Err(Error::InvalidProof);
+        }
+
+        let mut node = (hash_sum_leaf(data, value), value);
+        let mut siblings = self.siblings.iter();
+        let mut position = self.index;
+        let mut width = self.leaf_count;
+
+        while width > 1 {
+            let is_right = position % 2 == 1;
+            // the last node of an odd level has no sibling and is carried up
+            if is_right || position + 1 < width {
+                let sibling = siblings.next().ok_or(Error::InvalidProof)?;
+                let parent = if is_right { hash_sum_node(sibling, &node) } else { hash_sum_node(&node, sibling) };
+                node = parent.ok_or(Error::InvalidProof)?;
+            }
+
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        if siblings.next().is_some() {
+            return Err(Error::InvalidProof);
+        }
+
+        Ok(node)
+    }
+
+    /// Verifies that `data` with `value` is the leaf at `self.index()` of the
+    /// tree with `root` and `total`.
+    pub fn verify(&self, data: &[u8], value: u64, root: &[u8; 32], total: u64) -> Result<(), Error> {
+        if self.root(data, value)? != (*root, total) {
+            return Err(Error::InvalidProof);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proofs_for_all_sizes() -> Result<(), Error> {
+        for size in 1..=17usize {
+            let leaves = (0..size).map(|i| i.to_be_bytes()).collect::<Vec<_>>();
+            let tree = MerkleTree::new(&leaves);
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).ok_or(Error::DevError)?;
+                proof.verify(leaf, &tree.root())?;
+            }
+            assert_eq!(tree.proof(size), None);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_leaf() -> Result<(), Error> {
+        let tree = MerkleTree::new(&[b"a", b"b", b"c"]);
+        let proof = tree.proof(1).ok_or(Error::DevError)?;
+
+        assert_eq!(proof.verify(b"c", &tree.root()), Err(Error::InvalidProof));
+        Ok(())
+    }
+
+    #[test]
+    fn single_leaf() {
+        let tree = MerkleTree::new(&[b"a"]);
+        assert_eq!(tree.root(), hash_leaf(b"a"));
+    }
+
+    #[test]
+    fn sum_proofs_for_all_sizes() -> Result<(), Error> {
+        for size in 1..=17u64 {
+            let leaves = (0..size).map(|i| (i.to_be_bytes(), i * 10)).collect::<Vec<_>>();
+            let tree = MerkleSumTree::new(&leaves)?;
+            assert_eq!(tree.total(), (0..size).map(|i| i * 10).sum::<u64>());
+
+            for (index, (data, value)) in leaves.iter().enumerate() {
+                let proof = tree.proof(index).ok_or(Error::DevError)?;
+                proof.verify(data, *value, &tree.root(), tree.total())?;
+                assert_eq!(proof.verify(data, value + 1, &tree.root(), tree.total()), Err(Error::InvalidProof));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn sums_can_not_overflow() -> Result<(), Error> {
+        assert_eq!(MerkleSumTree::new(&[(b"a", u64::MAX), (b"b", 1)]), Err(Error::AmountOverflow));
+
+        // a sibling claiming a huge sum can not wrap the total around to a smaller one
+        let tree = MerkleSumTree::new(&[(b"a", 5), (b"b", 7)])?;
+        let mut proof = tree.proof(0).ok_or(Error::DevError)?;
+        proof.siblings[0].1 = u64::MAX;
+        assert_eq!(proof.root(b"a", 5), Err(Error::InvalidProof));
+
+        Ok(())
+    }
+}
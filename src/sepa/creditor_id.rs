@@ -0,0 +1,104 @@
+use std::fmt::Display;
+
+use crate::iban::mod97;
+use crate::Error;
+
+/// A SEPA Creditor Identifier, e.g. `DE98ZZZ09999999999`.
+///
+/// Made up of a country code, two check digits, a three character creditor
+/// business code and a national identifier. The check digits are computed
+/// like an IBAN's, but over the national identifier only.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CreditorId(String);
+
+impl CreditorId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn country(&self) -> &str {
+        &self.0[0..2]
+    }
+
+    pub fn business_code(&self) -> &str {
+        &self.0[4..7]
+    }
+
+    pub fn national_id(&self) -> &str {
+        &self.0[7..]
+    }
+}
+
+impl TryFrom<&str> for CreditorId {
+    type Error = crate::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let input = value.replace(' ', "").to_uppercase();
+
+        if input.len() < 8 || input.len() > 35 || !input.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::InvalidCreditorId);
+        }
+
+        let country = &input[0..2];
+        let check_digits = &input[2..4];
+        if !country.chars().all(|c| c.is_ascii_uppercase()) || !check_digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidCreditorId);
+        }
+
+        // the business code is not covered by the check digits
+        let rearranged = format!("{}{}{}", &input[7..], country, check_digits);
+        if mod97(&rearranged) != 1 {
+            return Err(Error::InvalidCreditorId);
+        }
+
+        Ok(CreditorId(input))
+    }
+}
+
+impl TryFrom<String> for CreditorId {
+    type Error = crate::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl Display for CreditorId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() -> Result<(), Error> {
+        let id = CreditorId::try_from("de98 zzz 09999999999")?;
+
+        assert_eq!(id.as_str(), "DE98ZZZ09999999999");
+        assert_eq!(id.country(), "DE");
+        assert_eq!(id.business_code(), "ZZZ");
+        assert_eq!(id.national_id(), "09999999999");
+
+        // the business code may be changed freely
+        CreditorId::try_from("DE98ABC09999999999")?;
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        let ids = [
+            "DE97ZZZ09999999999",
+            "DE98ZZZ09999999998",
+            "DE98ZZZ",
+            "9E98ZZZ09999999999",
+            "DE98ZZZ0999999999!",
+        ];
+
+        for id in ids {
+            assert_eq!(CreditorId::try_from(id), Err(Error::InvalidCreditorId));
+        }
+    }
+}
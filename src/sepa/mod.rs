@@ -0,0 +1,293 @@
+//! SEPA Direct Debit style pull payments.
+//!
+//! A creditor collects from a debtor's account on the strength of a
+//! [`SignedMandate`](crate::transaction::SignedMandate) the debtor signed,
+//! which names the creditor's identifier and mandate reference every debit
+//! is made under. Every debit must be announced to the debtor ahead of its due
+//! date, and the debtor may reclaim a collected debit without giving reasons
+//! within [`REFUND_WINDOW`].
+
+mod creditor_id;
+
+pub use creditor_id::CreditorId;
+
+use sha2::{Digest, Sha256};
+
+use crate::crypto::Signature;
+use crate::encoding::{put_key, put_str, put_u64};
+use crate::iban::parse_valid;
+use crate::time::{DAY, WEEK};
+use crate::traits::ToBytes;
+use crate::transaction::{Currency, MandateId, SignedMandate};
+use crate::{merchant::Merchant, user::User, Error, Timestamp, IBAN};
+
+/// How long after collection a debtor may reclaim a direct debit.
+pub const REFUND_WINDOW: Timestamp = 8 * WEEK;
+
+/// How long before the due date a debtor has to be notified, unless the
+/// creditor and debtor agreed on something else.
+pub const DEFAULT_NOTICE_PERIOD: Timestamp = 14 * DAY;
+
+/// Identifies a direct debit by the SHA-256 hash of its contents.
+pub type DebitId = [u8; 32];
+
+/// The position of a debit within the series of debits under one mandate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SequenceType {
+    /// The first of a series of recurring debits.
+    First,
+    /// A recurring debit following a first one.
+    Recurring,
+    /// The last of a series of recurring debits.
+    Final,
+    /// A single debit, after which the mandate is used up.
+    OneOff,
+}
+
+impl SequenceType {
+    /// Returns the code used for the sequence type in SEPA messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::First => "FRST",
+            Self::Recurring => "RCUR",
+            Self::Final => "FNAL",
+            Self::OneOff => "OOFF",
+        }
+    }
+
+    /// Returns whether a debit of type `self` may follow one of type `previous`
+    /// under the same mandate. `None` means there was no previous debit.
+    pub fn may_follow(&self, previous: Option<SequenceType>) -> bool {
+        matches!(
+            (previous, self),
+            (None, Self::First | Self::OneOff)
+                | (Some(Self::First | Self::Recurring), Self::Recurring | Self::Final)
+        )
+    }
+}
+
+/// A creditor-initiated debit in euro.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectDebit {
+    creditor: Merchant,
+    creditor_id: CreditorId,
+    creditor_iban: IBAN,
+    debtor: User,
+    debtor_iban: IBAN,
+    // counted in thousandths of a euro
+    amount: u64,
+    mandate_reference: String,
+    mandate_id: MandateId,
+    sequence: SequenceType,
+    due_date: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedDirectDebit {
+    debit: DirectDebit,
+    // made by the creditor
+    signature: Signature,
+}
+
+/// The creditor's record of having told the debtor about an upcoming debit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreNotification {
+    debit_id: DebitId,
+    amount: u64,
+    due_date: Timestamp,
+    sent_at: Timestamp,
+    // made by the creditor
+    signature: Signature,
+}
+
+/// A debtor's unconditional request to have a collected debit refunded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefundClaim {
+    debit_id: DebitId,
+    // made by the debtor
+    signature: Signature,
+}
+
+impl DirectDebit {
+    /// Creates a new direct debit from the debtor of `mandate` to `creditor`.
+    ///
+    /// The debit is made under the creditor identifier and mandate reference
+    /// of the mandate. Fails if the mandate was not given to `creditor` for
+    /// direct debits in euro, or if either party's account number is not a
+    /// valid IBAN.
+    pub fn new(
+        creditor: Merchant,
+        mandate: &SignedMandate,
+        amount: u64,
+        sequence: SequenceType,
+        due_date: Timestamp,
+    ) -> Result<Self, Error> {
+        let terms = mandate.mandate();
+        let (creditor_id, mandate_reference) = terms.direct_debit().ok_or(Error::MandateMismatch)?;
+        if terms.merchant().verifying_key() != creditor.verifying_key() || terms.currency() != Currency::EUR {
+            return Err(Error::MandateMismatch);
+        }
+        let debtor = terms.user();
+
+        Ok(Self {
+            creditor_iban: parse_valid(creditor.account_number())?,
+            debtor_iban: *debtor.account_number(),
+            creditor: creditor.to_public(),
+            creditor_id: creditor_id.clone(),
+            debtor: debtor.to_public(),
+            amount,
+            mandate_reference: mandate_reference.to_string(),
+            mandate_id: mandate.id(),
+            sequence,
+            due_date,
+        })
+    }
+
+    pub fn creditor(&self) -> &Merchant {
+        &self.creditor
+    }
+
+    pub fn creditor_id(&self) -> &CreditorId {
+        &self.creditor_id
+    }
+
+    pub fn creditor_iban(&self) -> &IBAN {
+        &self.creditor_iban
+    }
+
+    pub fn debtor(&self) -> &User {
+        &self.debtor
+    }
+
+    pub fn debtor_iban(&self) -> &IBAN {
+        &self.debtor_iban
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn mandate_reference(&self) -> &str {
+        &self.mandate_reference
+    }
+
+    /// Returns the id of the mandate the debtor signed for the debit.
+    pub fn mandate_id(&self) -> &MandateId {
+        &self.mandate_id
+    }
+
+    pub fn sequence(&self) -> SequenceType {
+        self.sequence
+    }
+
+    pub fn due_date(&self) -> Timestamp {
+        self.due_date
+    }
+
+    pub fn id(&self) -> DebitId {
+        Sha256::digest(self.as_bytes()).into()
+    }
+}
+
+impl ToBytes for DirectDebit {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_key(&mut buf, self.creditor.verifying_key());
+        put_str(&mut buf, self.creditor_id.as_str());
+        buf.extend_from_slice(&self.creditor_iban.as_bytes());
+        put_key(&mut buf, self.debtor.verifying_key());
+        buf.extend_from_slice(&self.debtor_iban.as_bytes());
+        put_u64(&mut buf, self.amount);
+        put_str(&mut buf, &self.mandate_reference);
+        buf.extend_from_slice(&self.mandate_id);
+        put_str(&mut buf, self.sequence.code());
+        put_u64(&mut buf, self.due_date);
+        buf
+    }
+}
+
+impl SignedDirectDebit {
+    pub(crate) fn new(debit: DirectDebit, signature: Signature) -> Self {
+        Self { debit, signature }
+    }
+
+    pub fn debit(&self) -> &DirectDebit {
+        &self.debit
+    }
+
+    pub fn id(&self) -> DebitId {
+        self.debit.id()
+    }
+
+    /// Verifies that the debit was signed by its creditor.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.debit.creditor.verifying_key()
+            .verify(&self.debit.as_bytes(), &self.signature)
+    }
+}
+
+impl PreNotification {
+    pub(crate) fn new(debit: &DirectDebit, sent_at: Timestamp, signature: Signature) -> Self {
+        Self { debit_id: debit.id(), amount: debit.amount, due_date: debit.due_date, sent_at, signature }
+    }
+
+    pub(crate) fn signed_bytes(debit_id: &DebitId, amount: u64, due_date: Timestamp, sent_at: Timestamp) -> Vec<u8> {
+        let mut buf = b"pre-notification".to_vec();
+        buf.extend_from_slice(debit_id);
+        put_u64(&mut buf, amount);
+        put_u64(&mut buf, due_date);
+        put_u64(&mut buf, sent_at);
+        buf
+    }
+
+    pub fn debit_id(&self) -> &DebitId {
+        &self.debit_id
+    }
+
+    pub fn sent_at(&self) -> Timestamp {
+        self.sent_at
+    }
+
+    /// Verifies that the notification announces `debit` and was signed by
+    /// its creditor at least `notice_period` before the due date.
+    pub fn verify(&self, debit: &DirectDebit, notice_period: Timestamp) -> Result<(), Error> {
+        if self.debit_id != debit.id() || self.amount != debit.amount || self.due_date != debit.due_date {
+            return Err(Error::InvalidPreNotification);
+        }
+
+        debit.creditor.verifying_key()
+            .verify(&Self::signed_bytes(&self.debit_id, self.amount, self.due_date, self.sent_at), &self.signature)?;
+
+        if self.sent_at.saturating_add(notice_period) > self.due_date {
+            return Err(Error::PreNotificationTooLate);
+        }
+
+        Ok(())
+    }
+}
+
+impl RefundClaim {
+    pub(crate) fn new(debit_id: DebitId, signature: Signature) -> Self {
+        Self { debit_id, signature }
+    }
+
+    pub(crate) fn signed_bytes(debit_id: &DebitId) -> Vec<u8> {
+        let mut buf = b"refund".to_vec();
+        buf.extend_from_slice(debit_id);
+        buf
+    }
+
+    pub fn debit_id(&self) -> &DebitId {
+        &self.debit_id
+    }
+
+    /// Verifies that the claim was signed by the debtor of `debit`.
+    pub fn verify(&self, debit: &DirectDebit) -> Result<(), Error> {
+        if self.debit_id != debit.id() {
+            return Err(Error::UnknownDebit);
+        }
+
+        debit.debtor.verifying_key()
+            .verify(&Self::signed_bytes(&self.debit_id), &self.signature)
+    }
+}
@@ -0,0 +1,188 @@
+//! Backends holding the signing keys of users, so that keys can live in
+//! an HSM or a separate process instead of next to the application.
+
+#[cfg(unix)]
+mod socket;
+
+#[cfg(unix)]
+pub use socket::{serve, SocketSigner};
+
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use p256::ecdsa::{signature::Signer as _, SigningKey};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+
+use crate::crypto::{PublicKey, Signature};
+use crate::Error;
+
+/// Something that signs messages with a single key without revealing it.
+pub trait Signer: Debug + Send + Sync {
+    /// Returns the key signatures can be verified with, which also
+    /// determines the signature algorithm.
+    fn verifying_key(&self) -> PublicKey;
+
+    /// Signs `message` with the algorithm of the key.
+    ///
+    /// Implementations should derive nonces deterministically, as RFC 6979
+    /// does for ECDSA and Ed25519 does by design, so the same key and message
+    /// always give the same signature and no weak random number generator
+    /// can leak the key.
+    fn sign(&self, message: &[u8]) -> Result<Signature, Error>;
+}
+
+/// Keeps a P-256 signing key in the memory of the current process.
+///
+/// The key is wiped from memory on drop and never shows up in `Debug` output.
+#[derive(Clone)]
+pub struct SoftwareSigner {
+    signing_key: SigningKey,
+}
+
+impl SoftwareSigner {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self { signing_key }
+    }
+
+    /// Creates a signer with a freshly generated key.
+    pub fn random() -> Self {
+        Self::from_rng(&mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Creates a signer with a key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(rng: &mut impl CryptoRngCore) -> Self {
+        Self::new(SigningKey::random(rng))
+    }
+}
+
+/// Keeps an Ed25519 signing key in the memory of the current process.
+///
+/// The key is wiped from memory on drop and never shows up in `Debug` output.
+#[derive(Clone)]
+pub struct Ed25519Signer {
+    signing_key: ed25519_dalek::SigningKey,
+}
+
+impl Debug for SoftwareSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SoftwareSigner").field("verifying_key", self.signing_key.verifying_key()).finish_non_exhaustive()
+    }
+}
+
+impl Signer for SoftwareSigner {
+    fn verifying_key(&self) -> PublicKey {
+        (*self.signing_key.verifying_key()).into()
+    }
+
+    // uses RFC 6979 nonces
+    fn sign(&self, message: &[u8]) -> Result<Signature, Error> {
+        let signature: p256::ecdsa::Signature = self.signing_key.sign(message);
+        Ok(signature.into())
+    }
+}
+
+impl Ed25519Signer {
+    pub fn new(signing_key: ed25519_dalek::SigningKey) -> Self {
+        Self { signing_key }
+    }
+
+    /// Creates a signer with a freshly generated key.
+    pub fn random() -> Self {
+        Self::from_rng(&mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Creates a signer with a key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(rng: &mut impl CryptoRngCore) -> Self {
+        Self::new(ed25519_dalek::SigningKey::generate(rng))
+    }
+}
+
+impl Debug for Ed25519Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ed25519Signer").field("verifying_key", &self.signing_key.verifying_key()).finish_non_exhaustive()
+    }
+}
+
+impl Signer for Ed25519Signer {
+    fn verifying_key(&self) -> PublicKey {
+        self.signing_key.verifying_key().into()
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, Error> {
+        Ok(ed25519_dalek::Signer::sign(&self.signing_key, message).into())
+    }
+}
+
+/// A signer shared between clones of its owner.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedSigner(Arc<dyn Signer>);
+
+impl SharedSigner {
+    pub(crate) fn new(signer: impl Signer + 'static) -> Self {
+        Self(Arc::new(signer))
+    }
+
+    pub(crate) fn verifying_key(&self) -> PublicKey {
+        self.0.verifying_key()
+    }
+
+    pub(crate) fn sign(&self, message: &[u8]) -> Result<Signature, Error> {
+        self.0.sign(message)
+    }
+}
+
+impl PartialEq for SharedSigner {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedSigner {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    // RFC 6979, A.2.5, P-256 with SHA-256 over "sample"
+    #[test]
+    fn rfc6979_vector() -> Result<(), Error> {
+        let key = [
+            0xC9, 0xAF, 0xA9, 0xD8, 0x45, 0xBA, 0x75, 0x16, 0x6B, 0x5C, 0x21, 0x57, 0x67, 0xB1, 0xD6, 0x93,
+            0x4E, 0x50, 0xC3, 0xDB, 0x36, 0xE8, 0x9B, 0x12, 0x7B, 0x8A, 0x62, 0x2B, 0x12, 0x0F, 0x67, 0x21,
+        ];
+        let signer = SoftwareSigner::new(SigningKey::from_slice(&key).map_err(|_| Error::InvalidKey)?);
+
+        let Signature::EcdsaP256(signature) = signer.sign(b"sample")? else {
+            panic!("P-256 signer made another kind of signature");
+        };
+        let (r, s) = signature.split_bytes();
+        assert_eq!(hex(&r), "EFD48B2AACB6A8FD1140DD9CD45E81D69D2C877B56AAF991C34D0EA84EAF3716");
+        assert_eq!(hex(&s), "F7CB1C942D657C41D436C7A1B6E29F65F3E900DBB9AFF4064DC4AB2F843ACDA8");
+
+        Ok(())
+    }
+
+    // RFC 8032, 7.1, test 2
+    #[test]
+    fn rfc8032_vector() -> Result<(), Error> {
+        let key = [
+            0x4c, 0xcd, 0x08, 0x9b, 0x28, 0xff, 0x96, 0xda, 0x9d, 0xb6, 0xc3, 0x46, 0xec, 0x11, 0x4e, 0x0f,
+            0x5b, 0x8a, 0x31, 0x9f, 0x35, 0xab, 0xa6, 0x24, 0xda, 0x8c, 0xf6, 0xed, 0x4f, 0xb8, 0xa6, 0xfb,
+        ];
+        let signer = Ed25519Signer::new(ed25519_dalek::SigningKey::from_bytes(&key));
+
+        let signature = signer.sign(&[0x72])?;
+        assert_eq!(
+            hex(&signature.to_bytes()[1..]),
+            "92A009A9F0D4CAB8720E820B5F642540A2B27B5416503F8FB3762223EBDB69DA\
+             085AC1E43E15996E458F3613D0F11D8C387B2EAEB4302AEEB00D291612BB0C00"
+        );
+        signer.verifying_key().verify(&[0x72], &signature)
+    }
+}
@@ -0,0 +1,289 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::Signer;
+use crate::crypto::{Algorithm, PublicKey, Signature};
+use crate::Error;
+
+// requests start with one of these, a signing request is followed by the
+// message length as a big endian u64 and the message
+const GET_KEY: u8 = 0;
+const SIGN: u8 = 1;
+
+// responses start with one of these, followed by the algorithm id and the
+// key or the 64 byte signature if the request succeeded
+const OK: u8 = 0;
+const FAILED: u8 = 1;
+
+// keeps a client from making the daemon allocate arbitrary amounts of memory
+const MAX_MESSAGE_LEN: u64 = 1 << 20;
+
+/// Talks to a signing daemon over a Unix socket, so the key never enters
+/// the address space of the application.
+///
+/// A connection that failed mid-request is dropped, as it can't be told
+/// where the next response starts, and a new one is made for the next
+/// request.
+#[derive(Debug)]
+pub struct SocketSigner {
+    path: PathBuf,
+    // `None` after the connection failed
+    stream: Mutex<Option<UnixStream>>,
+    verifying_key: PublicKey,
+}
+
+impl SocketSigner {
+    /// Connects to the daemon listening at `path` and asks for its key.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let (stream, verifying_key) = open(path.as_ref())?;
+        Ok(Self { path: path.as_ref().to_path_buf(), stream: Mutex::new(Some(stream)), verifying_key })
+    }
+}
+
+fn open(path: &Path) -> Result<(UnixStream, PublicKey), Error> {
+    let mut stream = UnixStream::connect(path).map_err(|_| Error::SignerUnavailable)?;
+
+    stream.write_all(&[GET_KEY]).map_err(|_| Error::SignerUnavailable)?;
+    let response = read_response(&mut stream, |algorithm| match algorithm {
+        Algorithm::EcdsaP256 => 33,
+        Algorithm::Ed25519 => 32,
+    })?;
+    Ok((stream, PublicKey::from_bytes(&response)?))
+}
+
+impl Signer for SocketSigner {
+    fn verifying_key(&self) -> PublicKey {
+        self.verifying_key
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Signature, Error> {
+        let mut request = vec![SIGN];
+        request.extend_from_slice(&(message.len() as u64).to_be_bytes());
+        request.extend_from_slice(message);
+
+        let response = {
+            // a panic while holding the lock leaves the stream unusable anyway
+            let mut stream = self.stream.lock().map_err(|_| Error::SignerUnavailable)?;
+            // a connection kept from before may have been closed by the
+            // daemon since, which is worth one more try on a fresh one
+            let retry = stream.is_some();
+            match self.request(&mut stream, &request) {
+                Err(Error::SignerUnavailable) if retry => self.request(&mut stream, &request)?,
+                response => response?,
+            }
+        };
+
+        // don't hand out what a misbehaving daemon made up
+        let signature = Signature::from_bytes(&response)?;
+        self.verifying_key.verify(message, &signature)?;
+
+        Ok(signature)
+    }
+}
+
+impl SocketSigner {
+    // drops the connection on anything that may leave it out of sync
+    fn request(&self, stream: &mut Option<UnixStream>, request: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut connected = match stream.take() {
+            Some(connected) => connected,
+            None => {
+                let (connected, key) = open(&self.path)?;
+                if key != self.verifying_key {
+                    return Err(Error::WrongSigner);
+                }
+                connected
+            }
+        };
+
+        connected.write_all(request).map_err(|_| Error::SignerUnavailable)?;
+        let response = read_response(&mut connected, |_| 64);
+        if matches!(response, Ok(_) | Err(Error::SignerRejected)) {
+            *stream = Some(connected);
+        }
+        response
+    }
+}
+
+// returns the algorithm id followed by the payload, whose length may depend on the algorithm
+fn read_response(stream: &mut UnixStream, len: impl Fn(Algorithm) -> usize) -> Result<Vec<u8>, Error> {
+    let mut header = [0; 2];
+    stream.read_exact(&mut header[..1]).map_err(|_| Error::SignerUnavailable)?;
+    if header[0] != OK {
+        return Err(Error::SignerRejected);
+    }
+
+    stream.read_exact(&mut header[1..]).map_err(|_| Error::SignerUnavailable)?;
+    let mut response = vec![0; 1 + len(Algorithm::from_id(header[1])?)];
+    response[0] = header[1];
+    stream.read_exact(&mut response[1..]).map_err(|_| Error::SignerUnavailable)?;
+    Ok(response)
+}
+
+/// Runs a signing daemon answering [`SocketSigner`]s connecting to `listener`,
+/// one thread per connection. Only returns if accepting a connection fails.
+///
+/// A connection failing ends that connection only, and its error is passed
+/// to `report`.
+pub fn serve(
+    listener: &UnixListener,
+    signer: Arc<dyn Signer>,
+    report: impl Fn(io::Error) + Send + Sync + 'static,
+) -> io::Result<()> {
+    let report = Arc::new(report);
+    for stream in listener.incoming() {
+        let (signer, report) = (Arc::clone(&signer), Arc::clone(&report));
+        let stream = stream?;
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, signer.as_ref()) {
+                report(err);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle(mut stream: UnixStream, signer: &dyn Signer) -> io::Result<()> {
+    loop {
+        let mut op = [0];
+        match stream.read_exact(&mut op) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+
+        match op[0] {
+            GET_KEY => {
+                stream.write_all(&[OK])?;
+                stream.write_all(&signer.verifying_key().to_bytes())?;
+            }
+            SIGN => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len)?;
+                let len = u64::from_be_bytes(len);
+                if len > MAX_MESSAGE_LEN {
+                    stream.write_all(&[FAILED])?;
+                    return Ok(());
+                }
+
+                let mut message = vec![0; len as usize];
+                stream.read_exact(&mut message)?;
+                match signer.sign(&message) {
+                    Ok(signature) => {
+                        stream.write_all(&[OK])?;
+                        stream.write_all(&signature.to_bytes())?;
+                    }
+                    Err(_) => stream.write_all(&[FAILED])?,
+                }
+            }
+            _ => {
+                stream.write_all(&[FAILED])?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::signer::{Ed25519Signer, SoftwareSigner};
+    use crate::traits::TransactionSign;
+    use crate::transaction::{Currency, Transaction};
+    use crate::user::User;
+
+    fn daemon(name: &str, signer: impl Signer + 'static) -> PathBuf {
+        reporting_daemon(name, signer, |_| ())
+    }
+
+    fn reporting_daemon(name: &str, signer: impl Signer + 'static, report: impl Fn(io::Error) + Send + Sync + 'static) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rustpay-{}-{}.sock", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).expect("socket can be bound");
+
+        let signer: Arc<dyn Signer> = Arc::new(signer);
+        thread::spawn(move || serve(&listener, signer, report));
+        path
+    }
+
+    #[test]
+    fn sign_through_daemon() -> Result<(), Error> {
+        let software = SoftwareSigner::random();
+        let key = software.verifying_key();
+        let path = daemon("sign", software);
+
+        let user = User::with_signer("DE89370400440532013000".parse()?, SocketSigner::connect(&path)?);
+        assert_eq!(*user.verifying_key(), key);
+
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0))?;
+        signed.verify()?;
+
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    #[test]
+    fn ed25519_through_daemon() -> Result<(), Error> {
+        let path = daemon("ed25519", Ed25519Signer::random());
+
+        let signer = SocketSigner::connect(&path)?;
+        assert_eq!(signer.verifying_key().algorithm(), Algorithm::Ed25519);
+        signer.verifying_key().verify(b"message", &signer.sign(b"message")?)?;
+
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    #[test]
+    fn reconnects_after_failure() -> Result<(), Error> {
+        let (errors, reported) = std::sync::mpsc::channel();
+        let path = reporting_daemon("reconnect", SoftwareSigner::random(), move |err| {
+            let _ = errors.send(err.kind());
+        });
+
+        // the daemon hangs up on a message that is too long, possibly while it is still being sent
+        let signer = SocketSigner::connect(&path)?;
+        let refused = signer.sign(&vec![0; MAX_MESSAGE_LEN as usize + 1]);
+        assert!(matches!(refused, Err(Error::SignerUnavailable | Error::SignerRejected)));
+        signer.verifying_key().verify(b"message", &signer.sign(b"message")?)?;
+
+        // a client hanging up mid-request is reported
+        let mut client = UnixStream::connect(&path).map_err(|_| Error::SignerUnavailable)?;
+        client.write_all(&[SIGN, 0, 0]).map_err(|_| Error::SignerUnavailable)?;
+        drop(client);
+        let kind = reported.recv_timeout(std::time::Duration::from_secs(5)).map_err(|_| Error::DevError)?;
+        assert_eq!(kind, io::ErrorKind::UnexpectedEof);
+
+        let _ = std::fs::remove_file(path);
+        Ok(())
+    }
+
+    #[derive(Debug)]
+    struct Locked(PublicKey);
+
+    impl Signer for Locked {
+        fn verifying_key(&self) -> PublicKey {
+            self.0
+        }
+
+        fn sign(&self, _: &[u8]) -> Result<Signature, Error> {
+            Err(Error::NoPrivateKey)
+        }
+    }
+
+    #[test]
+    fn daemon_refuses() -> Result<(), Error> {
+        let path = daemon("refuse", Locked(SoftwareSigner::random().verifying_key()));
+
+        let signer = SocketSigner::connect(&path)?;
+        assert_eq!(signer.sign(b"message"), Err(Error::SignerRejected));
+
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(SocketSigner::connect(&path).map(|_| ()), Err(Error::SignerUnavailable));
+        Ok(())
+    }
+}
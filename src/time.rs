@@ -0,0 +1,61 @@
+//! Calendar helpers over unix timestamps.
+//!
+//! rustpay never reads the system clock on its own, every time-dependent
+//! check takes the current time as a parameter. This keeps verification
+//! deterministic and testable.
+
+/// Seconds since the unix epoch, in UTC.
+pub type Timestamp = u64;
+
+pub(crate) const DAY: Timestamp = 86_400;
+pub(crate) const WEEK: Timestamp = 7 * DAY;
+
+/// Converts a timestamp into its civil date as `(year, month, day)`.
+///
+/// Months and days are counted starting at 1.
+pub(crate) fn civil_date(timestamp: Timestamp) -> (u64, u64, u64) {
+    // Howard Hinnant's `civil_from_days`, restricted to dates after 1970
+    let z = timestamp / DAY + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Returns how many calendar months lie between the months of `from` and `to`.
+pub(crate) fn months_between(from: Timestamp, to: Timestamp) -> u64 {
+    let (from_year, from_month, _) = civil_date(from);
+    let (to_year, to_month, _) = civil_date(to);
+
+    (to_year * 12 + to_month).saturating_sub(from_year * 12 + from_month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epoch() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+    }
+
+    #[test]
+    fn leap_day() {
+        // 2024-02-29T12:00:00Z
+        assert_eq!(civil_date(1_709_208_000), (2024, 2, 29));
+    }
+
+    #[test]
+    fn months() {
+        // 2024-01-31 to 2024-02-01
+        assert_eq!(months_between(1_706_659_200, 1_706_745_600), 1);
+        // 2023-12-15 to 2025-01-01
+        assert_eq!(months_between(1_702_598_400, 1_735_689_600), 13);
+    }
+}
@@ -0,0 +1,409 @@
+//! Bearer tokens of fixed denominations, blind-signed by the bank.
+//!
+//! A user withdraws a token by having the bank sign a blinded serial number,
+//! debiting the user's account. The token itself carries no trace of the
+//! user, so neither the merchant it is spent at nor the bank depositing it
+//! can tell who paid. The bank only learns the serial when the token is
+//! deposited, and refuses serials it has seen before.
+//!
+//! Every token names the key it was signed with, so the bank can replace the
+//! key of a denomination and still take back the tokens signed with the old one.
+//!
+//! Signatures are Chaum's RSA blind signatures over a full domain hash.
+
+use std::fmt::{self, Debug};
+
+use num_bigint_dig::{ModInverse, RandBigInt};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+use rsa::hazmat::{rsa_decrypt_and_check, rsa_encrypt};
+use rsa::traits::PublicKeyParts;
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+use crate::crypto::Signature;
+use crate::encoding::{put_bytes, put_iban, put_key, put_u64};
+use crate::traits::ToBytes;
+use crate::transaction::Currency;
+use crate::user::User;
+use crate::Error;
+
+/// The size of the keys the bank signs tokens with.
+pub const TOKEN_KEY_BITS: usize = 2048;
+
+/// A random number identifying a token, chosen by the user.
+pub type TokenSerial = [u8; 32];
+
+/// The hash of a [`TokenKey`], naming the key a token was signed with.
+pub type TokenKeyId = [u8; 32];
+
+/// The hash of a [`Withdrawal`], by which the bank recognizes withdrawals it processed.
+pub type WithdrawalId = [u8; 32];
+
+/// What a token is worth. The bank signs every denomination with its own key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Denomination {
+    // counted in thousandths, like transaction amounts
+    amount: u64,
+    currency: Currency,
+}
+
+/// The key the bank signs tokens of one denomination with.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MintKey {
+    key: RsaPrivateKey,
+    token_key: TokenKey,
+}
+
+/// The public part of a [`MintKey`], which users blind tokens for and
+/// anyone can verify tokens with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenKey {
+    denomination: Denomination,
+    key: RsaPublicKey,
+}
+
+/// A token the user waits on the bank to sign.
+///
+/// Holds the blinding factor, which links the token to the withdrawal and
+/// is therefore left out of `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PendingToken {
+    denomination: Denomination,
+    key_id: TokenKeyId,
+    serial: TokenSerial,
+    // the inverse of the blinding factor
+    unblinder: BigUint,
+}
+
+/// Asks the bank to sign a blinded token and to debit the user's account
+/// with its denomination, signed by the user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Withdrawal {
+    user: User,
+    key_id: TokenKeyId,
+    denomination: Denomination,
+    blinded: Vec<u8>,
+    signature: Signature,
+}
+
+/// The bank's signature over a blinded token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlindSignature {
+    denomination: Denomination,
+    signature: Vec<u8>,
+}
+
+/// A token that can be spent anywhere the bank's token keys are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    denomination: Denomination,
+    key_id: TokenKeyId,
+    serial: TokenSerial,
+    signature: Vec<u8>,
+}
+
+impl Denomination {
+    pub fn new(amount: u64, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+}
+
+impl ToBytes for Denomination {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_u64(&mut buf, self.amount);
+        put_bytes(&mut buf, &self.currency.as_bytes());
+        buf
+    }
+}
+
+impl MintKey {
+    pub fn new(denomination: Denomination) -> Self {
+        Self::from_rng(denomination, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Creates a mint key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(denomination: Denomination, rng: &mut impl CryptoRngCore) -> Self {
+        let key = RsaPrivateKey::new(rng, TOKEN_KEY_BITS).expect("keys of a fixed supported size can be generated");
+        let token_key = TokenKey { denomination, key: key.to_public_key() };
+
+        Self { key, token_key }
+    }
+
+    pub fn denomination(&self) -> Denomination {
+        self.token_key.denomination
+    }
+
+    pub fn token_key(&self) -> &TokenKey {
+        &self.token_key
+    }
+
+    /// Signs a blinded token, without learning anything about it.
+    pub(crate) fn sign_blinded(&self, blinded: &[u8]) -> Result<BlindSignature, Error> {
+        let blinded = BigUint::from_bytes_be(blinded);
+        if blinded >= *self.key.n() {
+            return Err(Error::InvalidToken);
+        }
+
+        // the randomness blinds the private key operation against timing attacks
+        let signature = rsa_decrypt_and_check(&self.key, Some(&mut ChaCha20Rng::from_entropy()), &blinded)
+            .map_err(|_| Error::InvalidToken)?;
+        Ok(BlindSignature { denomination: self.denomination(), signature: self.token_key.to_fixed_bytes(&signature) })
+    }
+}
+
+impl Debug for MintKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MintKey").field("token_key", &self.token_key).finish_non_exhaustive()
+    }
+}
+
+impl TokenKey {
+    pub fn denomination(&self) -> Denomination {
+        self.denomination
+    }
+
+    /// Returns the hash of the key, which tokens signed with it carry.
+    pub fn id(&self) -> TokenKeyId {
+        Sha256::new()
+            .chain_update(b"token-key")
+            .chain_update(self.denomination.as_bytes())
+            .chain_update(self.key.n().to_bytes_be())
+            .chain_update(self.key.e().to_bytes_be())
+            .finalize()
+            .into()
+    }
+
+    /// Checks that `token` was signed with this key.
+    pub fn verify(&self, token: &Token) -> Result<(), Error> {
+        let signature = BigUint::from_bytes_be(&token.signature);
+        if token.denomination != self.denomination || token.key_id != self.id() || signature >= *self.key.n() {
+            return Err(Error::InvalidToken);
+        }
+
+        let message = rsa_encrypt(&self.key, &signature).map_err(|_| Error::InvalidToken)?;
+        if message != self.hash(&token.serial) {
+            return Err(Error::InvalidToken);
+        }
+
+        Ok(())
+    }
+
+    // hashes the serial onto a number below the modulus, by stretching
+    // SHA-256 to one byte less than the modulus is long
+    fn hash(&self, serial: &TokenSerial) -> BigUint {
+        let len = self.key.size() - 1;
+        let mut buf = Vec::with_capacity(len + 32);
+        for counter in 0u32.. {
+            if buf.len() >= len {
+                break;
+            }
+
+            let block = Sha256::new()
+                .chain_update(b"rustpay token")
+                .chain_update(counter.to_be_bytes())
+                .chain_update(self.denomination.as_bytes())
+                .chain_update(serial)
+                .finalize();
+            buf.extend_from_slice(&block);
+        }
+        buf.truncate(len);
+
+        BigUint::from_bytes_be(&buf)
+    }
+
+    fn to_fixed_bytes(&self, value: &BigUint) -> Vec<u8> {
+        let bytes = value.to_bytes_be();
+        let mut buf = vec![0; self.key.size() - bytes.len()];
+        buf.extend_from_slice(&bytes);
+        buf
+    }
+}
+
+impl PendingToken {
+    /// Picks a fresh serial for a token signed with `key` and blinds it.
+    ///
+    /// Returns the pending token along with the blinded message for the bank.
+    pub(crate) fn new(key: &TokenKey, rng: &mut impl CryptoRngCore) -> (Self, Vec<u8>) {
+        let mut serial = [0; 32];
+        rng.fill_bytes(&mut serial);
+
+        let n = key.key.n();
+        let (factor, unblinder) = loop {
+            let factor = rng.gen_biguint_below(n);
+            // a factor without an inverse shares a prime with the modulus,
+            // which is as likely as guessing it
+            if let Some(unblinder) = factor.clone().mod_inverse(n).and_then(|inverse| inverse.to_biguint()) {
+                break (factor, unblinder);
+            }
+        };
+
+        let blinded = (key.hash(&serial) * factor.modpow(key.key.e(), n)) % n;
+        (Self { denomination: key.denomination, key_id: key.id(), serial, unblinder }, key.to_fixed_bytes(&blinded))
+    }
+
+    pub fn denomination(&self) -> Denomination {
+        self.denomination
+    }
+
+    /// Removes the blinding from the bank's signature, checking that the
+    /// result is a valid token.
+    pub fn finish(self, key: &TokenKey, signature: &BlindSignature) -> Result<Token, Error> {
+        if signature.denomination != self.denomination || key.id() != self.key_id {
+            return Err(Error::InvalidToken);
+        }
+
+        let blinded = BigUint::from_bytes_be(&signature.signature);
+        let unblinded = (blinded * &self.unblinder) % key.key.n();
+        let token = Token::new(self.denomination, self.key_id, self.serial, key.to_fixed_bytes(&unblinded));
+        key.verify(&token)?;
+
+        Ok(token)
+    }
+}
+
+impl Debug for PendingToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingToken").field("denomination", &self.denomination).finish_non_exhaustive()
+    }
+}
+
+impl Withdrawal {
+    pub(crate) fn new(user: User, key: &TokenKey, blinded: Vec<u8>, signature: Signature) -> Self {
+        Self { user, key_id: key.id(), denomination: key.denomination, blinded, signature }
+    }
+
+    pub(crate) fn signed_bytes(user: &User, key: &TokenKey, blinded: &[u8]) -> Vec<u8> {
+        Self::encode(user, &key.denomination, &key.id(), blinded)
+    }
+
+    fn encode(user: &User, denomination: &Denomination, key_id: &TokenKeyId, blinded: &[u8]) -> Vec<u8> {
+        let mut buf = b"token-withdrawal".to_vec();
+        put_iban(&mut buf, user.account_number());
+        put_key(&mut buf, user.verifying_key());
+        buf.extend_from_slice(&denomination.as_bytes());
+        buf.extend_from_slice(key_id);
+        put_bytes(&mut buf, blinded);
+        buf
+    }
+
+    /// Returns the hash of everything the user signed, which the bank
+    /// processes once.
+    pub fn id(&self) -> WithdrawalId {
+        Sha256::digest(Self::encode(&self.user, &self.denomination, &self.key_id, &self.blinded)).into()
+    }
+
+    /// Returns the user whose account is debited.
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn denomination(&self) -> Denomination {
+        self.denomination
+    }
+
+    /// Returns the id of the key the token was blinded for.
+    pub fn key_id(&self) -> &TokenKeyId {
+        &self.key_id
+    }
+
+    pub fn blinded(&self) -> &[u8] {
+        &self.blinded
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Checks that the user signed the withdrawal.
+    pub fn verify(&self) -> Result<(), Error> {
+        let message = Self::encode(&self.user, &self.denomination, &self.key_id, &self.blinded);
+        self.user.verifying_key().verify(&message, &self.signature)
+    }
+}
+
+impl BlindSignature {
+    pub fn denomination(&self) -> Denomination {
+        self.denomination
+    }
+}
+
+impl Token {
+    pub(crate) fn new(denomination: Denomination, key_id: TokenKeyId, serial: TokenSerial, signature: Vec<u8>) -> Self {
+        Self { denomination, key_id, serial, signature }
+    }
+
+    pub fn denomination(&self) -> Denomination {
+        self.denomination
+    }
+
+    /// Returns the id of the key the token was signed with.
+    pub fn key_id(&self) -> &TokenKeyId {
+        &self.key_id
+    }
+
+    pub fn serial(&self) -> &TokenSerial {
+        &self.serial
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mint(amount: u64) -> MintKey {
+        MintKey::from_rng(Denomination::new(amount, Currency::EUR), &mut ChaCha20Rng::seed_from_u64(amount))
+    }
+
+    #[test]
+    fn blind_signature() -> Result<(), Error> {
+        let mint = mint(5_000);
+        let key = mint.token_key();
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+
+        let (pending, blinded) = PendingToken::new(key, &mut rng);
+        let signature = mint.sign_blinded(&blinded)?;
+        // the bank never sees what it signed
+        assert_ne!(signature.signature, blinded);
+        let serial = pending.serial;
+
+        let token = pending.finish(key, &signature)?;
+        assert_eq!(token.serial(), &serial);
+        key.verify(&token)?;
+
+        let mut forged = token.clone();
+        forged.serial[0] ^= 1;
+        assert_eq!(key.verify(&forged), Err(Error::InvalidToken));
+
+        Ok(())
+    }
+
+    #[test]
+    fn denominations() -> Result<(), Error> {
+        let (five, ten) = (mint(5_000), mint(10_000));
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+
+        // a token signed with the key of a smaller denomination can not be passed off as a larger one
+        let (pending, blinded) = PendingToken::new(five.token_key(), &mut rng);
+        let token = pending.finish(five.token_key(), &five.sign_blinded(&blinded)?)?;
+        let inflated = Token { denomination: ten.denomination(), key_id: ten.token_key().id(), ..token };
+        assert_eq!(ten.token_key().verify(&inflated), Err(Error::InvalidToken));
+
+        let (pending, _) = PendingToken::new(ten.token_key(), &mut rng);
+        let (_, blinded) = PendingToken::new(five.token_key(), &mut rng);
+        assert_eq!(pending.finish(ten.token_key(), &five.sign_blinded(&blinded)?), Err(Error::InvalidToken));
+
+        assert_eq!(five.sign_blinded(&[0xff; TOKEN_KEY_BITS / 8]), Err(Error::InvalidToken));
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+use sha2::{Digest, Sha256};
+
+use super::Currency;
+use crate::crypto::Signature;
+use crate::encoding::{put_bytes, put_key, put_str, put_u64};
+use crate::iban::parse_valid;
+use crate::merkle::{MerkleProof, MerkleTree};
+use crate::traits::ToBytes;
+use crate::{merchant::Merchant, Error, Timestamp, IBAN};
+
+/// A single credit to an account within a payout batch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreditTransfer {
+    destination: IBAN,
+    // counted in thousandths, like `Transaction::amount`
+    amount: u64,
+    currency: Currency,
+    reference: String,
+}
+
+/// Many credit transfers from one originator, authorized with one signature
+/// over the Merkle root of the transfers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayoutBatch {
+    originator: Merchant,
+    transfers: Vec<CreditTransfer>,
+    created_at: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedPayoutBatch {
+    batch: PayoutBatch,
+    // made by the originator
+    signature: Signature,
+}
+
+/// Proves to the recipient of a single transfer that it is part of a batch
+/// the originator signed, without revealing the other transfers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferProof {
+    originator: Merchant,
+    created_at: Timestamp,
+    root: [u8; 32],
+    signature: Signature,
+    transfer: CreditTransfer,
+    proof: MerkleProof,
+}
+
+impl CreditTransfer {
+    /// Creates a new credit transfer, checking that `destination` is a valid
+    /// IBAN and that the reference fits into 140 characters.
+    pub fn new(destination: &str, amount: u64, currency: Currency, reference: String) -> Result<Self, Error> {
+        if reference.chars().count() > 140 {
+            return Err(Error::InvalidReference);
+        }
+
+        Ok(Self { destination: parse_valid(destination)?, amount, currency, reference })
+    }
+
+    pub fn destination(&self) -> &IBAN {
+        &self.destination
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+}
+
+impl ToBytes for CreditTransfer {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = self.destination.as_bytes();
+        put_u64(&mut buf, self.amount);
+        put_bytes(&mut buf, &self.currency.as_bytes());
+        put_str(&mut buf, &self.reference);
+        buf
+    }
+}
+
+impl PayoutBatch {
+    pub fn new(originator: Merchant, transfers: Vec<CreditTransfer>, created_at: Timestamp) -> Self {
+        Self { originator: originator.to_public(), transfers, created_at }
+    }
+
+    pub fn originator(&self) -> &Merchant {
+        &self.originator
+    }
+
+    pub fn transfers(&self) -> &[CreditTransfer] {
+        &self.transfers
+    }
+
+    pub fn created_at(&self) -> Timestamp {
+        self.created_at
+    }
+
+    pub fn tree(&self) -> MerkleTree {
+        let leaves = self.transfers.iter().map(|transfer| transfer.as_bytes()).collect::<Vec<_>>();
+        MerkleTree::new(&leaves)
+    }
+
+    /// Returns the Merkle root over all transfers.
+    pub fn root(&self) -> [u8; 32] {
+        self.tree().root()
+    }
+
+    /// Identifies the batch by the SHA-256 hash of what the originator signs.
+    pub fn id(&self) -> [u8; 32] {
+        let bytes = Self::signed_bytes(&self.originator, self.created_at, self.transfers.len(), &self.root());
+        Sha256::digest(bytes).into()
+    }
+
+    pub(crate) fn signed_bytes(originator: &Merchant, created_at: Timestamp, leaf_count: usize, root: &[u8; 32]) -> Vec<u8> {
+        let mut buf = b"payout-batch".to_vec();
+        put_str(&mut buf, originator.account_number());
+        put_key(&mut buf, originator.verifying_key());
+        put_u64(&mut buf, created_at);
+        put_u64(&mut buf, leaf_count as u64);
+        buf.extend_from_slice(root);
+        buf
+    }
+}
+
+impl SignedPayoutBatch {
+    pub(crate) fn new(batch: PayoutBatch, signature: Signature) -> Self {
+        Self { batch, signature }
+    }
+
+    pub fn batch(&self) -> &PayoutBatch {
+        &self.batch
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Verifies that the originator signed the root over exactly these transfers.
+    pub fn verify(&self) -> Result<(), Error> {
+        let batch = &self.batch;
+        let bytes = PayoutBatch::signed_bytes(&batch.originator, batch.created_at, batch.transfers.len(), &batch.root());
+
+        batch.originator.verifying_key()
+            .verify(&bytes, &self.signature)
+    }
+
+    /// Returns the inclusion proof for the transfer at `index`.
+    pub fn proof(&self, index: usize) -> Option<TransferProof> {
+        let tree = self.batch.tree();
+
+        Some(TransferProof {
+            originator: self.batch.originator.clone(),
+            created_at: self.batch.created_at,
+            root: tree.root(),
+            signature: self.signature,
+            transfer: self.batch.transfers.get(index)?.clone(),
+            proof: tree.proof(index)?,
+        })
+    }
+}
+
+impl TransferProof {
+    pub fn originator(&self) -> &Merchant {
+        &self.originator
+    }
+
+    pub fn transfer(&self) -> &CreditTransfer {
+        &self.transfer
+    }
+
+    /// Returns the root of the batch the transfer is part of.
+    pub fn root(&self) -> &[u8; 32] {
+        &self.root
+    }
+
+    /// Verifies that the transfer is part of the batch and that the
+    /// originator signed the batch.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.proof.verify(&self.transfer.as_bytes(), &self.root)?;
+
+        let bytes = PayoutBatch::signed_bytes(&self.originator, self.created_at, self.proof.leaf_count(), &self.root);
+        self.originator.verifying_key()
+            .verify(&bytes, &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inclusion_proofs() -> Result<(), Error> {
+        let originator = Merchant::new("DE89370400440532013000".to_string());
+        let transfers = vec![
+            CreditTransfer::new("GB82WEST12345698765432", 250_000, Currency::GBP, "salary 2024-05".to_string())?,
+            CreditTransfer::new("FR1420041010050500013M02606", 310_000, Currency::EUR, "salary 2024-05".to_string())?,
+            CreditTransfer::new("AT611904300234573201", 290_000, Currency::EUR, "salary 2024-05".to_string())?,
+        ];
+        let signed = originator.sign_payout_batch(PayoutBatch::new(originator.clone(), transfers, 0))?;
+        signed.verify()?;
+
+        for index in 0..3 {
+            signed.proof(index).ok_or(Error::DevError)?.verify()?;
+        }
+        assert_eq!(signed.proof(3), None);
+
+        let mut forged = signed.proof(0).ok_or(Error::DevError)?;
+        forged.transfer.amount = 2_500_000;
+        assert_eq!(forged.verify(), Err(Error::InvalidProof));
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_destination() {
+        assert_eq!(
+            CreditTransfer::new("DE22 8472 162", 1_000, Currency::EUR, String::new()),
+            Err(Error::NotAnIBAN)
+        );
+    }
+}
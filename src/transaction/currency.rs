@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+use crate::traits::ToBytes;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Currency {
     EUR,
     USD,
@@ -11,4 +13,30 @@ pub enum Currency {
     HKD,
     NZD,
     Other([char; 3])
-}
\ No newline at end of file
+}
+
+impl Currency {
+    /// Returns the three letter ISO 4217 code of the currency.
+    pub fn code(&self) -> [char; 3] {
+        match self {
+            Self::EUR => ['E', 'U', 'R'],
+            Self::USD => ['U', 'S', 'D'],
+            Self::JPY => ['J', 'P', 'Y'],
+            Self::GBP => ['G', 'B', 'P'],
+            Self::AUD => ['A', 'U', 'D'],
+            Self::CAD => ['C', 'A', 'D'],
+            Self::CHF => ['C', 'H', 'F'],
+            Self::CNH => ['C', 'N', 'H'],
+            Self::HKD => ['H', 'K', 'D'],
+            Self::NZD => ['N', 'Z', 'D'],
+            Self::Other(code) => *code,
+        }
+    }
+}
+
+impl ToBytes for Currency {
+    /// Returns the currency code, encoded as UTF-8.
+    fn as_bytes(&self) -> Vec<u8> {
+        self.code().iter().collect::<String>().into_bytes()
+    }
+}
@@ -0,0 +1,297 @@
+use sha2::{Digest, Sha256};
+
+use super::{Currency, Transaction};
+use crate::crypto::Signature;
+use crate::encoding::{put_bytes, put_iban, put_key, put_str, put_u64};
+use crate::sepa::CreditorId;
+use crate::time::{months_between, civil_date, WEEK};
+use crate::traits::ToBytes;
+use crate::{merchant::Merchant, user::User, Error, Timestamp};
+
+/// Identifies a mandate by the SHA-256 hash of its terms.
+pub type MandateId = [u8; 32];
+
+/// The interval after which the amount a mandate allows is available again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Period {
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Period {
+    /// Returns the index of the period `at` falls into, counted from `start`.
+    ///
+    /// Weekly periods are counted in full weeks since `start`, monthly and
+    /// yearly periods follow calendar months and years.
+    pub fn index(&self, start: Timestamp, at: Timestamp) -> u64 {
+        match self {
+            Self::Weekly => at.saturating_sub(start) / WEEK,
+            Self::Monthly => months_between(start, at),
+            Self::Yearly => civil_date(at).0.saturating_sub(civil_date(start).0),
+        }
+    }
+
+    fn as_byte(&self) -> u8 {
+        match self {
+            Self::Weekly => 0,
+            Self::Monthly => 1,
+            Self::Yearly => 2,
+        }
+    }
+}
+
+/// A user's permission for a merchant to pull recurring payments.
+///
+/// The merchant may collect up to `max_amount` per `period`, in a single
+/// currency, from `start` until (excluding) `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mandate {
+    user: User,
+    merchant: Merchant,
+    currency: Currency,
+    // counted in thousandths, like `Transaction::amount`
+    max_amount: u64,
+    period: Period,
+    start: Timestamp,
+    end: Timestamp,
+    // the creditor identifier and mandate reference direct debits are made under
+    direct_debit: Option<(CreditorId, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedMandate {
+    mandate: Mandate,
+    signature: Signature,
+}
+
+/// A user-signed statement that a mandate may not be used anymore.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MandateRevocation {
+    mandate_id: MandateId,
+    revoked_at: Timestamp,
+    signature: Signature,
+}
+
+/// A merchant-initiated transaction, authorized through a mandate instead of
+/// a user signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MandatedTransaction {
+    transaction: Transaction,
+    mandate_id: MandateId,
+    // made by the merchant
+    signature: Signature,
+}
+
+impl Mandate {
+    /// Creates a mandate, which has to allow a positive amount and end
+    /// after it starts.
+    pub fn new(
+        user: User,
+        merchant: Merchant,
+        currency: Currency,
+        max_amount: u64,
+        period: Period,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Self, Error> {
+        if max_amount == 0 || start >= end {
+            return Err(Error::InvalidMandate);
+        }
+
+        Ok(Self {
+            user: user.to_public(),
+            merchant: merchant.to_public(),
+            currency,
+            max_amount,
+            period,
+            start,
+            end,
+            direct_debit: None,
+        })
+    }
+
+    /// Allows the merchant to collect SEPA direct debits under the mandate,
+    /// as the creditor with `creditor_id` and under `reference`, which has
+    /// to be between 1 and 35 characters long.
+    pub fn for_direct_debit(mut self, creditor_id: CreditorId, reference: String) -> Result<Self, Error> {
+        if reference.is_empty() || reference.chars().count() > 35 {
+            return Err(Error::InvalidMandateReference);
+        }
+
+        self.direct_debit = Some((creditor_id, reference));
+        Ok(self)
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn merchant(&self) -> &Merchant {
+        &self.merchant
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn max_amount(&self) -> u64 {
+        self.max_amount
+    }
+
+    pub fn period(&self) -> Period {
+        self.period
+    }
+
+    pub fn start(&self) -> Timestamp {
+        self.start
+    }
+
+    pub fn end(&self) -> Timestamp {
+        self.end
+    }
+
+    /// Returns the creditor identifier and mandate reference direct debits
+    /// have to be made under, if the mandate allows direct debits.
+    pub fn direct_debit(&self) -> Option<(&CreditorId, &str)> {
+        self.direct_debit.as_ref().map(|(creditor_id, reference)| (creditor_id, reference.as_str()))
+    }
+
+    /// Returns whether the mandate may be used at `at`.
+    pub fn is_active(&self, at: Timestamp) -> bool {
+        self.start <= at && at < self.end
+    }
+
+    pub fn id(&self) -> MandateId {
+        Sha256::digest(self.as_bytes()).into()
+    }
+}
+
+impl ToBytes for Mandate {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_iban(&mut buf, self.user.account_number());
+        put_key(&mut buf, self.user.verifying_key());
+        put_str(&mut buf, self.merchant.account_number());
+        put_key(&mut buf, self.merchant.verifying_key());
+        put_bytes(&mut buf, &self.currency.as_bytes());
+        put_u64(&mut buf, self.max_amount);
+        buf.push(self.period.as_byte());
+        put_u64(&mut buf, self.start);
+        put_u64(&mut buf, self.end);
+        match &self.direct_debit {
+            None => buf.push(0),
+            Some((creditor_id, reference)) => {
+                buf.push(1);
+                put_str(&mut buf, creditor_id.as_str());
+                put_str(&mut buf, reference);
+            }
+        }
+        buf
+    }
+}
+
+impl SignedMandate {
+    pub(crate) fn new(mandate: Mandate, signature: Signature) -> Self {
+        Self { mandate, signature }
+    }
+
+    pub fn mandate(&self) -> &Mandate {
+        &self.mandate
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    pub fn id(&self) -> MandateId {
+        self.mandate.id()
+    }
+
+    /// Verifies that the mandate was signed by its user.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.mandate.user.verifying_key()
+            .verify(&self.mandate.as_bytes(), &self.signature)
+    }
+}
+
+impl MandateRevocation {
+    pub(crate) fn new(mandate_id: MandateId, revoked_at: Timestamp, signature: Signature) -> Self {
+        Self { mandate_id, revoked_at, signature }
+    }
+
+    pub(crate) fn signed_bytes(mandate_id: &MandateId, revoked_at: Timestamp) -> Vec<u8> {
+        let mut buf = b"revoke".to_vec();
+        buf.extend_from_slice(mandate_id);
+        put_u64(&mut buf, revoked_at);
+        buf
+    }
+
+    pub fn mandate_id(&self) -> &MandateId {
+        &self.mandate_id
+    }
+
+    pub fn revoked_at(&self) -> Timestamp {
+        self.revoked_at
+    }
+
+    /// Verifies that the revocation was signed by the user of `mandate`.
+    pub fn verify(&self, mandate: &Mandate) -> Result<(), Error> {
+        if mandate.id() != self.mandate_id {
+            return Err(Error::MandateMismatch);
+        }
+
+        mandate.user.verifying_key()
+            .verify(&Self::signed_bytes(&self.mandate_id, self.revoked_at), &self.signature)
+    }
+}
+
+impl MandatedTransaction {
+    pub(crate) fn new(transaction: Transaction, mandate_id: MandateId, signature: Signature) -> Self {
+        Self { transaction, mandate_id, signature }
+    }
+
+    pub(crate) fn signed_bytes(transaction: &Transaction, mandate_id: &MandateId) -> Vec<u8> {
+        let mut buf = transaction.as_bytes();
+        buf.extend_from_slice(mandate_id);
+        buf
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn mandate_id(&self) -> &MandateId {
+        &self.mandate_id
+    }
+
+    pub fn created_at(&self) -> Timestamp {
+        self.transaction.created_at()
+    }
+
+    /// Verifies the merchant's signature and that the transaction stays
+    /// within the static terms of `mandate`.
+    ///
+    /// Spending caps depend on earlier transactions and are enforced by the bank.
+    pub fn verify(&self, mandate: &Mandate) -> Result<(), Error> {
+        let transaction = &self.transaction;
+        let merchant = transaction.merchant().ok_or(Error::MandateMismatch)?;
+
+        if mandate.id() != self.mandate_id
+            || merchant.verifying_key() != mandate.merchant.verifying_key()
+            || transaction.user().verifying_key() != mandate.user.verifying_key()
+            || transaction.currency() != mandate.currency
+        {
+            return Err(Error::MandateMismatch);
+        }
+
+        merchant.verifying_key()
+            .verify(&Self::signed_bytes(transaction, &self.mandate_id), &self.signature)?;
+
+        if !mandate.is_active(transaction.created_at()) {
+            return Err(Error::MandateNotActive);
+        }
+
+        Ok(())
+    }
+}
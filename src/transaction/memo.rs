@@ -0,0 +1,91 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use hkdf::Hkdf;
+use p256::ecdh::{diffie_hellman, EphemeralSecret, SharedSecret};
+use p256::ecdsa::SigningKey;
+use rand_core::CryptoRngCore;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::crypto::PublicKey;
+use crate::encoding::put_bytes;
+use crate::Error;
+
+/// The most characters a memo may have, as many as a SEPA remittance information.
+pub const MAX_MEMO_LEN: usize = 140;
+
+/// A memo only the merchant of a transaction can read.
+///
+/// Encrypted with AES-256-GCM under a key derived by HKDF-SHA256 from an
+/// ECDH exchange between a fresh ephemeral key and the merchant's key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedMemo {
+    ephemeral_key: p256::PublicKey,
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedMemo {
+    /// Encrypts `memo` to `recipient`, which has to be a P-256 key, bound to
+    /// `aad` so that it can't be moved to another transaction.
+    pub(crate) fn seal(recipient: &PublicKey, memo: &str, aad: &[u8], rng: &mut impl CryptoRngCore) -> Result<Self, Error> {
+        if memo.chars().count() > MAX_MEMO_LEN {
+            return Err(Error::InvalidReference);
+        }
+        let PublicKey::EcdsaP256(recipient) = recipient else {
+            return Err(Error::CannotEncryptMemo);
+        };
+        let recipient = p256::PublicKey::from(recipient);
+
+        let ephemeral = EphemeralSecret::random(rng);
+        let ephemeral_key = ephemeral.public_key();
+        let cipher = cipher(&ephemeral.diffie_hellman(&recipient), &ephemeral_key, &recipient);
+
+        let mut nonce = [0; 12];
+        rng.fill_bytes(&mut nonce);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: memo.as_bytes(), aad })
+            .map_err(|_| Error::CannotEncryptMemo)?;
+
+        Ok(Self { ephemeral_key, nonce, ciphertext })
+    }
+
+    /// Decrypts the memo with the secret key of its recipient, given the
+    /// same `aad` it was sealed with.
+    pub(crate) fn open(&self, recipient: &SigningKey, aad: &[u8]) -> Result<String, Error> {
+        let shared = diffie_hellman(recipient.as_nonzero_scalar(), self.ephemeral_key.as_affine());
+        let cipher = cipher(&shared, &self.ephemeral_key, &p256::PublicKey::from(recipient.verifying_key()));
+
+        let plaintext = Zeroizing::new(
+            cipher
+                .decrypt(Nonce::from_slice(&self.nonce), Payload { msg: &self.ciphertext, aad })
+                .map_err(|_| Error::CannotDecryptMemo)?,
+        );
+        String::from_utf8(plaintext.to_vec()).map_err(|_| Error::CannotDecryptMemo)
+    }
+
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+
+    pub(crate) fn put(&self, buf: &mut Vec<u8>) {
+        put_bytes(buf, &self.ephemeral_key.to_sec1_bytes());
+        put_bytes(buf, &self.nonce);
+        put_bytes(buf, &self.ciphertext);
+    }
+}
+
+// binds the key to both public keys, so a memo can't be passed off as
+// encrypted to another recipient
+fn cipher(shared: &SharedSecret, ephemeral_key: &p256::PublicKey, recipient: &p256::PublicKey) -> Aes256Gcm {
+    let mut info = b"rustpay memo".to_vec();
+    put_bytes(&mut info, &ephemeral_key.to_sec1_bytes());
+    put_bytes(&mut info, &recipient.to_sec1_bytes());
+
+    let mut key = Zeroizing::new([0; 32]);
+    Hkdf::<Sha256>::new(None, shared.raw_secret_bytes())
+        .expand(&info, key.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    Aes256Gcm::new(key.as_ref().into())
+}
@@ -1,27 +1,401 @@
+pub use batch::{CreditTransfer, PayoutBatch, SignedPayoutBatch, TransferProof};
 pub use currency::Currency;
-use p256::ecdsa::Signature;
+pub use memo::{EncryptedMemo, MAX_MEMO_LEN};
+pub use mandate::{Mandate, MandateId, MandateRevocation, MandatedTransaction, Period, SignedMandate};
+pub use offline::{AllowanceId, OfflineAllowance, OfflinePayment};
+pub use refund::{LegRefund, RefundId};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
 
-use crate::{merchant::Merchant, user::User};
+use crate::bank::{escrow_terms, Condition};
+use crate::crypto::{Algorithm, KeyCache, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_str, put_u64};
+use crate::traits::ToBytes;
+use crate::user::{verify_chain, DelegationCertificate};
+use crate::{merchant::Merchant, user::User, Error, Timestamp};
 
+mod batch;
 mod currency;
+mod mandate;
+mod memo;
+mod offline;
+mod refund;
+
+/// Identifies a transaction by the SHA-256 hash of its contents.
+pub type TransactionId = [u8; 32];
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Transaction {
     // amount is counted in thousandths, e.g. 1€ equals amount = 1000
     amount: u64,
     currency: Currency,
-    merchant: Merchant,
+    legs: Vec<Leg>,
     user: User,
+    created_at: Timestamp,
+    purpose: Purpose,
+    // readable by the merchant only, covered by the signature as ciphertext
+    memo: Option<EncryptedMemo>,
+}
+
+/// What a user signed a transaction for. Only payments can be applied to a
+/// ledger directly, so that a signature made for anything else can not be
+/// spent a second time as a payment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Purpose {
+    #[default]
+    Payment,
+    /// Funds an escrow under the release and refund conditions with this hash.
+    Escrow([u8; 32]),
+    /// Pays offline against the allowance with this id.
+    Offline(AllowanceId),
+}
+
+/// The part of a transaction's amount that goes to one merchant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg {
+    merchant: Merchant,
+    amount: u64,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SignedTransaction {
     transaction: Transaction,
     signature: Signature,
+    // leads from the user to the key that made the signature, empty if the
+    // user signed directly
+    delegation: Vec<DelegationCertificate>,
+}
+
+impl Transaction {
+    /// Creates a new transaction paying a single merchant.
+    ///
+    /// Only the public parts of `merchant` and `user` are kept, so that a
+    /// transaction can be handed to other parties without leaking keys.
+    pub fn new(amount: u64, currency: Currency, merchant: Merchant, user: User, created_at: Timestamp) -> Self {
+        Self { amount, currency, legs: vec![Leg::new(merchant, amount)], user: user.to_public(), created_at, purpose: Purpose::Payment, memo: None }
+    }
+
+    /// Creates a new transaction paying several merchants at once.
+    ///
+    /// Fails if there are no legs or if they do not sum up to `amount`.
+    pub fn split(amount: u64, currency: Currency, legs: Vec<Leg>, user: User, created_at: Timestamp) -> Result<Self, Error> {
+        let transaction = Self { amount, currency, legs, user: user.to_public(), created_at, purpose: Purpose::Payment, memo: None };
+        transaction.validate()?;
+
+        Ok(transaction)
+    }
+
+    /// Makes the transaction fund an escrow that pays out under `release` and
+    /// `refund`, instead of paying the merchants directly.
+    pub fn for_escrow(mut self, release: &Condition, refund: &Condition) -> Self {
+        self.purpose = Purpose::Escrow(escrow_terms(release, refund));
+        self
+    }
+
+    /// Makes the transaction pay against an offline allowance, which the
+    /// bank settles when reconciling the allowance only.
+    pub fn for_offline(mut self, allowance: &OfflineAllowance) -> Self {
+        self.purpose = Purpose::Offline(allowance.id());
+        self
+    }
+
+    /// Attaches a memo of at most [`MAX_MEMO_LEN`] characters, encrypted to
+    /// the merchant. Only transactions paying a single merchant can carry one.
+    ///
+    /// The memo is bound to the rest of the transaction, so it has to be
+    /// attached last.
+    pub fn with_memo(self, memo: &str) -> Result<Self, Error> {
+        self.with_memo_from_rng(memo, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`Transaction::with_memo`], drawing the ephemeral key and nonce from `rng`.
+    pub fn with_memo_from_rng(mut self, memo: &str, rng: &mut impl CryptoRngCore) -> Result<Self, Error> {
+        let merchant = self.merchant().ok_or(Error::CannotEncryptMemo)?;
+        self.memo = Some(EncryptedMemo::seal(merchant.verifying_key(), memo, &self.memo_aad(), rng)?);
+
+        Ok(self)
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn purpose(&self) -> Purpose {
+        self.purpose
+    }
+
+    pub fn memo(&self) -> Option<&EncryptedMemo> {
+        self.memo.as_ref()
+    }
+
+    pub fn legs(&self) -> &[Leg] {
+        &self.legs
+    }
+
+    /// Returns the merchant, if the transaction pays exactly one.
+    pub fn merchant(&self) -> Option<&Merchant> {
+        match self.legs.as_slice() {
+            [leg] => Some(&leg.merchant),
+            _ => None,
+        }
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn created_at(&self) -> Timestamp {
+        self.created_at
+    }
+
+    pub fn id(&self) -> TransactionId {
+        Sha256::digest(self.as_bytes()).into()
+    }
+
+    /// Checks that there is at least one leg and that the legs sum up to the amount.
+    pub fn validate(&self) -> Result<(), Error> {
+        let total = self.legs.iter()
+            .try_fold(0u64, |total, leg| total.checked_add(leg.amount))
+            .ok_or(Error::InvalidLegs)?;
+
+        if self.legs.is_empty() || total != self.amount {
+            return Err(Error::InvalidLegs);
+        }
+
+        Ok(())
+    }
+}
+
+impl Leg {
+    pub fn new(merchant: Merchant, amount: u64) -> Self {
+        Self { merchant: merchant.to_public(), amount }
+    }
+
+    pub fn merchant(&self) -> &Merchant {
+        &self.merchant
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
 }
 
 impl Transaction {
-    pub fn new(amount: u64, currency: Currency, merchant: Merchant, user: User) -> Self {
-        Self { amount, currency, merchant, user }
+    // everything signed but the memo, which the memo is encrypted under as
+    // associated data
+    pub(crate) fn memo_aad(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_u64(&mut buf, self.amount);
+        put_bytes(&mut buf, &self.currency.as_bytes());
+        put_u64(&mut buf, self.legs.len() as u64);
+        for leg in &self.legs {
+            put_str(&mut buf, leg.merchant.account_number());
+            put_key(&mut buf, leg.merchant.verifying_key());
+            // 0 is not a valid category code
+            put_u64(&mut buf, leg.merchant.category_code().unwrap_or(0).into());
+            put_u64(&mut buf, leg.amount);
+        }
+        put_iban(&mut buf, self.user.account_number());
+        put_key(&mut buf, self.user.verifying_key());
+        put_u64(&mut buf, self.created_at);
+        match self.purpose {
+            Purpose::Payment => buf.push(0),
+            Purpose::Escrow(terms) => {
+                buf.push(1);
+                buf.extend_from_slice(&terms);
+            }
+            Purpose::Offline(allowance) => {
+                buf.push(2);
+                buf.extend_from_slice(&allowance);
+            }
+        }
+        buf
     }
-}
\ No newline at end of file
+}
+
+impl ToBytes for Transaction {
+    /// Returns the canonical encoding of the transaction, which is what gets signed.
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = self.memo_aad();
+        match &self.memo {
+            Some(memo) => {
+                buf.push(1);
+                memo.put(&mut buf);
+            }
+            None => buf.push(0),
+        }
+        buf
+    }
+}
+
+impl SignedTransaction {
+    pub(crate) fn new(transaction: Transaction, signature: Signature) -> Self {
+        Self { transaction, signature, delegation: Vec::new() }
+    }
+
+    pub(crate) fn with_delegation(mut self, delegation: Vec<DelegationCertificate>) -> Self {
+        self.delegation = delegation;
+        self
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Returns the algorithm of the signature.
+    pub fn algorithm(&self) -> Algorithm {
+        self.signature.algorithm()
+    }
+
+    /// Returns the delegation chain the transaction was signed through.
+    pub fn delegation(&self) -> &[DelegationCertificate] {
+        &self.delegation
+    }
+
+    pub fn id(&self) -> TransactionId {
+        self.transaction.id()
+    }
+
+    /// Verifies that the transaction is well-formed and was signed by its
+    /// user, or by a key the user delegated to within its allowance.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.transaction.validate()?;
+        verify_chain(&self.transaction, &self.delegation)?
+            .verify(&self.transaction.as_bytes(), &self.signature)
+    }
+
+    /// Verifies like [`SignedTransaction::verify`], checking the signature
+    /// with the key cached under the signer's key id.
+    pub(crate) fn verify_cached(&self, keys: &KeyCache) -> Result<(), Error> {
+        self.transaction.validate()?;
+        let signer = verify_chain(&self.transaction, &self.delegation)?;
+        keys.resolve(signer).verify(&self.transaction.as_bytes(), &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::TransactionSign;
+
+    #[test]
+    fn sign_and_verify() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant, user.clone(), 0);
+
+        let signed = user.sign(transaction)?;
+        signed.verify()
+    }
+
+    #[test]
+    fn sign_requires_private_key() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant, user.clone(), 0);
+
+        assert_eq!(user.to_public().sign(transaction), Err(Error::NoPrivateKey));
+        Ok(())
+    }
+
+    #[test]
+    fn sign_by_other_user() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let other = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant, user, 0);
+
+        assert_eq!(other.sign(transaction), Err(Error::WrongSigner));
+        Ok(())
+    }
+
+    #[test]
+    fn split() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let seller = Merchant::new("GB82WEST12345698765432".to_string());
+        let platform = Merchant::new("FR1420041010050500013M02606".to_string());
+        let legs = vec![Leg::new(seller.clone(), 9_500), Leg::new(platform.clone(), 500)];
+
+        let signed = user.sign(Transaction::split(10_000, Currency::EUR, legs.clone(), user.clone(), 0)?)?;
+        signed.verify()?;
+        assert_eq!(signed.transaction().merchant(), None);
+
+        assert_eq!(
+            Transaction::split(9_000, Currency::EUR, legs, user.clone(), 0),
+            Err(Error::InvalidLegs)
+        );
+        assert_eq!(
+            Transaction::split(0, Currency::EUR, vec![], user, 0),
+            Err(Error::InvalidLegs)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn tampered_amount() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant, user.clone(), 0);
+
+        let mut signed = user.sign(transaction)?;
+        signed.transaction.amount = 100_000;
+        signed.transaction.legs[0].amount = 100_000;
+
+        assert_eq!(signed.verify(), Err(Error::InvalidSignature));
+        Ok(())
+    }
+
+    #[test]
+    fn memo() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let eavesdropper = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant.clone(), user.clone(), 0).with_memo("invoice 4711")?;
+
+        let mut signed = user.sign(transaction)?;
+        signed.verify()?;
+        assert_eq!(merchant.read_memo(signed.transaction())?, Some("invoice 4711".to_string()));
+        assert_eq!(eavesdropper.read_memo(signed.transaction()), Err(Error::CannotDecryptMemo));
+        assert_eq!(merchant.to_public().read_memo(signed.transaction()), Err(Error::NoPrivateKey));
+
+        // the ciphertext is covered by the signature
+        let other = Transaction::new(2000, Currency::EUR, merchant.clone(), user.clone(), 0).with_memo("invoice 4712")?;
+        signed.transaction.memo = other.memo.clone();
+        assert_eq!(signed.verify(), Err(Error::InvalidSignature));
+
+        // and can't be moved to another transaction to be signed again
+        let mut moved = Transaction::new(1000, Currency::EUR, merchant.clone(), user.clone(), 0);
+        moved.memo = other.memo;
+        assert_eq!(merchant.read_memo(&moved), Err(Error::CannotDecryptMemo));
+
+        Ok(())
+    }
+
+    #[test]
+    fn memo_limits() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let transaction = Transaction::new(1000, Currency::EUR, merchant.clone(), user.clone(), 0);
+
+        assert_eq!(transaction.clone().with_memo(&"x".repeat(141)), Err(Error::InvalidReference));
+        let legs = vec![Leg::new(merchant.clone(), 500), Leg::new(merchant.clone(), 500)];
+        assert_eq!(
+            Transaction::split(1000, Currency::EUR, legs, user, 0)?.with_memo("split"),
+            Err(Error::CannotEncryptMemo)
+        );
+        assert_eq!(merchant.read_memo(&transaction)?, None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use sha2::{Digest, Sha256};
+
+use super::{Currency, Purpose, SignedTransaction, Transaction, TransactionId};
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_u64};
+use crate::traits::ToBytes;
+use crate::{merchant::Merchant, user::User, Error, Timestamp};
+
+/// Identifies an offline allowance by the SHA-256 hash of its signed contents.
+pub type AllowanceId = [u8; 32];
+
+/// Lets a user pay up to `limit` in total while offline, signed by the bank,
+/// which holds back that amount on the user's account until it reconciles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineAllowance {
+    user: User,
+    currency: Currency,
+    // counted in thousandths, like transaction amounts
+    limit: u64,
+    valid_from: Timestamp,
+    expires_at: Timestamp,
+    bank_key: PublicKey,
+    signature: Signature,
+}
+
+/// A transaction paid against an offline allowance.
+///
+/// The paying device numbers its payments, so that the bank can tell a
+/// payment uploaded twice from two payments made with the same number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflinePayment {
+    allowance: OfflineAllowance,
+    signed: SignedTransaction,
+    counter: u64,
+    // made by the user over the allowance, the counter and the transaction
+    signature: Signature,
+}
+
+impl OfflineAllowance {
+    pub(crate) fn new(
+        user: User,
+        currency: Currency,
+        limit: u64,
+        valid_from: Timestamp,
+        expires_at: Timestamp,
+        bank_key: PublicKey,
+        signature: Signature,
+    ) -> Self {
+        Self { user, currency, limit, valid_from, expires_at, bank_key, signature }
+    }
+
+    pub(crate) fn signed_bytes(
+        user: &User,
+        currency: Currency,
+        limit: u64,
+        valid_from: Timestamp,
+        expires_at: Timestamp,
+        bank_key: &PublicKey,
+    ) -> Vec<u8> {
+        let mut buf = b"offline-allowance".to_vec();
+        put_iban(&mut buf, user.account_number());
+        put_key(&mut buf, user.verifying_key());
+        put_bytes(&mut buf, &currency.as_bytes());
+        put_u64(&mut buf, limit);
+        put_u64(&mut buf, valid_from);
+        put_u64(&mut buf, expires_at);
+        put_key(&mut buf, bank_key);
+        buf
+    }
+
+    fn to_signed_bytes(&self) -> Vec<u8> {
+        Self::signed_bytes(&self.user, self.currency, self.limit, self.valid_from, self.expires_at, &self.bank_key)
+    }
+
+    pub fn id(&self) -> AllowanceId {
+        Sha256::digest(self.to_signed_bytes()).into()
+    }
+
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn valid_from(&self) -> Timestamp {
+        self.valid_from
+    }
+
+    pub fn expires_at(&self) -> Timestamp {
+        self.expires_at
+    }
+
+    pub fn bank_key(&self) -> &PublicKey {
+        &self.bank_key
+    }
+
+    /// Checks that the allowance was signed by `bank_key`.
+    pub fn verify(&self, bank_key: &PublicKey) -> Result<(), Error> {
+        if *bank_key != self.bank_key {
+            return Err(Error::WrongSigner);
+        }
+
+        bank_key.verify(&self.to_signed_bytes(), &self.signature)
+    }
+
+    /// Checks that a single transaction may be paid against the allowance
+    /// at `now`: both the transaction's date and `now` have to fall within
+    /// the allowance, as the user picks the date.
+    ///
+    /// Whether all payments together stay within the limit can only be told
+    /// by the paying device, or by the bank once all of them are uploaded.
+    pub fn check(&self, transaction: &Transaction, now: Timestamp) -> Result<(), Error> {
+        let user = transaction.user();
+        if user.verifying_key() != self.user.verifying_key()
+            || user.account_number() != self.user.account_number()
+            || transaction.currency() != self.currency
+        {
+            return Err(Error::AllowanceMismatch);
+        }
+
+        if transaction.purpose() != Purpose::Offline(self.id()) {
+            return Err(Error::WrongPurpose);
+        }
+
+        if [transaction.created_at(), now].iter().any(|at| *at < self.valid_from || *at >= self.expires_at) {
+            return Err(Error::AllowanceExpired);
+        }
+
+        if transaction.amount() > self.limit {
+            return Err(Error::AllowanceExceeded);
+        }
+
+        Ok(())
+    }
+}
+
+impl OfflinePayment {
+    pub(crate) fn new(allowance: OfflineAllowance, signed: SignedTransaction, counter: u64, signature: Signature) -> Self {
+        Self { allowance, signed, counter, signature }
+    }
+
+    pub(crate) fn signed_bytes(allowance: &AllowanceId, counter: u64, transaction: &TransactionId) -> Vec<u8> {
+        let mut buf = b"offline-payment".to_vec();
+        put_bytes(&mut buf, allowance);
+        put_u64(&mut buf, counter);
+        put_bytes(&mut buf, transaction);
+        buf
+    }
+
+    pub fn allowance(&self) -> &OfflineAllowance {
+        &self.allowance
+    }
+
+    pub fn signed(&self) -> &SignedTransaction {
+        &self.signed
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        self.signed.transaction()
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.counter
+    }
+
+    /// Verifies everything that can be checked without talking to the bank:
+    /// the allowance and its signature by `bank_key`, that the transaction
+    /// fits the allowance at `now`, and the user's signatures.
+    pub fn verify(&self, bank_key: &PublicKey, now: Timestamp) -> Result<(), Error> {
+        self.allowance.verify(bank_key)?;
+        self.allowance.check(self.transaction(), now)?;
+        self.signed.verify()?;
+
+        let message = Self::signed_bytes(&self.allowance.id(), self.counter, &self.signed.id());
+        self.allowance.user.verifying_key().verify(&message, &self.signature)
+    }
+
+    /// Returns the amount the payment credits `merchant` with.
+    pub fn amount_for(&self, merchant: &Merchant) -> u64 {
+        self.transaction().legs().iter()
+            .filter(|leg| leg.merchant().verifying_key() == merchant.verifying_key())
+            .map(|leg| leg.amount())
+            .sum()
+    }
+}
@@ -0,0 +1,64 @@
+use sha2::{Digest, Sha256};
+
+use super::{SignedTransaction, TransactionId};
+use crate::crypto::Signature;
+use crate::encoding::put_u64;
+use crate::Error;
+
+/// Identifies a refund by the SHA-256 hash of its signed contents.
+pub type RefundId = [u8; 32];
+
+/// A merchant's refund of (part of) the leg of a transaction that paid it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegRefund {
+    transaction_id: TransactionId,
+    leg: usize,
+    amount: u64,
+    // random, so that two refunds of the same amount can be told apart from a replayed one
+    nonce: [u8; 16],
+    // made by the merchant of the leg
+    signature: Signature,
+}
+
+impl LegRefund {
+    pub(crate) fn new(transaction_id: TransactionId, leg: usize, amount: u64, nonce: [u8; 16], signature: Signature) -> Self {
+        Self { transaction_id, leg, amount, nonce, signature }
+    }
+
+    pub(crate) fn signed_bytes(transaction_id: &TransactionId, leg: usize, amount: u64, nonce: &[u8; 16]) -> Vec<u8> {
+        let mut buf = b"refund-leg".to_vec();
+        buf.extend_from_slice(transaction_id);
+        put_u64(&mut buf, leg as u64);
+        put_u64(&mut buf, amount);
+        buf.extend_from_slice(nonce);
+        buf
+    }
+
+    pub fn id(&self) -> RefundId {
+        Sha256::digest(Self::signed_bytes(&self.transaction_id, self.leg, self.amount, &self.nonce)).into()
+    }
+
+    pub fn transaction_id(&self) -> &TransactionId {
+        &self.transaction_id
+    }
+
+    /// Returns the index of the refunded leg within the transaction.
+    pub fn leg(&self) -> usize {
+        self.leg
+    }
+
+    pub fn amount(&self) -> u64 {
+        self.amount
+    }
+
+    /// Verifies that the refund was signed by the merchant of the refunded leg.
+    pub fn verify(&self, signed: &SignedTransaction) -> Result<(), Error> {
+        if signed.id() != self.transaction_id {
+            return Err(Error::UnknownTransaction);
+        }
+
+        let leg = signed.transaction().legs().get(self.leg).ok_or(Error::UnknownLeg)?;
+        leg.merchant().verifying_key()
+            .verify(&Self::signed_bytes(&self.transaction_id, self.leg, self.amount, &self.nonce), &self.signature)
+    }
+}
@@ -0,0 +1,230 @@
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_key, put_u64};
+use crate::traits::ToBytes;
+use crate::transaction::{Currency, Transaction};
+use crate::{Error, Timestamp};
+
+/// Identifies a delegation by the SHA-256 hash of its signed contents.
+pub type DelegationId = [u8; 32];
+
+/// What a delegated key may spend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allowance {
+    // the most all transactions signed under the allowance may amount to
+    // together, in thousandths
+    max_amount: u64,
+    currency: Currency,
+    // `None` allows paying any merchant
+    merchants: Option<Vec<PublicKey>>,
+    expires_at: Timestamp,
+}
+
+/// A certificate, signed by the delegator, allowing another key to sign
+/// transactions on the delegator's behalf within an allowance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelegationCertificate {
+    delegator: PublicKey,
+    delegate: PublicKey,
+    allowance: Allowance,
+    signature: Signature,
+}
+
+impl Allowance {
+    pub fn new(max_amount: u64, currency: Currency, expires_at: Timestamp) -> Self {
+        Self { max_amount, currency, merchants: None, expires_at }
+    }
+
+    /// Restricts the allowance to paying the given merchants only.
+    pub fn with_merchants(mut self, merchants: Vec<PublicKey>) -> Self {
+        self.merchants = Some(merchants);
+        self
+    }
+
+    pub fn max_amount(&self) -> u64 {
+        self.max_amount
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn merchants(&self) -> Option<&[PublicKey]> {
+        self.merchants.as_deref()
+    }
+
+    pub fn expires_at(&self) -> Timestamp {
+        self.expires_at
+    }
+
+    /// Checks that a transaction on its own stays within the allowance.
+    ///
+    /// The expiry is checked against the transaction's own timestamp, which
+    /// the delegate chooses. Banks check it against the time they receive the
+    /// transaction, and the total spent, through
+    /// [`DelegationBook`](crate::bank::DelegationBook).
+    pub fn check(&self, transaction: &Transaction) -> Result<(), Error> {
+        if transaction.created_at() >= self.expires_at {
+            return Err(Error::DelegationExpired);
+        }
+
+        let merchant_allowed = |key: &PublicKey| self.merchants.as_ref().is_none_or(|merchants| merchants.contains(key));
+        if transaction.currency() != self.currency
+            || transaction.amount() > self.max_amount
+            || !transaction.legs().iter().all(|leg| merchant_allowed(leg.merchant().verifying_key()))
+        {
+            return Err(Error::DelegationOutOfScope);
+        }
+
+        Ok(())
+    }
+}
+
+impl ToBytes for Allowance {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        put_u64(&mut buf, self.max_amount);
+        put_bytes(&mut buf, &self.currency.as_bytes());
+        match &self.merchants {
+            Some(merchants) => {
+                buf.push(1);
+                put_u64(&mut buf, merchants.len() as u64);
+                for merchant in merchants {
+                    put_key(&mut buf, merchant);
+                }
+            }
+            None => buf.push(0),
+        }
+        put_u64(&mut buf, self.expires_at);
+        buf
+    }
+}
+
+impl DelegationCertificate {
+    pub(crate) fn new(delegator: PublicKey, delegate: PublicKey, allowance: Allowance, signature: Signature) -> Self {
+        Self { delegator, delegate, allowance, signature }
+    }
+
+    pub(crate) fn signed_bytes(delegator: &PublicKey, delegate: &PublicKey, allowance: &Allowance) -> Vec<u8> {
+        let mut buf = b"delegation".to_vec();
+        put_key(&mut buf, delegator);
+        put_key(&mut buf, delegate);
+        buf.extend_from_slice(&allowance.as_bytes());
+        buf
+    }
+
+    pub fn delegator(&self) -> &PublicKey {
+        &self.delegator
+    }
+
+    pub fn delegate(&self) -> &PublicKey {
+        &self.delegate
+    }
+
+    pub fn allowance(&self) -> &Allowance {
+        &self.allowance
+    }
+
+    pub fn id(&self) -> DelegationId {
+        Sha256::digest(Self::signed_bytes(&self.delegator, &self.delegate, &self.allowance)).into()
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        self.delegator
+            .verify(&Self::signed_bytes(&self.delegator, &self.delegate, &self.allowance), &self.signature)
+    }
+}
+
+/// Walks a delegation chain starting at the user of `transaction`, checking
+/// every certificate and that the transaction stays within each allowance.
+///
+/// Returns the key that has to have signed the transaction.
+pub(crate) fn verify_chain<'a>(transaction: &'a Transaction, chain: &'a [DelegationCertificate]) -> Result<&'a PublicKey, Error> {
+    let mut signer = transaction.user().verifying_key();
+
+    for certificate in chain {
+        if certificate.delegator() != signer {
+            return Err(Error::DelegationChainBroken);
+        }
+
+        certificate.verify()?;
+        certificate.allowance().check(transaction)?;
+        signer = certificate.delegate();
+    }
+
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{DelegationBook, Ledger};
+    use crate::iban::parse_valid;
+    use crate::merchant::Merchant;
+    use crate::user::User;
+
+    const EXPIRY: Timestamp = 1_000;
+
+    fn transaction(owner: &User, merchant: &Merchant, amount: u64, at: Timestamp) -> Transaction {
+        Transaction::new(amount, Currency::EUR, merchant.clone(), owner.clone(), at)
+    }
+
+    #[test]
+    fn delegated_payment() -> Result<(), Error> {
+        let owner = User::new("DE89370400440532013000".parse()?);
+        let device = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+
+        let certificate = owner.delegate(device.verifying_key(), Allowance::new(2_000, Currency::EUR, EXPIRY))?;
+        let signed = device.sign_delegated(transaction(&owner, &merchant, 2_000, 0), vec![certificate.clone()])?;
+        signed.verify()?;
+
+        let mut ledger = Ledger::new();
+        ledger.keys_mut().enroll(&owner, 0, None)?;
+        ledger.deposit(owner.account_number(), Currency::EUR, 2_000)?;
+        DelegationBook::new().apply(&mut ledger, &signed, 0)?;
+        assert_eq!(ledger.balance(&parse_valid(merchant.account_number())?, Currency::EUR), 2_000);
+
+        // out of scope transactions are refused by the device and by verifiers
+        let chain = vec![certificate];
+        assert_eq!(device.sign_delegated(transaction(&owner, &merchant, 2_001, 0), chain.clone()), Err(Error::DelegationOutOfScope));
+        assert_eq!(device.sign_delegated(transaction(&owner, &merchant, 1, EXPIRY), chain), Err(Error::DelegationExpired));
+
+        Ok(())
+    }
+
+    #[test]
+    fn sub_delegation() -> Result<(), Error> {
+        let owner = User::new("DE89370400440532013000".parse()?);
+        let parent = User::new("DE89370400440532013000".parse()?);
+        let child = User::new("DE89370400440532013000".parse()?);
+        let shop = Merchant::new("GB82WEST12345698765432".to_string());
+        let other = Merchant::new("FR1420041010050500013M02606".to_string());
+
+        let first = owner.delegate(parent.verifying_key(), Allowance::new(10_000, Currency::EUR, EXPIRY))?;
+        let second = parent.delegate(
+            child.verifying_key(),
+            Allowance::new(500, Currency::EUR, EXPIRY).with_merchants(vec![*shop.verifying_key()]),
+        )?;
+        let chain = vec![first.clone(), second.clone()];
+
+        child.sign_delegated(transaction(&owner, &shop, 500, 0), chain.clone())?.verify()?;
+        assert_eq!(
+            child.sign_delegated(transaction(&owner, &other, 500, 0), chain),
+            Err(Error::DelegationOutOfScope)
+        );
+
+        // a chain has to start at the owner and be unbroken
+        assert_eq!(
+            child.sign_delegated(transaction(&owner, &shop, 500, 0), vec![second]),
+            Err(Error::DelegationChainBroken)
+        );
+        assert_eq!(
+            child.sign_delegated(transaction(&owner, &shop, 500, 0), vec![first]),
+            Err(Error::WrongSigner)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use p256::ecdsa::{SigningKey, VerifyingKey};
+use p256::elliptic_curve::PrimeField;
+use p256::{FieldBytes, NonZeroScalar, Scalar};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::crypto::PublicKey;
+use crate::Error;
+
+/// Marks a child index as hardened, so its key can't be derived from the parent's public key.
+pub const HARDENED: u32 = 1 << 31;
+
+/// The first index of every path this crate derives keys for.
+pub const PURPOSE: u32 = 7_378;
+
+// the key of the master key derivation, as defined by SLIP-0010 for P-256
+const CURVE_SEED: &[u8] = b"Nist256p1 seed";
+
+/// A word list encoding random entropy and its checksum, from which keys are derived.
+///
+/// The words are wiped from memory on drop and never show up in `Debug` output.
+#[derive(Clone)]
+pub struct Mnemonic(bip39::Mnemonic);
+
+/// A path of child indexes from the master key down to a derived key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DerivationPath(Vec<u32>);
+
+/// A signing key along with the chain code needed to derive its children.
+///
+/// Both are wiped from memory on drop.
+#[derive(Clone)]
+pub struct ExtendedKey {
+    signing_key: SigningKey,
+    chain_code: [u8; 32],
+}
+
+impl Mnemonic {
+    /// Generates a new mnemonic of `word_count` words, which must be 12, 15, 18, 21 or 24.
+    pub fn generate(word_count: usize) -> Result<Self, Error> {
+        Self::from_rng(word_count, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Generates a new mnemonic of `word_count` words from the entropy of `rng`.
+    pub fn from_rng(word_count: usize, rng: &mut impl CryptoRngCore) -> Result<Self, Error> {
+        if !word_count.is_multiple_of(3) || !(12..=24).contains(&word_count) {
+            return Err(Error::InvalidMnemonic);
+        }
+
+        let mut entropy = Zeroizing::new(vec![0; word_count / 3 * 4]);
+        rng.fill_bytes(&mut entropy);
+        Self::from_entropy(&entropy)
+    }
+
+    /// Encodes 16 to 32 bytes of entropy, in steps of four bytes.
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, Error> {
+        bip39::Mnemonic::from_entropy(entropy).map(Self).map_err(|_| Error::InvalidMnemonic)
+    }
+
+    /// Decodes a mnemonic, failing on unknown words or a wrong checksum.
+    pub fn parse(phrase: &str) -> Result<Self, Error> {
+        bip39::Mnemonic::parse_normalized(phrase).map(Self).map_err(|_| Error::InvalidMnemonic)
+    }
+
+    pub fn entropy(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(self.0.to_entropy())
+    }
+
+    /// Stretches the mnemonic into the seed keys are derived from.
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        Zeroizing::new(self.0.to_seed_normalized(passphrase))
+    }
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Mnemonic {
+    fn eq(&self, other: &Self) -> bool {
+        // differing lengths reveal nothing beyond the word count
+        self.entropy().ct_eq(&other.entropy()).into()
+    }
+}
+
+impl Eq for Mnemonic {}
+
+/// Writes out the words, which is all it takes to steal the keys derived from them.
+impl Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl DerivationPath {
+    pub fn new(indexes: Vec<u32>) -> Self {
+        Self(indexes)
+    }
+
+    /// Returns the path of the key `device` of `account` signs with, `m/7378'/account'/0'/device'`.
+    pub fn device(account: u32, device: u32) -> Result<Self, Error> {
+        Ok(Self(vec![PURPOSE | HARDENED, hardened(account)?, HARDENED, hardened(device)?]))
+    }
+
+    /// Returns the path of the key `account` uses towards one merchant,
+    /// `m/7378'/account'/1'/index'` with the index taken from the hash of
+    /// the merchant's key, so every merchant sees a different key.
+    pub fn merchant(account: u32, merchant: &PublicKey) -> Result<Self, Error> {
+        let hash = Sha256::digest(merchant.to_bytes());
+        let index = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) & !HARDENED;
+
+        Ok(Self(vec![PURPOSE | HARDENED, hardened(account)?, 1 | HARDENED, index | HARDENED]))
+    }
+
+    pub fn indexes(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+fn hardened(index: u32) -> Result<u32, Error> {
+    if index >= HARDENED {
+        return Err(Error::InvalidDerivationPath);
+    }
+
+    Ok(index | HARDENED)
+}
+
+impl FromStr for DerivationPath {
+    type Err = Error;
+
+    /// Parses paths like `m/7378'/0'/1H/2`.
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            return Err(Error::InvalidDerivationPath);
+        }
+
+        parts
+            .map(|part| match part.strip_suffix(['\'', 'H']) {
+                Some(index) => index.parse().map_err(|_| Error::InvalidDerivationPath).and_then(hardened),
+                None => part.parse::<u32>().ok().filter(|index| *index < HARDENED).ok_or(Error::InvalidDerivationPath),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            match index & HARDENED {
+                0 => write!(f, "/{}", index)?,
+                _ => write!(f, "/{}'", index & !HARDENED)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ExtendedKey {
+    /// Derives the master key from a seed, following SLIP-0010.
+    pub fn master(seed: &[u8]) -> Self {
+        let mut digest = hmac_sha512(CURVE_SEED, &[seed]);
+        loop {
+            let (key, chain_code) = digest.split_at(32);
+            if let Some(key) = Option::<NonZeroScalar>::from(NonZeroScalar::from_repr(*FieldBytes::from_slice(key))) {
+                return Self { signing_key: SigningKey::from(key), chain_code: chain_code.try_into().expect("32 bytes") };
+            }
+
+            digest = hmac_sha512(CURVE_SEED, &[&*digest]);
+        }
+    }
+
+    /// Derives the child key at `index`, which is hardened if it is at least [`HARDENED`].
+    pub fn child(&self, index: u32) -> Self {
+        let parent = self.signing_key.as_nonzero_scalar();
+        let mut digest = if index & HARDENED != 0 {
+            let key = Zeroizing::new(self.signing_key.to_bytes());
+            hmac_sha512(&self.chain_code, &[&[0], &key, &index.to_be_bytes()])
+        } else {
+            let public = self.verifying_key().to_encoded_point(true);
+            hmac_sha512(&self.chain_code, &[public.as_bytes(), &index.to_be_bytes()])
+        };
+
+        loop {
+            let (tweak, chain_code) = digest.split_at(32);
+            let child = Option::<Scalar>::from(Scalar::from_repr(*FieldBytes::from_slice(tweak)))
+                .and_then(|tweak| Option::<NonZeroScalar>::from(NonZeroScalar::new(tweak + parent.as_ref())));
+            if let Some(child) = child {
+                return Self { signing_key: SigningKey::from(child), chain_code: chain_code.try_into().expect("32 bytes") };
+            }
+
+            digest = hmac_sha512(&self.chain_code, &[&[1], chain_code, &index.to_be_bytes()]);
+        }
+    }
+
+    /// Derives the key at the end of `path`.
+    pub fn derive(&self, path: &DerivationPath) -> Self {
+        path.0.iter().fold(self.clone(), |key, index| key.child(*index))
+    }
+
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.signing_key
+    }
+
+    pub fn verifying_key(&self) -> &VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+}
+
+impl Drop for ExtendedKey {
+    fn drop(&mut self) {
+        // the signing key wipes itself
+        self.chain_code.zeroize();
+    }
+}
+
+fn hmac_sha512(key: &[u8], data: &[&[u8]]) -> Zeroizing<[u8; 64]> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    for part in data {
+        mac.update(part);
+    }
+    Zeroizing::new(mac.finalize().into_bytes().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::user::User;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn mnemonic_vector() -> Result<(), Error> {
+        let mnemonic = Mnemonic::from_entropy(&[0; 16])?;
+        assert_eq!(
+            mnemonic.to_string(),
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+        );
+        assert_eq!(
+            hex(&*mnemonic.to_seed("TREZOR")),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        assert_eq!(Mnemonic::parse(&mnemonic.to_string())?, mnemonic);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mnemonic_checksum() -> Result<(), Error> {
+        assert_eq!(
+            Mnemonic::parse("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon"),
+            Err(Error::InvalidMnemonic)
+        );
+        assert_eq!(Mnemonic::generate(13), Err(Error::InvalidMnemonic));
+
+        let mnemonic = Mnemonic::generate(24)?;
+        assert_eq!(mnemonic.entropy().len(), 32);
+        assert_eq!(Mnemonic::parse(&mnemonic.to_string())?, mnemonic);
+
+        Ok(())
+    }
+
+    // test vector 1 for nist256p1 from SLIP-0010
+    #[test]
+    fn derivation_vector() -> Result<(), Error> {
+        let seed = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f];
+        let master = ExtendedKey::master(&seed);
+        assert_eq!(hex(master.chain_code()), "beeb672fe4621673f722f38529c07392fecaa61015c80c34f29ce8b41b3cb6ea");
+        assert_eq!(hex(&master.signing_key().to_bytes()), "612091aaa12e22dd2abef664f8a01a82cae99ad7441b7ef8110424915c268bc2");
+
+        let child = master.derive(&"m/0'".parse()?);
+        assert_eq!(hex(child.chain_code()), "3460cea53e6a6bb5fb391eeef3237ffd8724bf0a40e94943c98b83825342ee11");
+        assert_eq!(hex(&child.signing_key().to_bytes()), "6939694369114c67917a182c59ddb8cafc3004e63ca5d3b84403ba8613debc0c");
+        assert_eq!(
+            hex(child.verifying_key().to_encoded_point(true).as_bytes()),
+            "0384610f5ecffe8fda089363a41f56a5c7ffc1d81b59a612d0d649b2d22355590c"
+        );
+
+        let grandchild = master.derive(&"m/0H/1".parse()?);
+        assert_eq!(hex(grandchild.chain_code()), "4187afff1aafa8445010097fb99d23aee9f599450c7bd140b6826ac22ba21d0c");
+        assert_eq!(hex(&grandchild.signing_key().to_bytes()), "284e9d38d07d21e4e281b645089a94f4cf5a5a81369acf151a1c3a57f18b2129");
+
+        Ok(())
+    }
+
+    #[test]
+    fn key_per_merchant() -> Result<(), Error> {
+        let seed = Mnemonic::generate(12)?.to_seed("");
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        let other = Merchant::new("FR1420041010050500013M02606".to_string());
+
+        let path = DerivationPath::merchant(0, merchant.verifying_key())?;
+        let user = User::from_seed("DE89370400440532013000".parse()?, &*seed, &path);
+        let restored = User::from_seed("DE89370400440532013000".parse()?, &*seed, &path);
+        assert_eq!(user.verifying_key(), restored.verifying_key());
+
+        let path = DerivationPath::merchant(0, other.verifying_key())?;
+        let unlinked = User::from_seed("DE89370400440532013000".parse()?, &*seed, &path);
+        assert_ne!(user.verifying_key(), unlinked.verifying_key());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mnemonic_debug_hides_words() -> Result<(), Error> {
+        let mnemonic = Mnemonic::from_entropy(&[0; 16])?;
+        let debug = format!("{:?}", mnemonic);
+        assert!(!debug.contains("abandon"));
+        assert!(!debug.contains("about"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn paths() -> Result<(), Error> {
+        let path: DerivationPath = "m/7378'/0'/0'/3'".parse()?;
+        assert_eq!(path, DerivationPath::device(0, 3)?);
+        assert_eq!(path.to_string(), "m/7378'/0'/0'/3'");
+        assert_eq!("m/1/2H".parse::<DerivationPath>()?.indexes(), [1, 2 | HARDENED]);
+
+        for invalid in ["", "0/1", "m/x", "m/2147483648", "m/2147483648'"] {
+            assert_eq!(invalid.parse::<DerivationPath>(), Err(Error::InvalidDerivationPath));
+        }
+        assert_eq!(DerivationPath::device(HARDENED, 0), Err(Error::InvalidDerivationPath));
+
+        Ok(())
+    }
+}
@@ -1,20 +1,447 @@
-use p256::ecdsa::{SigningKey, VerifyingKey};
+mod delegation;
+mod hd;
+mod offline;
+mod policy;
+mod recovery;
+mod rotation;
+
+pub use delegation::{Allowance, DelegationCertificate, DelegationId};
+pub(crate) use delegation::verify_chain;
+pub use hd::{DerivationPath, ExtendedKey, Mnemonic, HARDENED, PURPOSE};
+pub use offline::OfflineWallet;
+pub use policy::{Rule, SpendingPolicy, MAX_CLOCK_SKEW};
+pub use recovery::{GuardianAppointment, Guardians, RecoveryCancellation, RecoveryId, RecoveryRequest};
+pub use rotation::{KeyAuthority, KeyRevocation, KeyRotation};
+
 use rand::SeedableRng;
 use rand_chacha::ChaCha20Rng;
+use rand_core::CryptoRngCore;
 
-#[derive(Debug, PartialEq, Eq)]
+use crate::bank::{
+    dispute_id, Decision, DisputeId, DisputeStep, EscrowApproval, EscrowId, LiabilityProof, LiabilityStatement,
+    PartiallySignedTransaction, ReasonCode, SettlementLeaf, SettlementProof, SignedDisputeStep,
+};
+use crate::crypto::{PublicKey, Signature};
+use crate::sepa::{RefundClaim, SignedDirectDebit};
+use crate::signer::{SharedSigner, Signer, SoftwareSigner};
+use crate::token::{PendingToken, TokenKey, Withdrawal};
+use crate::traits::{ToBytes, TransactionSign};
+use crate::transaction::{Currency, Mandate, MandateRevocation, SignedMandate, SignedTransaction, Transaction};
+use crate::{Error, Timestamp, IBAN};
+use policy::{SharedPolicy, Spend};
+
+/// A user of the bank.
+///
+/// Equality compares public data and whether clones share the same signer,
+/// never the secret key itself, and `Debug` output holds no key material.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct User {
-    account_number: String,
-    signing_key: SigningKey,
-    verifying_key: VerifyingKey,
+    account_number: IBAN,
+    // holds the key, empty for users only known by their public key
+    signer: Option<SharedSigner>,
+    verifying_key: PublicKey,
+    // consulted before signing any transaction
+    policy: Option<SharedPolicy>,
 }
 
 impl User {
-    pub fn new(account_number: String) -> Self {
-        let mut rng = ChaCha20Rng::from_entropy();
-        let signing_key = SigningKey::random(&mut rng);
-        let verifying_key = VerifyingKey::from(&signing_key);
+    pub fn new(account_number: IBAN) -> Self {
+        Self::with_signer(account_number, SoftwareSigner::random())
+    }
+
+    /// Creates a user with a key drawn from `rng`, e.g. a seeded one for reproducible tests.
+    pub fn from_rng(account_number: IBAN, rng: &mut impl CryptoRngCore) -> Self {
+        Self::with_signer(account_number, SoftwareSigner::from_rng(rng))
+    }
+
+    /// Creates a user that leaves signing to `signer`, e.g. an HSM or a signing daemon.
+    pub fn with_signer(account_number: IBAN, signer: impl Signer + 'static) -> Self {
+        let signer = SharedSigner::new(signer);
+        let verifying_key = signer.verifying_key();
+
+        User { account_number, signer: Some(signer), verifying_key, policy: None }
+    }
+
+    /// Creates a user signing with the key derived from `seed` along `path`,
+    /// so the same keys can be restored from a mnemonic backup.
+    pub fn from_seed(account_number: IBAN, seed: &[u8], path: &DerivationPath) -> Self {
+        let signing_key = ExtendedKey::master(seed).derive(path).signing_key().clone();
+        Self::with_signer(account_number, SoftwareSigner::new(signing_key))
+    }
+
+    /// Creates a user that only knows its public key, as seen by banks and merchants.
+    pub fn from_verifying_key(account_number: IBAN, verifying_key: PublicKey) -> Self {
+        User { account_number, signer: None, verifying_key, policy: None }
+    }
+
+    /// Creates a user from a public key encoded by [`PublicKey::to_bytes`].
+    pub fn from_public_bytes(account_number: IBAN, bytes: &[u8]) -> Result<Self, Error> {
+        let verifying_key = PublicKey::from_bytes(bytes)?;
+
+        Ok(Self::from_verifying_key(account_number, verifying_key))
+    }
+
+    /// Returns a copy of `self` without the signing key.
+    pub fn to_public(&self) -> Self {
+        Self::from_verifying_key(self.account_number, self.verifying_key)
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn verifying_key(&self) -> &PublicKey {
+        &self.verifying_key
+    }
+
+    /// Sets the policy every transaction, cosignature and token withdrawal
+    /// has to follow before it is signed.
+    ///
+    /// Clones made afterwards share the policy, including what has been spent.
+    pub fn set_policy(&mut self, policy: SpendingPolicy) {
+        self.policy = Some(SharedPolicy::new(policy));
+    }
+
+    /// Returns a snapshot of the current spending policy, if there is one.
+    pub fn policy(&self) -> Option<SpendingPolicy> {
+        self.policy.as_ref().map(|policy| policy.lock().clone())
+    }
+
+    /// Tells the spending policy the current time, see [`SpendingPolicy::advance`].
+    pub fn advance_clock(&self, now: Timestamp) {
+        if let Some(policy) = &self.policy {
+            policy.lock().advance(now);
+        }
+    }
+
+    /// Signs a mandate allowing its merchant to pull payments from this user.
+    pub fn sign_mandate(&self, mandate: Mandate) -> Result<SignedMandate, Error> {
+        if mandate.user().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let signature = self.sign_bytes(&mandate.as_bytes())?;
+        Ok(SignedMandate::new(mandate, signature))
+    }
+
+    /// Revokes a mandate previously signed by this user, effective from `revoked_at`.
+    pub fn revoke_mandate(&self, mandate: &SignedMandate, revoked_at: Timestamp) -> Result<MandateRevocation, Error> {
+        if mandate.mandate().user().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let mandate_id = mandate.id();
+        let signature = self.sign_bytes(&MandateRevocation::signed_bytes(&mandate_id, revoked_at))?;
+        Ok(MandateRevocation::new(mandate_id, revoked_at, signature))
+    }
+
+    /// Claims a refund of a direct debit collected from this user.
+    pub fn claim_refund(&self, debit: &SignedDirectDebit) -> Result<RefundClaim, Error> {
+        if debit.debit().debtor().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let signature = self.sign_bytes(&RefundClaim::signed_bytes(&debit.id()))?;
+        Ok(RefundClaim::new(debit.id(), signature))
+    }
+
+    /// Asks the bank for a token of `key`'s denomination, paid from this user's account.
+    ///
+    /// Returns the token to finish with the bank's blind signature, along with
+    /// the signed withdrawal to hand to the bank.
+    pub fn withdraw_token(&self, key: &TokenKey) -> Result<(PendingToken, Withdrawal), Error> {
+        self.withdraw_token_from_rng(key, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`User::withdraw_token`], drawing the serial and blinding factor from `rng`.
+    pub fn withdraw_token_from_rng(&self, key: &TokenKey, rng: &mut impl CryptoRngCore) -> Result<(PendingToken, Withdrawal), Error> {
+        let (pending, blinded) = PendingToken::new(key, rng);
+        let user = self.to_public();
+        let signature = self.sign_checked(&Spend::token(key.denomination()), &Withdrawal::signed_bytes(&user, key, &blinded))?;
+
+        Ok((pending, Withdrawal::new(user, key, blinded, signature)))
+    }
+
+    /// Checks, without asking the bank, that a transaction of this user is
+    /// part of a settlement signed by `bank_key`.
+    pub fn verify_settled(&self, signed: &SignedTransaction, proof: &SettlementProof, bank_key: &PublicKey) -> Result<(), Error> {
+        if *proof.leaf() != (SettlementLeaf::Transaction { id: signed.id(), account: self.account_number }) {
+            return Err(Error::InvalidProof);
+        }
+
+        proof.verify(bank_key)
+    }
+
+    /// Checks, without asking the bank, that a settlement signed by
+    /// `bank_key` holds a balance of this user's account in `currency`,
+    /// and returns it.
+    pub fn verify_balance(&self, currency: Currency, proof: &SettlementProof, bank_key: &PublicKey) -> Result<u64, Error> {
+        match *proof.leaf() {
+            SettlementLeaf::Balance { account, currency: settled, balance, .. } if account == self.account_number && settled == currency => {
+                proof.verify(bank_key)?;
+                Ok(balance)
+            }
+            _ => Err(Error::InvalidProof),
+        }
+    }
+
+    /// Checks, without asking the bank, that the balance of this user's
+    /// account is counted in a statement of liabilities signed by
+    /// `bank_key`, and returns it.
+    pub fn verify_liabilities(
+        &self,
+        statement: &LiabilityStatement,
+        proof: &LiabilityProof,
+        bank_key: &PublicKey,
+    ) -> Result<u64, Error> {
+        if *proof.account() != self.account_number {
+            return Err(Error::InvalidProof);
+        }
+
+        statement.verify(bank_key)?;
+        proof.verify(statement)?;
+        Ok(proof.balance())
+    }
+
+    /// Signs an approval of `decision` for the escrow with id `id`.
+    pub fn approve_escrow(&self, id: &EscrowId, decision: Decision) -> Result<EscrowApproval, Error> {
+        let signature = self.sign_bytes(&EscrowApproval::signed_bytes(id, decision))?;
+        Ok(EscrowApproval::new(*id, decision, self.verifying_key, signature))
+    }
+
+    /// Opens a dispute over `amount` of the leg at `leg` of a transaction this user signed.
+    pub fn open_dispute(
+        &self,
+        signed: &SignedTransaction,
+        leg: usize,
+        amount: u64,
+        reason: ReasonCode,
+        at: Timestamp,
+    ) -> Result<SignedDisputeStep, Error> {
+        if signed.transaction().user().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let step = DisputeStep::Open { transaction_id: signed.id(), leg, amount, reason };
+        self.respond_to_dispute(&dispute_id(&signed.id(), leg), step, at)
+    }
+
+    /// Allows `delegate` to sign transactions on behalf of this user, or on
+    /// behalf of whoever delegated to this user, within `allowance`.
+    pub fn delegate(&self, delegate: &PublicKey, allowance: Allowance) -> Result<DelegationCertificate, Error> {
+        let signature = self.sign_bytes(&DelegationCertificate::signed_bytes(&self.verifying_key, delegate, &allowance))?;
+        Ok(DelegationCertificate::new(self.verifying_key, *delegate, allowance, signature))
+    }
+
+    /// Signs a transaction of another user, authorized through a chain of
+    /// delegations leading from that user to this one.
+    pub fn sign_delegated(&self, transaction: Transaction, chain: Vec<DelegationCertificate>) -> Result<SignedTransaction, Error> {
+        if verify_chain(&transaction, &chain)? != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let signed = self.sign_transaction(transaction)?;
+        Ok(signed.with_delegation(chain))
+    }
+
+    /// Creates a new key for this user's account, signed over by the current one.
+    ///
+    /// Returns the user with the new key along with the rotation record.
+    pub fn rotate_key(&self, effective_at: Timestamp) -> Result<(User, KeyRotation), Error> {
+        self.rotate_key_from_rng(effective_at, &mut ChaCha20Rng::from_entropy())
+    }
+
+    /// Like [`User::rotate_key`], drawing the new key from `rng`.
+    pub fn rotate_key_from_rng(&self, effective_at: Timestamp, rng: &mut impl CryptoRngCore) -> Result<(User, KeyRotation), Error> {
+        let rotated = User { policy: self.policy.clone(), ..User::from_rng(self.account_number, rng) };
+
+        let bytes = KeyRotation::signed_bytes(
+            &self.account_number,
+            &self.verifying_key,
+            &rotated.verifying_key,
+            effective_at,
+            KeyAuthority::Own,
+        );
+        let signature = self.sign_bytes(&bytes)?;
+        let rotation = KeyRotation::new(
+            self.account_number,
+            self.verifying_key,
+            rotated.verifying_key,
+            effective_at,
+            KeyAuthority::Own,
+            signature,
+        );
+
+        Ok((rotated, rotation))
+    }
+
+    /// Uses this user's key as the recovery key of the account to replace `old_key` by `new_key`.
+    pub fn recover_key(&self, old_key: &PublicKey, new_key: &PublicKey, effective_at: Timestamp) -> Result<KeyRotation, Error> {
+        let bytes = KeyRotation::signed_bytes(&self.account_number, old_key, new_key, effective_at, KeyAuthority::Recovery);
+        let signature = self.sign_bytes(&bytes)?;
+
+        Ok(KeyRotation::new(self.account_number, *old_key, *new_key, effective_at, KeyAuthority::Recovery, signature))
+    }
+
+    /// Revokes `key` of the account `account_number`, either this user's own
+    /// key or one this user is the recovery key for.
+    pub fn revoke_key(&self, account_number: &IBAN, key: &PublicKey, revoked_at: Timestamp) -> Result<KeyRevocation, Error> {
+        let authority = if *key == self.verifying_key { KeyAuthority::Own } else { KeyAuthority::Recovery };
+        let signature = self.sign_bytes(&KeyRevocation::signed_bytes(account_number, key, revoked_at, authority))?;
+
+        Ok(KeyRevocation::new(*account_number, *key, revoked_at, authority, signature))
+    }
+
+    /// Appoints guardians that may jointly replace this user's key if it is lost.
+    pub fn appoint_guardians(&self, guardians: Guardians) -> Result<GuardianAppointment, Error> {
+        let signature = self.sign_bytes(&GuardianAppointment::signed_bytes(&self.account_number, &guardians))?;
+        Ok(GuardianAppointment::new(self.account_number, guardians, signature))
+    }
+
+    /// Approves a recovery request as one of the account's guardians.
+    pub fn approve_recovery(&self, request: &mut RecoveryRequest) -> Result<(), Error> {
+        let signature = self.sign_bytes(&request.signed_bytes())?;
+        request.add_approval(self.verifying_key, signature)
+    }
+
+    /// Cancels a recovery that would replace this user's key.
+    pub fn cancel_recovery(&self, request: &RecoveryRequest) -> Result<RecoveryCancellation, Error> {
+        if request.old_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        let signature = self.sign_bytes(&RecoveryCancellation::signed_bytes(&request.id()))?;
+        Ok(RecoveryCancellation::new(request.id(), signature))
+    }
+
+    /// Adds this user's signature to a transaction that needs several approvals.
+    pub fn cosign(&self, partial: &mut PartiallySignedTransaction) -> Result<(), Error> {
+        let transaction = partial.transaction();
+        let signature = self.sign_checked(&Spend::from(transaction), &transaction.as_bytes())?;
+        partial.add_signature(self.verifying_key, signature)
+    }
+
+    /// Signs a step of the dispute with id `id`.
+    pub fn respond_to_dispute(&self, id: &DisputeId, step: DisputeStep, at: Timestamp) -> Result<SignedDisputeStep, Error> {
+        let signature = self.sign_bytes(&SignedDisputeStep::signed_bytes(id, &step, at))?;
+        Ok(SignedDisputeStep::new(*id, step, at, self.verifying_key, signature))
+    }
+
+    /// Signs a transaction after consulting the spending policy.
+    fn sign_transaction(&self, transaction: Transaction) -> Result<SignedTransaction, Error> {
+        let signature = self.sign_checked(&Spend::from(&transaction), &transaction.as_bytes())?;
+        Ok(SignedTransaction::new(transaction, signature))
+    }
+
+    /// Signs `message`, which spends `spend`, after consulting the spending policy.
+    fn sign_checked(&self, spend: &Spend, message: &[u8]) -> Result<Signature, Error> {
+        // hold the lock until the spend is recorded, so concurrent
+        // signatures can't both pass a rolling limit
+        let mut policy = self.policy.as_ref().map(|policy| policy.lock());
+        if let Some(policy) = &policy {
+            policy.check_spend(spend)?;
+        }
+
+        let signature = self.sign_bytes(message)?;
+        if let Some(policy) = &mut policy {
+            policy.record_spend(spend);
+        }
+
+        Ok(signature)
+    }
+
+    fn sign_bytes(&self, message: &[u8]) -> Result<Signature, Error> {
+        self.signer.as_ref().ok_or(Error::NoPrivateKey)?.sign(message)
+    }
+}
+
+impl TransactionSign for User {
+    fn sign(&self, transaction: Transaction) -> Result<SignedTransaction, Error> {
+        if transaction.user().verifying_key() != self.verifying_key() {
+            return Err(Error::WrongSigner);
+        }
+
+        self.sign_transaction(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::SigningKey;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::merchant::Merchant;
+    use crate::transaction::Currency;
 
-        User { account_number, signing_key, verifying_key }
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn debug_hides_key() -> Result<(), Error> {
+        let signing_key = SigningKey::from_slice(&[7; 32]).expect("valid scalar");
+        let secret = signing_key.to_bytes();
+        let user = User::with_signer("DE89370400440532013000".parse()?, SoftwareSigner::new(signing_key));
+
+        for debug in [format!("{:?}", user), format!("{:#?}", user), format!("{:?}", user.signer)] {
+            assert!(!debug.contains(&hex(&secret)));
+            assert!(!debug.contains(&hex(&secret).to_uppercase()));
+            assert!(!debug.contains(&format!("{:?}", secret.as_slice())));
+            assert!(!debug.contains("7, 7, 7, 7"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn seeded_rotation() -> Result<(), Error> {
+        let user = User::from_rng("DE89370400440532013000".parse()?, &mut ChaCha20Rng::seed_from_u64(0));
+        let (first, rotation) = user.rotate_key_from_rng(100, &mut ChaCha20Rng::seed_from_u64(1))?;
+        let (second, _) = user.rotate_key_from_rng(100, &mut ChaCha20Rng::seed_from_u64(1))?;
+
+        assert_eq!(first.verifying_key(), second.verifying_key());
+        assert_ne!(first.verifying_key(), user.verifying_key());
+        assert_eq!(rotation.new_key(), first.verifying_key());
+        rotation.verify(None)
+    }
+
+    // keys drawn from ChaCha20 seeded with 0, user first, and the signature
+    // over a payment of 1€ at time 0
+    #[test]
+    fn known_answers() -> Result<(), Error> {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let user = User::from_rng("DE89370400440532013000".parse()?, &mut rng);
+        let merchant = Merchant::from_rng("GB82WEST12345698765432".to_string(), &mut rng);
+        let signed = user.sign(Transaction::new(1_000, Currency::EUR, merchant.clone(), user.clone(), 0))?;
+
+        assert_eq!(
+            hex(&user.verifying_key().to_bytes()),
+            "010322c13a424fde060ed518b1fff7b84957728c510bbe6f4fda8f3ad68c807c6174"
+        );
+        assert_eq!(
+            hex(&merchant.verifying_key().to_bytes()),
+            "010363c9a8aec06d261b93c23176fbdaac6428e4441936835c628092de9f17d75689"
+        );
+        assert_eq!(
+            hex(&signed.signature().to_bytes()),
+            "01f530d03bfa046cac13409d42230ec9d398255379fbce2a7eae48de546d238fc8\
+             ed0be023eab829a1fcedfc61f7f778f6236719a3a208479d697fea62277313e5"
+        );
+
+        // signing again gives the same signature
+        let again = user.sign(Transaction::new(1_000, Currency::EUR, merchant, user.clone(), 0))?;
+        assert_eq!(again.signature(), signed.signature());
+
+        Ok(())
+    }
+
+    #[test]
+    fn equality_ignores_key() -> Result<(), Error> {
+        let user = User::new("DE89370400440532013000".parse()?);
+        assert_eq!(user, user.clone());
+        assert_ne!(user, user.to_public());
+        assert_eq!(user.to_public(), user.clone().to_public());
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use super::User;
+use crate::traits::TransactionSign;
+use crate::transaction::{OfflineAllowance, OfflinePayment, Transaction};
+use crate::Error;
+
+/// Pays against an offline allowance on the user's device, numbering the
+/// payments and keeping track of what is left of the allowance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfflineWallet {
+    user: User,
+    allowance: OfflineAllowance,
+    next_counter: u64,
+    spent: u64,
+}
+
+impl OfflineWallet {
+    /// Creates a wallet for an allowance the bank issued to `user`.
+    pub fn new(user: User, allowance: OfflineAllowance) -> Result<Self, Error> {
+        if allowance.user().verifying_key() != user.verifying_key() || allowance.user().account_number() != user.account_number() {
+            return Err(Error::AllowanceMismatch);
+        }
+
+        Ok(Self { user, allowance, next_counter: 0, spent: 0 })
+    }
+
+    pub fn allowance(&self) -> &OfflineAllowance {
+        &self.allowance
+    }
+
+    /// Returns how much may still be paid against the allowance.
+    pub fn remaining(&self) -> u64 {
+        self.allowance.limit() - self.spent
+    }
+
+    /// Signs a transaction against the allowance, refusing to go over its limit.
+    pub fn pay(&mut self, transaction: Transaction) -> Result<OfflinePayment, Error> {
+        // the device dates its own payments, so their date is its current time
+        self.allowance.check(&transaction, transaction.created_at())?;
+        if transaction.amount() > self.remaining() {
+            return Err(Error::AllowanceExceeded);
+        }
+
+        let signed = self.user.sign(transaction)?;
+        let message = OfflinePayment::signed_bytes(&self.allowance.id(), self.next_counter, &signed.id());
+        let signature = self.user.sign_bytes(&message)?;
+        let payment = OfflinePayment::new(self.allowance.clone(), signed, self.next_counter, signature);
+
+        self.next_counter += 1;
+        self.spent += payment.transaction().amount();
+        Ok(payment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bank::{BankKey, Ledger, OfflineBook};
+    use crate::merchant::Merchant;
+    use crate::transaction::Currency;
+
+    #[test]
+    fn pay() -> Result<(), Error> {
+        let mut book = OfflineBook::new(BankKey::new());
+        let mut ledger = Ledger::new();
+        let user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        ledger.keys_mut().enroll(&user, 0, None)?;
+        ledger.deposit(user.account_number(), Currency::EUR, 5_000)?;
+        let allowance = book.issue(&mut ledger, &user, Currency::EUR, 2_000, 100, 200)?;
+
+        let other = User::new(*user.account_number());
+        assert_eq!(OfflineWallet::new(other.clone(), allowance.clone()), Err(Error::AllowanceMismatch));
+        let mut wallet = OfflineWallet::new(user.clone(), allowance.clone())?;
+
+        let payment = wallet.pay(Transaction::new(1_500, Currency::EUR, merchant.clone(), user.clone(), 150).for_offline(&allowance))?;
+        assert_eq!((payment.counter(), wallet.remaining()), (0, 500));
+        let payment = wallet.pay(Transaction::new(500, Currency::EUR, merchant.clone(), user.clone(), 150).for_offline(&allowance))?;
+        assert_eq!((payment.counter(), wallet.remaining()), (1, 0));
+
+        let mut wallet = OfflineWallet::new(user.clone(), wallet.allowance().clone())?;
+        let pay = |wallet: &mut OfflineWallet, amount, currency, user: &User, at| {
+            wallet.pay(Transaction::new(amount, currency, merchant.clone(), user.clone(), at).for_offline(&allowance))
+        };
+        assert_eq!(pay(&mut wallet, 2_001, Currency::EUR, &user, 150), Err(Error::AllowanceExceeded));
+        assert_eq!(pay(&mut wallet, 100, Currency::USD, &user, 150), Err(Error::AllowanceMismatch));
+        assert_eq!(pay(&mut wallet, 100, Currency::EUR, &other, 150), Err(Error::AllowanceMismatch));
+        assert_eq!(pay(&mut wallet, 100, Currency::EUR, &user, 99), Err(Error::AllowanceExpired));
+        assert_eq!(pay(&mut wallet, 100, Currency::EUR, &user, 200), Err(Error::AllowanceExpired));
+        // a plain payment is not paid against the allowance
+        let plain = Transaction::new(100, Currency::EUR, merchant.clone(), user.clone(), 150);
+        assert_eq!(wallet.pay(plain), Err(Error::WrongPurpose));
+        assert_eq!(wallet.remaining(), 2_000);
+
+        Ok(())
+    }
+}
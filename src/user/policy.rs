@@ -0,0 +1,391 @@
+use std::sync::{Arc, Mutex};
+
+use crate::crypto::PublicKey;
+use crate::merchant::Merchant;
+use crate::time::DAY;
+use crate::token::Denomination;
+use crate::transaction::{Currency, Transaction};
+use crate::{Error, Timestamp};
+
+/// How far ahead of the time last given to a policy a transaction may be
+/// dated under a rolling limit.
+pub const MAX_CLOCK_SKEW: Timestamp = 5 * 60;
+
+/// A single restriction on what a user is willing to sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// No single transaction in `currency` may exceed `amount`.
+    MaxPerTransaction { currency: Currency, amount: u64 },
+    /// All transactions in `currency` signed within any `window` long period
+    /// may not exceed `amount` in total. Since the window is placed by the
+    /// transactions' own timestamps, none may be older than the newest one
+    /// in `currency` signed so far, nor dated more than [`MAX_CLOCK_SKEW`]
+    /// after the current time given to the policy.
+    RollingLimit { currency: Currency, amount: u64, window: Timestamp },
+    /// Only these merchants may be paid. Tokens, which can pay anyone, may
+    /// not be withdrawn.
+    AllowMerchants(Vec<PublicKey>),
+    /// These merchants may not be paid, nor tokens withdrawn.
+    DenyMerchants(Vec<PublicKey>),
+    /// Only merchants with one of these category codes may be paid, and no
+    /// tokens withdrawn.
+    AllowCategories(Vec<u16>),
+    /// Merchants with these category codes may not be paid, nor tokens
+    /// withdrawn.
+    DenyCategories(Vec<u16>),
+    /// Transactions may only be made between these seconds of the day, in
+    /// UTC. If `from` is greater than `to`, the window spans midnight.
+    TimeOfDay { from: Timestamp, to: Timestamp },
+}
+
+impl Rule {
+    /// Limits the total spent in `currency` within any 24 hours.
+    pub fn daily_limit(currency: Currency, amount: u64) -> Self {
+        Self::RollingLimit { currency, amount, window: DAY }
+    }
+
+    /// Limits the total spent in `currency` within any 30 days.
+    pub fn monthly_limit(currency: Currency, amount: u64) -> Self {
+        Self::RollingLimit { currency, amount, window: 30 * DAY }
+    }
+}
+
+/// What a signature would spend, as far as the rules are concerned.
+#[derive(Debug)]
+pub(crate) struct Spend<'a> {
+    currency: Currency,
+    amount: u64,
+    // the policy's current time if not dated by the signer
+    at: Option<Timestamp>,
+    // empty for bearer tokens, which can pay anyone
+    merchants: Vec<&'a Merchant>,
+}
+
+impl<'a> From<&'a Transaction> for Spend<'a> {
+    fn from(transaction: &'a Transaction) -> Self {
+        Self {
+            currency: transaction.currency(),
+            amount: transaction.amount(),
+            at: Some(transaction.created_at()),
+            merchants: transaction.legs().iter().map(|leg| leg.merchant()).collect(),
+        }
+    }
+}
+
+impl Spend<'static> {
+    /// Withdrawing a token of `denomination`.
+    pub(crate) fn token(denomination: Denomination) -> Self {
+        Self { currency: denomination.currency(), amount: denomination.amount(), at: None, merchants: Vec::new() }
+    }
+}
+
+/// The rules a user's transactions have to follow, along with what the user
+/// has signed so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingPolicy {
+    rules: Vec<Rule>,
+    // (created_at, currency, amount) of the transactions signed under the
+    // policy that may still count towards a rolling limit
+    history: Vec<(Timestamp, Currency, u64)>,
+    // the latest current time the caller gave
+    now: Timestamp,
+}
+
+impl SpendingPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: Rule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Sets the current time, which rolling limits judge how far ahead
+    /// transactions are dated by. The time never goes back.
+    pub fn advance(&mut self, now: Timestamp) {
+        self.now = self.now.max(now);
+    }
+
+    /// Checks a transaction against every rule, returning the first one it breaks.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), Error> {
+        self.check_spend(&Spend::from(transaction))
+    }
+
+    /// Remembers a signed transaction for rolling limits.
+    pub fn record(&mut self, transaction: &Transaction) {
+        self.record_spend(&Spend::from(transaction));
+    }
+
+    pub(crate) fn check_spend(&self, spend: &Spend) -> Result<(), Error> {
+        for rule in &self.rules {
+            self.check_rule(rule, spend)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remembers what was spent, and forgets what no rolling limit counts
+    /// anymore: a later transaction can't be dated before this one, so its
+    /// window can't reach further back than the longest window from here.
+    pub(crate) fn record_spend(&mut self, spend: &Spend) {
+        let at = spend.at.unwrap_or(self.now);
+        let longest = self.rules.iter()
+            .filter_map(|rule| match rule {
+                Rule::RollingLimit { currency, window, .. } if *currency == spend.currency => Some(*window),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        self.history.push((at, spend.currency, spend.amount));
+        self.history.retain(|(recorded, currency, _)| *currency != spend.currency || at.saturating_sub(*recorded) < longest);
+    }
+
+    fn check_rule(&self, rule: &Rule, spend: &Spend) -> Result<(), Error> {
+        let at = spend.at.unwrap_or(self.now);
+        let merchants = &spend.merchants;
+        // a token can pay any merchant, so no merchant rule lets it through
+        if merchants.is_empty() {
+            match rule {
+                Rule::AllowMerchants(_) | Rule::DenyMerchants(_) => return Err(Error::PolicyMerchantDenied),
+                Rule::AllowCategories(_) | Rule::DenyCategories(_) => return Err(Error::PolicyCategoryDenied),
+                _ => {}
+            }
+        }
+
+        match rule {
+            Rule::MaxPerTransaction { currency, amount } => {
+                if spend.currency == *currency && spend.amount > *amount {
+                    return Err(Error::PolicyAmountExceeded);
+                }
+            }
+            Rule::RollingLimit { currency, amount, window } => {
+                if spend.currency != *currency {
+                    return Ok(());
+                }
+
+                // postdating or backdating a transaction would move it out of the window
+                if at > self.now.saturating_add(MAX_CLOCK_SKEW) {
+                    return Err(Error::PolicyPostdated);
+                }
+                if self.history.iter().any(|(recorded, c, _)| c == currency && *recorded > at) {
+                    return Err(Error::PolicyBackdated);
+                }
+
+                let spent = self.history.iter()
+                    .filter(|(recorded, c, _)| c == currency && at - *recorded < *window)
+                    .try_fold(spend.amount, |total, (_, _, amount)| total.checked_add(*amount));
+                if spent.is_none_or(|spent| spent > *amount) {
+                    return Err(Error::PolicyLimitExceeded);
+                }
+            }
+            Rule::AllowMerchants(keys) => {
+                if !merchants.iter().all(|merchant| keys.contains(merchant.verifying_key())) {
+                    return Err(Error::PolicyMerchantDenied);
+                }
+            }
+            Rule::DenyMerchants(keys) => {
+                if merchants.iter().any(|merchant| keys.contains(merchant.verifying_key())) {
+                    return Err(Error::PolicyMerchantDenied);
+                }
+            }
+            Rule::AllowCategories(codes) => {
+                if !merchants.iter().all(|merchant| merchant.category_code().is_some_and(|code| codes.contains(&code))) {
+                    return Err(Error::PolicyCategoryDenied);
+                }
+            }
+            Rule::DenyCategories(codes) => {
+                if merchants.iter().any(|merchant| merchant.category_code().is_some_and(|code| codes.contains(&code))) {
+                    return Err(Error::PolicyCategoryDenied);
+                }
+            }
+            Rule::TimeOfDay { from, to } => {
+                let second = at % DAY;
+                let inside = if from <= to {
+                    *from <= second && second < *to
+                } else {
+                    second >= *from || second < *to
+                };
+                if !inside {
+                    return Err(Error::PolicyOutsideHours);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A policy shared between all clones of a user, so that every clone counts
+/// towards the same rolling limits.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedPolicy(Arc<Mutex<SpendingPolicy>>);
+
+impl SharedPolicy {
+    pub(crate) fn new(policy: SpendingPolicy) -> Self {
+        Self(Arc::new(Mutex::new(policy)))
+    }
+
+    pub(crate) fn lock(&self) -> std::sync::MutexGuard<'_, SpendingPolicy> {
+        // a panic while holding the lock can't leave the policy half updated
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl PartialEq for SharedPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedPolicy {}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    use super::*;
+    use crate::bank::PartiallySignedTransaction;
+    use crate::token::MintKey;
+    use crate::traits::TransactionSign;
+    use crate::user::User;
+
+    // 2024-03-01T10:00:00Z
+    const MORNING: Timestamp = 1_709_287_200;
+
+    fn pay(user: &User, merchant: &Merchant, amount: u64, at: Timestamp) -> Result<(), Error> {
+        user.sign(Transaction::new(amount, Currency::EUR, merchant.clone(), user.clone(), at)).map(|_| ())
+    }
+
+    #[test]
+    fn per_transaction_cap() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::MaxPerTransaction { currency: Currency::EUR, amount: 5_000 }));
+
+        pay(&user, &merchant, 5_000, MORNING)?;
+        assert_eq!(pay(&user, &merchant, 5_001, MORNING), Err(Error::PolicyAmountExceeded));
+
+        // other currencies are not affected
+        user.sign(Transaction::new(9_000, Currency::USD, merchant.clone(), user.clone(), MORNING))?;
+        Ok(())
+    }
+
+    #[test]
+    fn daily_limit() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::daily_limit(Currency::EUR, 10_000)));
+        user.advance_clock(MORNING);
+
+        pay(&user, &merchant, 6_000, MORNING)?;
+        // clones share the spending history
+        user.advance_clock(MORNING + 3_600);
+        assert_eq!(pay(&user.clone(), &merchant, 6_000, MORNING + 3_600), Err(Error::PolicyLimitExceeded));
+        pay(&user, &merchant, 4_000, MORNING + 3_600)?;
+
+        user.advance_clock(MORNING + DAY);
+        assert_eq!(pay(&user, &merchant, 1, MORNING + DAY - 1), Err(Error::PolicyLimitExceeded));
+        pay(&user, &merchant, 6_000, MORNING + DAY)?;
+
+        // what no window can reach anymore is forgotten
+        let history = user.policy().ok_or(Error::DevError)?.history;
+        assert_eq!(history, [(MORNING + 3_600, Currency::EUR, 4_000), (MORNING + DAY, Currency::EUR, 6_000)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn backdated() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::daily_limit(Currency::EUR, 10_000)));
+        user.advance_clock(MORNING);
+
+        pay(&user, &merchant, 10_000, MORNING)?;
+        assert_eq!(pay(&user, &merchant, 10_000, MORNING - DAY), Err(Error::PolicyBackdated));
+        // nor can the window be moved ahead of the current time
+        assert_eq!(pay(&user, &merchant, 10_000, MORNING + DAY), Err(Error::PolicyPostdated));
+        assert_eq!(pay(&user, &merchant, 1, MORNING + MAX_CLOCK_SKEW), Err(Error::PolicyLimitExceeded));
+
+        user.advance_clock(MORNING + DAY);
+        pay(&user, &merchant, 10_000, MORNING + DAY)?;
+        assert_eq!(pay(&user, &merchant, 1, MORNING + DAY - 1), Err(Error::PolicyBackdated));
+
+        // transactions in other currencies don't count as later ones
+        user.sign(Transaction::new(1_000, Currency::USD, merchant.clone(), user.clone(), MORNING + 3 * DAY))?;
+        user.advance_clock(MORNING + 2 * DAY);
+        pay(&user, &merchant, 10_000, MORNING + 2 * DAY)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn cosignatures_and_tokens() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::daily_limit(Currency::EUR, 10_000)));
+        user.advance_clock(MORNING);
+
+        let mut partial = PartiallySignedTransaction::new(Transaction::new(6_000, Currency::EUR, merchant.clone(), user.clone(), MORNING));
+        user.cosign(&mut partial)?;
+
+        // a token withdrawal counts towards the limit, and is dated by the policy's clock
+        let five = MintKey::from_rng(Denomination::new(5_000, Currency::EUR), &mut ChaCha20Rng::seed_from_u64(0));
+        assert_eq!(user.withdraw_token(five.token_key()).err(), Some(Error::PolicyLimitExceeded));
+        user.advance_clock(MORNING + DAY);
+        user.withdraw_token(five.token_key())?;
+
+        let mut partial = PartiallySignedTransaction::new(Transaction::new(6_000, Currency::EUR, merchant.clone(), user.clone(), MORNING + DAY));
+        assert_eq!(user.cosign(&mut partial), Err(Error::PolicyLimitExceeded));
+
+        // tokens can pay any merchant, so merchant rules keep them out
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::DenyCategories(vec![7995])));
+        assert_eq!(user.withdraw_token(five.token_key()).err(), Some(Error::PolicyCategoryDenied));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merchants_and_categories() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let grocer = Merchant::new("GB82WEST12345698765432".to_string()).with_category_code(5411);
+        let casino = Merchant::new("FR1420041010050500013M02606".to_string()).with_category_code(7995);
+        let blocked = Merchant::new("AT611904300234573201".to_string()).with_category_code(5411);
+
+        user.set_policy(
+            SpendingPolicy::new()
+                .with_rule(Rule::DenyCategories(vec![7995]))
+                .with_rule(Rule::DenyMerchants(vec![*blocked.verifying_key()])),
+        );
+
+        pay(&user, &grocer, 1_000, MORNING)?;
+        assert_eq!(pay(&user, &casino, 1_000, MORNING), Err(Error::PolicyCategoryDenied));
+        assert_eq!(pay(&user, &blocked, 1_000, MORNING), Err(Error::PolicyMerchantDenied));
+
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::AllowMerchants(vec![*grocer.verifying_key()])));
+        pay(&user, &grocer, 1_000, MORNING)?;
+        assert_eq!(pay(&user, &blocked, 1_000, MORNING), Err(Error::PolicyMerchantDenied));
+
+        Ok(())
+    }
+
+    #[test]
+    fn time_of_day() -> Result<(), Error> {
+        let mut user = User::new("DE89370400440532013000".parse()?);
+        let merchant = Merchant::new("GB82WEST12345698765432".to_string());
+
+        // only between 22:00 and 06:00
+        user.set_policy(SpendingPolicy::new().with_rule(Rule::TimeOfDay { from: 22 * 3_600, to: 6 * 3_600 }));
+        assert_eq!(pay(&user, &merchant, 1_000, MORNING), Err(Error::PolicyOutsideHours));
+        pay(&user, &merchant, 1_000, MORNING + 13 * 3_600)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use sha2::{Digest, Sha256};
+
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_bytes, put_iban, put_key, put_u64};
+use crate::{Error, Timestamp, IBAN};
+
+/// Identifies a recovery request by the hash of what the guardians sign.
+pub type RecoveryId = [u8; 32];
+
+/// The keys that may jointly replace a lost key, `threshold` of which have to agree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Guardians {
+    threshold: usize,
+    keys: Vec<PublicKey>,
+}
+
+/// Guardians appointed by the current key of an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardianAppointment {
+    account_number: IBAN,
+    guardians: Guardians,
+    signature: Signature,
+}
+
+/// A request to replace the key of an account, approved by its guardians.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryRequest {
+    account_number: IBAN,
+    old_key: PublicKey,
+    new_key: PublicKey,
+    requested_at: Timestamp,
+    approvals: Vec<(PublicKey, Signature)>,
+}
+
+/// Stops a pending recovery, signed by the key it would replace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryCancellation {
+    recovery_id: RecoveryId,
+    signature: Signature,
+}
+
+impl Guardians {
+    /// Creates a guardian set requiring `threshold` of `keys`. Fails if the
+    /// threshold can never or always be reached, or a key is listed twice.
+    pub fn new(threshold: usize, keys: Vec<PublicKey>) -> Result<Self, Error> {
+        if threshold == 0 || threshold > keys.len() {
+            return Err(Error::InvalidThreshold);
+        }
+        if keys.iter().enumerate().any(|(i, key)| keys[..i].contains(key)) {
+            return Err(Error::DuplicateSigner);
+        }
+
+        Ok(Self { threshold, keys })
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// Verifies that enough distinct guardians approved the request.
+    pub fn verify(&self, request: &RecoveryRequest) -> Result<(), Error> {
+        let bytes = request.signed_bytes();
+        for (i, (guardian, signature)) in request.approvals.iter().enumerate() {
+            if !self.keys.contains(guardian) {
+                return Err(Error::UnknownSigner);
+            }
+            if request.approvals[..i].iter().any(|(other, _)| other == guardian) {
+                return Err(Error::DuplicateSigner);
+            }
+
+            guardian.verify(&bytes, signature)?;
+        }
+
+        if request.approvals.len() < self.threshold {
+            return Err(Error::NotEnoughSignatures);
+        }
+
+        Ok(())
+    }
+}
+
+impl GuardianAppointment {
+    pub(crate) fn new(account_number: IBAN, guardians: Guardians, signature: Signature) -> Self {
+        Self { account_number, guardians, signature }
+    }
+
+    pub(crate) fn signed_bytes(account_number: &IBAN, guardians: &Guardians) -> Vec<u8> {
+        let mut buf = b"guardians".to_vec();
+        put_iban(&mut buf, account_number);
+        put_u64(&mut buf, guardians.threshold as u64);
+        put_u64(&mut buf, guardians.keys.len() as u64);
+        for key in &guardians.keys {
+            put_key(&mut buf, key);
+        }
+        buf
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn guardians(&self) -> &Guardians {
+        &self.guardians
+    }
+
+    /// Verifies that `key` appointed the guardians.
+    pub fn verify(&self, key: &PublicKey) -> Result<(), Error> {
+        let bytes = Self::signed_bytes(&self.account_number, &self.guardians);
+        key.verify(&bytes, &self.signature)
+    }
+}
+
+impl RecoveryRequest {
+    /// Creates a request to replace `old_key` by `new_key`, without any approvals yet.
+    pub fn new(account_number: IBAN, old_key: PublicKey, new_key: PublicKey, requested_at: Timestamp) -> Self {
+        Self { account_number, old_key, new_key, requested_at, approvals: Vec::new() }
+    }
+
+    pub(crate) fn signed_bytes(&self) -> Vec<u8> {
+        let mut buf = b"recovery".to_vec();
+        put_iban(&mut buf, &self.account_number);
+        put_key(&mut buf, &self.old_key);
+        put_key(&mut buf, &self.new_key);
+        put_u64(&mut buf, self.requested_at);
+        buf
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn old_key(&self) -> &PublicKey {
+        &self.old_key
+    }
+
+    pub fn new_key(&self) -> &PublicKey {
+        &self.new_key
+    }
+
+    pub fn requested_at(&self) -> Timestamp {
+        self.requested_at
+    }
+
+    pub fn id(&self) -> RecoveryId {
+        Sha256::digest(self.signed_bytes()).into()
+    }
+
+    /// Returns the guardians that have approved so far.
+    pub fn approvers(&self) -> Vec<PublicKey> {
+        self.approvals.iter().map(|(guardian, _)| *guardian).collect()
+    }
+
+    /// Adds an approval, unless the guardian already approved.
+    pub(crate) fn add_approval(&mut self, guardian: PublicKey, signature: Signature) -> Result<(), Error> {
+        if self.approvals.iter().any(|(other, _)| *other == guardian) {
+            return Err(Error::DuplicateSigner);
+        }
+
+        self.approvals.push((guardian, signature));
+        Ok(())
+    }
+}
+
+impl RecoveryCancellation {
+    pub(crate) fn new(recovery_id: RecoveryId, signature: Signature) -> Self {
+        Self { recovery_id, signature }
+    }
+
+    pub(crate) fn signed_bytes(recovery_id: &RecoveryId) -> Vec<u8> {
+        let mut buf = b"cancel-recovery".to_vec();
+        put_bytes(&mut buf, recovery_id);
+        buf
+    }
+
+    pub fn recovery_id(&self) -> &RecoveryId {
+        &self.recovery_id
+    }
+
+    /// Verifies that `key` cancelled the recovery.
+    pub fn verify(&self, key: &PublicKey) -> Result<(), Error> {
+        key.verify(&Self::signed_bytes(&self.recovery_id), &self.signature)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::crypto::{PublicKey, Signature};
+use crate::encoding::{put_iban, put_key, put_u64};
+use crate::{Error, Timestamp, IBAN};
+
+/// Which key authorized a rotation or revocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyAuthority {
+    /// The key being replaced or revoked.
+    Own,
+    /// The recovery key registered for the account.
+    Recovery,
+}
+
+impl KeyAuthority {
+    fn as_byte(&self) -> u8 {
+        match self {
+            Self::Own => 0,
+            Self::Recovery => 1,
+        }
+    }
+}
+
+/// Replaces the key of an account, starting at `effective_at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRotation {
+    account_number: IBAN,
+    old_key: PublicKey,
+    new_key: PublicKey,
+    effective_at: Timestamp,
+    authority: KeyAuthority,
+    signature: Signature,
+}
+
+/// Declares that signatures made with `key` from `revoked_at` on are invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRevocation {
+    account_number: IBAN,
+    key: PublicKey,
+    revoked_at: Timestamp,
+    authority: KeyAuthority,
+    signature: Signature,
+}
+
+impl KeyRotation {
+    pub(crate) fn new(
+        account_number: IBAN,
+        old_key: PublicKey,
+        new_key: PublicKey,
+        effective_at: Timestamp,
+        authority: KeyAuthority,
+        signature: Signature,
+    ) -> Self {
+        Self { account_number, old_key, new_key, effective_at, authority, signature }
+    }
+
+    pub(crate) fn signed_bytes(
+        account_number: &IBAN,
+        old_key: &PublicKey,
+        new_key: &PublicKey,
+        effective_at: Timestamp,
+        authority: KeyAuthority,
+    ) -> Vec<u8> {
+        let mut buf = b"key-rotation".to_vec();
+        put_iban(&mut buf, account_number);
+        put_key(&mut buf, old_key);
+        put_key(&mut buf, new_key);
+        put_u64(&mut buf, effective_at);
+        buf.push(authority.as_byte());
+        buf
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn old_key(&self) -> &PublicKey {
+        &self.old_key
+    }
+
+    pub fn new_key(&self) -> &PublicKey {
+        &self.new_key
+    }
+
+    pub fn effective_at(&self) -> Timestamp {
+        self.effective_at
+    }
+
+    pub fn authority(&self) -> KeyAuthority {
+        self.authority
+    }
+
+    /// Verifies the signature, made either by the old key or by `recovery_key`.
+    pub fn verify(&self, recovery_key: Option<&PublicKey>) -> Result<(), Error> {
+        let signer = match self.authority {
+            KeyAuthority::Own => &self.old_key,
+            KeyAuthority::Recovery => recovery_key.ok_or(Error::WrongSigner)?,
+        };
+
+        let bytes = Self::signed_bytes(&self.account_number, &self.old_key, &self.new_key, self.effective_at, self.authority);
+        signer.verify(&bytes, &self.signature)
+    }
+}
+
+impl KeyRevocation {
+    pub(crate) fn new(
+        account_number: IBAN,
+        key: PublicKey,
+        revoked_at: Timestamp,
+        authority: KeyAuthority,
+        signature: Signature,
+    ) -> Self {
+        Self { account_number, key, revoked_at, authority, signature }
+    }
+
+    pub(crate) fn signed_bytes(account_number: &IBAN, key: &PublicKey, revoked_at: Timestamp, authority: KeyAuthority) -> Vec<u8> {
+        let mut buf = b"key-revocation".to_vec();
+        put_iban(&mut buf, account_number);
+        put_key(&mut buf, key);
+        put_u64(&mut buf, revoked_at);
+        buf.push(authority.as_byte());
+        buf
+    }
+
+    pub fn account_number(&self) -> &IBAN {
+        &self.account_number
+    }
+
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    pub fn revoked_at(&self) -> Timestamp {
+        self.revoked_at
+    }
+
+    pub fn authority(&self) -> KeyAuthority {
+        self.authority
+    }
+
+    /// Verifies the signature, made either by the revoked key or by `recovery_key`.
+    pub fn verify(&self, recovery_key: Option<&PublicKey>) -> Result<(), Error> {
+        let signer = match self.authority {
+            KeyAuthority::Own => &self.key,
+            KeyAuthority::Recovery => recovery_key.ok_or(Error::WrongSigner)?,
+        };
+
+        let bytes = Self::signed_bytes(&self.account_number, &self.key, self.revoked_at, self.authority);
+        signer.verify(&bytes, &self.signature)
+    }
+}